@@ -1,47 +1,207 @@
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
 
+use log::warn;
 use serde::{Deserialize, Serialize};
 
-use self::models::{BookDB, BookError};
+use self::merge::MergeSummary;
+use self::models::{Book, BookDB, BookError, DbTuning, NameCollation};
+use self::operation::CancellationToken;
 use self::store::SqliteStore;
-use crate::from_err;
-use crate::pool::{Creator, PoolItem, PoolManager};
+pub use self::store::{schema_version, sqlite_version};
+use crate::rec_pois;
+use crate::pool::{Creator, PoolItem, PoolManager, PoolMode};
 
 // Module declarations
+pub mod covers;
+pub mod export;
+pub mod merge;
+pub mod metadata;
 pub mod models;
+pub mod query;
+// Bulk operations are not wired into any command yet.
+#[allow(dead_code)]
+pub mod operation;
+pub mod sample;
 mod store;
 
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum Error {
+    #[error("Database is already open")]
     PoolAlreadyAdded,
+    #[error("Database is not open")]
     PoolNotFound,
+    #[error("No current database is set")]
     CurrentPoolNotSet,
-    BookError(BookError),
+    #[error(transparent)]
+    BookError(#[from] BookError),
+    #[error("Conversion failed")]
     ConversionFailed,
+    #[error(transparent)]
+    IoError(#[from] io::Error),
+    #[error("Database is busy, please try again")]
+    PoolTimeout,
 }
-from_err!(Error, BookError, BookError);
 
 pub type Result<T = (), E = Error> = std::result::Result<T, E>;
 pub type BookPool = PoolManager<dyn BookDB, SqliteCreator>;
 
+/// Maximum time to wait for a database connection.
+const POOL_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub struct SqliteCreator {
     path: String,
+    /// WAL auto-checkpoint pages of all connections, negative if not set.
+    wal_autocheckpoint: AtomicI64,
+    /// Ordering of authors and tags of all connections.
+    name_collation: Mutex<NameCollation>,
+    /// Pragmas of all connections, fixed when the pool is created.
+    tuning: DbTuning,
+    /// Incremented whenever the settings above change, so connections which were
+    /// idle or in use at that time get them before they are used again.
+    generation: AtomicU64,
+}
+
+impl SqliteCreator {
+    /// Applies the settings which can change while the pool is open to a connection.
+    fn apply_settings(&self, store: &mut dyn BookDB) -> Result<(), BookError> {
+        let pages = self.wal_autocheckpoint.load(Ordering::Relaxed);
+        if pages >= 0 {
+            store.set_wal_autocheckpoint(pages)?;
+        }
+
+        let collation = *rec_pois!(self.name_collation);
+        store.set_name_collation(collation)
+    }
+
+    /// Starts a new settings generation after a setting was changed.
+    fn settings_changed(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
 }
 
 impl Creator<dyn BookDB> for SqliteCreator {
     fn create_item(&self) -> Box<dyn BookDB> {
-        Box::new(SqliteStore::new(self.path.as_str()).expect("Failed to create SqliteStore"))
+        let mut store: Box<dyn BookDB> = Box::new(
+            SqliteStore::new(self.path.as_str(), &self.tuning)
+                .expect("Failed to create SqliteStore"),
+        );
+        self.apply_settings(store.as_mut())
+            .expect("Failed to apply connection settings");
+        store
+    }
+
+    fn is_healthy(&self, item: &dyn BookDB) -> bool {
+        item.is_healthy()
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    fn update_item(&self, item: &mut dyn BookDB) -> bool {
+        match self.apply_settings(item) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Dropping connection with outdated settings: {}", e);
+                false
+            }
+        }
+    }
+}
+
+/// Returns the canonical form of a database path, so the same file is always opened under
+/// the same path. Falls back to the canonical directory if the file doesn't exist yet.
+fn canonical_db_path(path: &Path) -> PathBuf {
+    if let Ok(p) = fs::canonicalize(path) {
+        return p;
+    }
+
+    match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) => fs::canonicalize(if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        })
+        .map(|d| d.join(name))
+        .unwrap_or_else(|_| path.to_path_buf()),
+        _ => path.to_path_buf(),
     }
 }
 
 impl BookPool {
-    pub fn new_sqlite_pool(path: &PathBuf) -> Result<BookPool> {
-        let db_file = path.to_str().ok_or(Error::ConversionFailed)?.to_owned();
+    /// Creates a pool for the given database with up to `pool_size` connections, if all of
+    /// them are in use, callers wait for a released one. In lazy mode connections are only
+    /// opened when needed, which is faster when opening many databases at once. All
+    /// connections use the given pragmas.
+    pub fn new_sqlite_pool(
+        path: &PathBuf,
+        pool_size: usize,
+        mode: PoolMode,
+        tuning: DbTuning,
+    ) -> Result<BookPool> {
+        let db_file = canonical_db_path(path)
+            .to_str()
+            .ok_or(Error::ConversionFailed)?
+            .to_owned();
         // Ensure we can read and write file
-        let _ = SqliteStore::new(&db_file)?;
+        let _ = SqliteStore::new(&db_file, &tuning)?;
+
+        Ok(BookPool::bounded(
+            pool_size,
+            pool_size,
+            mode,
+            SqliteCreator {
+                path: db_file,
+                wal_autocheckpoint: AtomicI64::new(-1),
+                name_collation: Mutex::new(NameCollation::default()),
+                tuning,
+                generation: AtomicU64::new(0),
+            },
+        ))
+    }
+
+    /// Creates a pool for an existing database, unlike [BookPool::new_sqlite_pool] a
+    /// missing file is not created but reported as error.
+    pub fn open_sqlite_pool(
+        path: &PathBuf,
+        pool_size: usize,
+        mode: PoolMode,
+        tuning: DbTuning,
+    ) -> Result<BookPool> {
+        if !path.is_file() {
+            return Err(Error::IoError(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Database file not found: {}", path.display()),
+            )));
+        }
+        Self::new_sqlite_pool(path, pool_size, mode, tuning)
+    }
+
+    /// Sets the WAL auto-checkpoint pages on all connections of the pool, connections in
+    /// use get the new value when they are released.
+    pub fn set_wal_autocheckpoint(&self, pages: i64) -> Result {
+        // Validates the value before other connections get it
+        self.get_pool_item().set_wal_autocheckpoint(pages)?;
+        let creator = self.creator();
+        creator.wal_autocheckpoint.store(pages, Ordering::Relaxed);
+        creator.settings_changed();
+        Ok(())
+    }
 
-        Ok(BookPool::new(5, SqliteCreator { path: db_file }))
+    /// Sets the ordering of authors and tags on all connections of the pool, connections in
+    /// use get the new ordering when they are released.
+    pub fn set_name_collation(&self, collation: NameCollation) -> Result {
+        self.get_pool_item().set_name_collation(collation)?;
+        let creator = self.creator();
+        *rec_pois!(creator.name_collation) = collation;
+        creator.settings_changed();
+        Ok(())
     }
 }
 
@@ -60,10 +220,21 @@ impl BookManager {
         Ok(())
     }
 
+    /// Returns the key of the open pool of the given database file. Paths are compared
+    /// canonicalized, so a file opened through another path is found as well.
+    pub fn pool_key_by_path(&self, path: &Path) -> Option<String> {
+        let path = canonical_db_path(path);
+        self.book_db_pools
+            .iter()
+            .find(|(_, pool)| Path::new(&pool.creator().path) == path)
+            .map(|(key, _)| key.clone())
+    }
+
     pub fn remove_pool<T: AsRef<str>>(&mut self, pool_name: T) -> Option<BookPool> {
         match self.book_db_pools.remove_entry(pool_name.as_ref()) {
             Some(entry) => {
-                if self.current.is_some() && entry.0.as_str() == pool_name.as_ref() {
+                // Only clear the selection if the current pool was removed
+                if self.current.as_deref() == Some(entry.0.as_str()) {
                     self.current = None;
                 }
                 Some(entry.1)
@@ -88,17 +259,189 @@ impl BookManager {
         Err(Error::PoolNotFound)
     }
 
+    /// Switches to the given pool and returns the previously selected one. If the pool
+    /// doesn't exist (anymore), an error is returned and the selection is left unchanged.
+    pub fn swap_current_pool<T: AsRef<str>>(&mut self, pool_name: T) -> Result<Option<String>> {
+        let previous = self.current.clone();
+        self.set_current_pool(pool_name)?;
+        Ok(previous)
+    }
+
+    /// Sets the ordering of authors and tags on all open databases.
+    pub fn set_name_collation(&self, collation: NameCollation) -> Result {
+        for pool in self.book_db_pools.values() {
+            pool.set_name_collation(collation)?;
+        }
+        Ok(())
+    }
+
     pub fn get_pools(&self) -> Vec<&str> {
         self.book_db_pools.keys().map(|k| k.as_str()).collect()
     }
 
+    /// Returns a connection of the current database, fails if none becomes available
+    /// within `POOL_TIMEOUT`.
     pub fn get_current_pool(&self) -> Result<PoolItem<dyn BookDB>> {
+        self.get_current_book_pool()?
+            .get_pool_item_timeout(POOL_TIMEOUT)
+            .ok_or(Error::PoolTimeout)
+    }
+
+    /// Lists cover files in the cover directories of all open databases, which are
+    /// not referenced by any book of these databases.
+    pub fn list_orphaned_covers(&self) -> Result<Vec<PathBuf>> {
+        let (dirs, referenced) = self.cover_references()?;
+        Ok(covers::orphaned_covers(&dirs, &referenced)?)
+    }
+
+    /// Deletes all orphaned cover files and returns the freed bytes.
+    pub fn purge_orphaned_covers(&self) -> Result<u64> {
+        let (dirs, referenced) = self.cover_references()?;
+        let orphans = covers::orphaned_covers(&dirs, &referenced)?;
+        Ok(covers::purge_covers(&dirs, &orphans)?)
+    }
+
+    /// Returns the cover directories of all open databases and the paths of all referenced covers.
+    fn cover_references(&self) -> Result<(Vec<PathBuf>, HashSet<PathBuf>)> {
+        let mut dirs: Vec<PathBuf> = Vec::new();
+        let mut referenced: HashSet<PathBuf> = HashSet::new();
+
+        for pool in self.book_db_pools.values() {
+            let db_file = Path::new(&pool.creator().path);
+            let dir = covers::covers_dir(db_file);
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+
+            for cover in pool.get_pool_item().get_cover_references()? {
+                referenced.insert(covers::database_dir(db_file).join(cover));
+            }
+        }
+
+        Ok((dirs, referenced))
+    }
+
+    /// Merges all books of the given open database into the current one.
+    pub fn merge_from<T: AsRef<str>>(
+        &self,
+        source: T,
+        token: &CancellationToken,
+    ) -> Result<MergeSummary> {
+        let source = self
+            .book_db_pools
+            .get(source.as_ref())
+            .ok_or(Error::PoolNotFound)?;
+        let mut target = self.get_current_pool()?;
+        // The source may be the current database, waiting without timeout could deadlock
+        let mut source = source
+            .get_pool_item_timeout(POOL_TIMEOUT)
+            .ok_or(Error::PoolTimeout)?;
+
+        Ok(merge::merge_from(&mut *target, &mut *source, token)?)
+    }
+
+    /// Copies the cover of the given book of the current database to `dest`.
+    /// Returns the written file, which has the extension of the cover.
+    pub fn export_cover(&self, id: i64, dest: &Path) -> Result<PathBuf> {
+        let pool = self.get_current_book_pool()?;
+        let cover = pool
+            .get_pool_item()
+            .get_book(id)?
+            .cover_img
+            .ok_or_else(|| BookError::Generic(format!("Book {} has no cover", id)))?;
+
+        Ok(covers::export_cover(
+            Path::new(&pool.creator().path),
+            &cover,
+            dest,
+        )?)
+    }
+
+    /// Writes a standalone copy of the current database to `dest`, including its covers.
+    /// Fails if `dest` is the current database itself.
+    pub fn export_database(&self, dest: &Path) -> Result {
+        let pool = self.get_current_book_pool()?;
+        let db_file = Path::new(&pool.creator().path);
+        if canonical_db_path(dest) == db_file {
+            return Err(BookError::Generic(String::from(
+                "Can't export a database onto itself",
+            ))
+            .into());
+        }
+
+        let mut db = pool.get_pool_item();
+        db.backup_to(dest)?;
+        covers::copy_covers(db_file, dest, &db.get_cover_references()?)?;
+
+        Ok(())
+    }
+
+    /// Returns the cover files of the given books of the current database, books without
+    /// a cover or with a missing cover file are skipped. At most [covers::MAX_PREFETCH_COVERS]
+    /// ids are resolved, the remaining ids are ignored.
+    pub fn prefetch_covers(&self, ids: &[i64]) -> Result<HashMap<i64, PathBuf>> {
+        let pool = self.get_current_book_pool()?;
+        let mut db = pool.get_pool_item();
+
+        let mut covers = Vec::new();
+        for id in ids.iter().take(covers::MAX_PREFETCH_COVERS) {
+            match db.get_book(*id) {
+                Ok(book) => {
+                    if let Some(cover) = book.cover_img {
+                        covers.push((*id, cover));
+                    }
+                }
+                Err(BookError::NotFound) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(covers::cover_paths(Path::new(&pool.creator().path), covers))
+    }
+
+    /// Sets the cover of the given book of the current database to a copy of `source`,
+    /// `None` clears the cover. The previous cover file is deleted if no other book uses it.
+    pub fn set_cover(&self, id: i64, source: Option<&Path>) -> Result<Book> {
+        let pool = self.get_current_book_pool()?;
+        let db_file = Path::new(&pool.creator().path);
+        let cover = source
+            .map(|s| covers::import_cover(db_file, s))
+            .transpose()?;
+
+        self.replace_cover(id, cover)
+    }
+
+    /// Stores the given image in the cover directory of the current database and sets
+    /// it as cover of the book with given id. Returns the updated book.
+    pub fn set_cover_data(&self, id: i64, data: &[u8]) -> Result<Book> {
+        let pool = self.get_current_book_pool()?;
+        let db_file = Path::new(&pool.creator().path);
+        let cover = covers::store_cover(data, covers::database_dir(db_file))?;
+
+        self.replace_cover(id, Some(cover))
+    }
+
+    /// Sets the cover of the book, the previous cover file is deleted if no other book
+    /// references it.
+    fn replace_cover(&self, id: i64, cover: Option<String>) -> Result<Book> {
+        let pool = self.get_current_book_pool()?;
+        let db_file = Path::new(&pool.creator().path);
+
+        let mut db = pool.get_pool_item();
+        let previous = db.set_cover(id, cover.clone())?;
+        if let Some(previous) = previous.filter(|p| Some(p) != cover.as_ref()) {
+            let file = covers::database_dir(db_file).join(&previous);
+            if file.is_file() && !db.get_cover_references()?.contains(&previous) {
+                covers::purge_covers(&[covers::covers_dir(db_file)], &[file])?;
+            }
+        }
+
+        Ok(db.get_book(id)?)
+    }
+
+    pub fn get_current_book_pool(&self) -> Result<&BookPool> {
         match self.current.as_ref() {
-            Some(s) => Ok(self
-                .book_db_pools
-                .get(s)
-                .ok_or(Error::PoolNotFound)?
-                .get_pool_item()),
+            Some(s) => self.book_db_pools.get(s).ok_or(Error::PoolNotFound),
             None => Err(Error::CurrentPoolNotSet),
         }
     }
@@ -112,4 +455,200 @@ pub const BOOK_MANAGER_EVENTS: &str = "book-manager-event";
 pub enum BookManagerEvent {
     CurrentDBChanged(String),
     OpenDBChanged(Vec<String>)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::models::{Book, NameCollation, SearchConfig};
+    use super::{BookManager, BookPool, DbTuning, Error};
+    use crate::pool::PoolMode;
+
+    #[test]
+    fn failed_switch_keeps_current_pool() {
+        let mut manager = BookManager::default();
+        let pool = BookPool::new_sqlite_pool(
+            &PathBuf::from("first.db"),
+            5,
+            PoolMode::Lazy,
+            DbTuning::default(),
+        )
+        .unwrap();
+        manager.add_pool("first", pool).unwrap();
+        let pool = BookPool::new_sqlite_pool(
+            &PathBuf::from("second.db"),
+            5,
+            PoolMode::Lazy,
+            DbTuning::default(),
+        )
+        .unwrap();
+        manager.add_pool("second", pool).unwrap();
+
+        assert_eq!(manager.swap_current_pool("first").unwrap(), None);
+        assert_eq!(manager.swap_current_pool("second").unwrap(), Some("first".to_owned()));
+        assert_eq!(manager.swap_current_pool("first").unwrap(), Some("second".to_owned()));
+
+        // Closing another pool must not clear the selection
+        assert!(manager.remove_pool("second").is_some());
+        assert_eq!(manager.current_pool_name().unwrap(), "first");
+
+        assert!(matches!(manager.swap_current_pool("second"), Err(Error::PoolNotFound)));
+        assert!(matches!(manager.set_current_pool("second"), Err(Error::PoolNotFound)));
+        assert_eq!(manager.current_pool_name().unwrap(), "first");
+    }
+
+    #[test]
+    fn set_wal_autocheckpoint_while_in_use() {
+        let pool = BookPool::new_sqlite_pool(
+            &PathBuf::from("wal.db"),
+            2,
+            PoolMode::Lazy,
+            DbTuning::default(),
+        )
+        .unwrap();
+
+        let mut held = pool.get_pool_item();
+        let default_pages = held.get_wal_autocheckpoint().unwrap();
+        pool.set_wal_autocheckpoint(default_pages + 100).unwrap();
+        assert_eq!(held.get_wal_autocheckpoint().unwrap(), default_pages);
+        drop(held);
+
+        // Both the connection which was in use and the idle one got the new value
+        let mut first = pool.get_pool_item();
+        let mut second = pool.get_pool_item();
+        assert_eq!(first.get_wal_autocheckpoint().unwrap(), default_pages + 100);
+        assert_eq!(second.get_wal_autocheckpoint().unwrap(), default_pages + 100);
+    }
+
+    #[test]
+    fn set_name_collation_while_in_use() {
+        let pool = BookPool::new_sqlite_pool(
+            &PathBuf::from("collation.db"),
+            2,
+            PoolMode::Lazy,
+            DbTuning::default(),
+        )
+        .unwrap();
+
+        // Debug builds open connections in memory, so the book only exists on this one
+        let mut held = pool.get_pool_item();
+        let mut book = Book {
+            authors: vec!["Zweig".to_owned(), "ärger".to_owned()],
+            isbn: "9783550087653".to_owned(),
+            lang: "DE".to_owned(),
+            title: "Namen".to_owned(),
+            ..Default::default()
+        };
+        held.add_book(&mut book).unwrap();
+        pool.set_name_collation(NameCollation::Unicode).unwrap();
+        drop(held);
+
+        // The released connection is taken first and got the new ordering
+        let mut released = pool.get_pool_item();
+        let authors = released.get_book(book.id).unwrap().authors;
+        assert_eq!(authors, vec!["ärger".to_owned(), "Zweig".to_owned()]);
+    }
+
+    #[test]
+    fn set_and_clear_cover() {
+        let dir = std::env::temp_dir().join(format!("bookshelf-set-cover-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("source.gif");
+        std::fs::write(&source, b"GIF89a-cover").unwrap();
+
+        let mut manager = BookManager::default();
+        let pool = BookPool::new_sqlite_pool(
+            &dir.join("books.db"),
+            5,
+            PoolMode::Lazy,
+            DbTuning::default(),
+        )
+        .unwrap();
+        manager.add_pool("books", pool).unwrap();
+        manager.set_current_pool("books").unwrap();
+        // Debug builds open connections in memory, the pool reuses its only idle connection
+        let mut book = Book {
+            authors: vec!["Richard Dawkins".to_owned()],
+            isbn: "9783550087653".to_owned(),
+            lang: "DE".to_owned(),
+            title: "Es gibt keine Schöpfung".to_owned(),
+            ..Default::default()
+        };
+        manager.get_current_pool().unwrap().add_book(&mut book).unwrap();
+        let id = book.id;
+
+        let book = manager.set_cover(id, Some(&source)).unwrap();
+        let cover = book.cover_img.expect("cover is set");
+        assert!(dir.join(&cover).is_file());
+
+        let book = manager.set_cover(id, None).unwrap();
+        assert_eq!(book.cover_img, None);
+        assert!(!dir.join(&cover).exists());
+        assert!(manager.set_cover(id, Some(&dir.join("missing.gif"))).is_err());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn find_pool_of_same_file() {
+        let dir = std::env::temp_dir().join(format!("bookshelf-same-file-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("x.db"), b"").unwrap();
+
+        let mut manager = BookManager::default();
+        let pool = BookPool::new_sqlite_pool(
+            &dir.join("./x.db"),
+            5,
+            PoolMode::Lazy,
+            DbTuning::default(),
+        )
+        .unwrap();
+        manager.add_pool("x.db", pool).unwrap();
+
+        assert_eq!(manager.pool_key_by_path(&dir.join("x.db")), Some("x.db".to_owned()));
+        assert_eq!(
+            manager.pool_key_by_path(&dir.join("sub/../x.db")),
+            Some("x.db".to_owned())
+        );
+        assert_eq!(manager.pool_key_by_path(&dir.join("y.db")), None);
+        assert_eq!(manager.get_pools().len(), 1);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn open_existing_database() {
+        let dir = std::env::temp_dir().join(format!("bookshelf-open-db-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("books.db"), b"").unwrap();
+
+        assert!(matches!(
+            BookPool::open_sqlite_pool(
+                &dir.join("missing.db"),
+                5,
+                PoolMode::Lazy,
+                DbTuning::default(),
+            ),
+            Err(Error::IoError(_))
+        ));
+
+        let mut manager = BookManager::default();
+        let pool = BookPool::open_sqlite_pool(
+            &dir.join("books.db"),
+            5,
+            PoolMode::Lazy,
+            DbTuning::default(),
+        )
+        .unwrap();
+        manager.add_pool("books.db", pool).unwrap();
+        manager.set_current_pool("books.db").unwrap();
+        let count = manager
+            .get_current_pool()
+            .unwrap()
+            .count_books(SearchConfig::new("").build());
+        assert_eq!(count.unwrap(), 0);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
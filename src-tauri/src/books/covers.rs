@@ -0,0 +1,340 @@
+// Copyright © 2023 Sandro Dallo
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+// This file contains the handling of cover images stored next to a database.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+/// Name of the directory next to a database file which contains its covers.
+pub const COVERS_DIR: &str = "covers";
+
+/// Maximum number of covers resolved by a single prefetch.
+pub const MAX_PREFETCH_COVERS: usize = 200;
+
+/// Returns the directory of the given database file, covers are referenced relative to it.
+pub fn database_dir(db_file: &Path) -> &Path {
+    db_file.parent().unwrap_or_else(|| Path::new(""))
+}
+
+/// Returns the cover directory of the given database file.
+pub fn covers_dir(db_file: &Path) -> PathBuf {
+    database_dir(db_file).join(COVERS_DIR)
+}
+
+/// Lists all files within the given cover directories which are not referenced.
+/// Missing directories are skipped.
+pub fn orphaned_covers<'a, D>(dirs: D, referenced: &HashSet<PathBuf>) -> io::Result<Vec<PathBuf>>
+where
+    D: IntoIterator<Item = &'a PathBuf>,
+{
+    let mut orphans = Vec::new();
+    for dir in dirs {
+        if !dir.is_dir() {
+            continue;
+        }
+
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_file() && !referenced.contains(&path) {
+                orphans.push(path);
+            }
+        }
+    }
+
+    orphans.sort();
+    Ok(orphans)
+}
+
+/// Deletes the given cover files and returns the freed bytes. Files outside
+/// of the given cover directories are never touched.
+pub fn purge_covers<'a, D>(dirs: D, files: &[PathBuf]) -> io::Result<u64>
+where
+    D: IntoIterator<Item = &'a PathBuf> + Clone,
+{
+    let mut freed = 0;
+    for file in files {
+        if !dirs
+            .clone()
+            .into_iter()
+            .any(|d| file.parent() == Some(d.as_path()))
+        {
+            continue;
+        }
+
+        freed += fs::metadata(file)?.len();
+        fs::remove_file(file)?;
+    }
+
+    Ok(freed)
+}
+
+/// Copies the cover of a book to `dest`, the cover is referenced relative to the
+/// database directory. The cover format is preserved, so `dest` gets the extension
+/// of the cover. Returns the written file.
+pub fn export_cover(db_file: &Path, cover: &str, dest: &Path) -> io::Result<PathBuf> {
+    let source = database_dir(db_file).join(cover);
+    let mut dest = dest.to_path_buf();
+    if let Some(ext) = source.extension() {
+        dest.set_extension(ext);
+    }
+
+    fs::copy(&source, &dest)?;
+    Ok(dest)
+}
+
+/// Returns the file extension of the given image data, `None` if the data is not
+/// a supported image (JPEG, PNG, GIF or WebP).
+pub fn image_extension(data: &[u8]) -> Option<&'static str> {
+    match data {
+        [0xff, 0xd8, 0xff, ..] => Some("jpg"),
+        [0x89, b'P', b'N', b'G', ..] => Some("png"),
+        [b'G', b'I', b'F', b'8', ..] => Some("gif"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("webp"),
+        _ => None,
+    }
+}
+
+/// Copies the given image into the cover directory of the database and returns its
+/// reference relative to the database directory, see [store_cover].
+pub fn import_cover(db_file: &Path, source: &Path) -> io::Result<String> {
+    let data = fs::read(source)?;
+    store_cover(&data, database_dir(db_file)).map_err(|e| match e.kind() {
+        io::ErrorKind::InvalidInput => io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a supported image", source.display()),
+        ),
+        _ => e,
+    })
+}
+
+/// Writes the given image into the cover directory within `books_dir`, the directory
+/// of the database, and returns its reference relative to `books_dir`. Files are named
+/// by the hash of their content, so the same image is only stored once.
+pub fn store_cover(data: &[u8], books_dir: &Path) -> io::Result<String> {
+    let ext = image_extension(data).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "data is not a supported image")
+    })?;
+
+    let name = format!("{:x}.{}", Sha256::digest(data), ext);
+    let dir = books_dir.join(COVERS_DIR);
+    fs::create_dir_all(&dir)?;
+
+    let dest = dir.join(&name);
+    if !dest.is_file() {
+        fs::write(&dest, data)?;
+    }
+
+    Ok(format!("{}/{}", COVERS_DIR, name))
+}
+
+/// Copies the given covers of a database next to another database file, e.g. a copy of the
+/// database. Missing cover files are skipped. Returns the number of copied covers.
+pub fn copy_covers<'a, I>(db_file: &Path, dest_db_file: &Path, covers: I) -> io::Result<u64>
+where
+    I: IntoIterator<Item = &'a String>,
+{
+    let (source_dir, dest_dir) = (database_dir(db_file), database_dir(dest_db_file));
+    let mut copied = 0;
+    for cover in covers {
+        let (source, dest) = (source_dir.join(cover), dest_dir.join(cover));
+        if !source.is_file() || source == dest {
+            continue;
+        }
+
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(&source, &dest)?;
+        copied += 1;
+    }
+
+    Ok(copied)
+}
+
+/// Resolves the covers of the given books to their files, covers are referenced relative
+/// to the database directory. Books whose cover file is missing are skipped.
+pub fn cover_paths<I>(db_file: &Path, covers: I) -> HashMap<i64, PathBuf>
+where
+    I: IntoIterator<Item = (i64, String)>,
+{
+    let dir = database_dir(db_file);
+    covers
+        .into_iter()
+        .map(|(id, cover)| (id, dir.join(cover)))
+        .filter(|(_, path)| path.is_file())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::fs;
+    use std::io::Result;
+
+    use super::{
+        copy_covers, cover_paths, covers_dir, database_dir, export_cover, import_cover,
+        orphaned_covers, purge_covers, store_cover,
+    };
+
+    #[test]
+    fn list_and_purge_orphaned_covers() -> Result<()> {
+        let db_file = std::env::temp_dir()
+            .join(format!("bookshelf-covers-{}", std::process::id()))
+            .join("books.db");
+        let dir = covers_dir(&db_file);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+
+        fs::write(dir.join("used.jpg"), [1u8; 10])?;
+        fs::write(dir.join("orphan.png"), [2u8; 42])?;
+
+        let referenced: HashSet<_> = [database_dir(&db_file).join("covers/used.jpg")].into();
+        let dirs = vec![dir.clone()];
+
+        let orphans = orphaned_covers(&dirs, &referenced)?;
+        assert_eq!(orphans, vec![dir.join("orphan.png")]);
+
+        assert_eq!(purge_covers(&dirs, &orphans)?, 42);
+        assert!(!dir.join("orphan.png").exists());
+        assert!(dir.join("used.jpg").exists());
+        assert!(orphaned_covers(&dirs, &referenced)?.is_empty());
+
+        // Never deletes files outside of the cover directories
+        let outside = vec![database_dir(&db_file).join("books.db")];
+        fs::write(&outside[0], [3u8; 5])?;
+        assert_eq!(purge_covers(&dirs, &outside)?, 0);
+        assert!(outside[0].exists());
+
+        let _ = fs::remove_dir_all(database_dir(&db_file));
+        Ok(())
+    }
+
+    #[test]
+    fn export_cover_keeps_format() -> Result<()> {
+        let db_file = std::env::temp_dir()
+            .join(format!("bookshelf-export-cover-{}", std::process::id()))
+            .join("books.db");
+        let dir = covers_dir(&db_file);
+        fs::create_dir_all(&dir)?;
+
+        let content: Vec<u8> = (0..=255).collect();
+        fs::write(dir.join("cover.png"), &content)?;
+
+        let dest = database_dir(&db_file).join("exported");
+        let written = export_cover(&db_file, "covers/cover.png", &dest)?;
+        assert_eq!(written, database_dir(&db_file).join("exported.png"));
+        assert_eq!(fs::read(&written)?, content);
+
+        assert!(export_cover(&db_file, "covers/missing.png", &dest).is_err());
+
+        let _ = fs::remove_dir_all(database_dir(&db_file));
+        Ok(())
+    }
+
+    #[test]
+    fn copy_covers_to_other_database() -> Result<()> {
+        let base =
+            std::env::temp_dir().join(format!("bookshelf-copy-covers-{}", std::process::id()));
+        let db_file = base.join("books.db");
+        let dest_db_file = base.join("copy").join("books.db");
+        fs::create_dir_all(covers_dir(&db_file))?;
+        fs::write(covers_dir(&db_file).join("cover.png"), [1u8; 10])?;
+
+        let covers = vec![
+            "covers/cover.png".to_owned(),
+            "covers/missing.png".to_owned(),
+        ];
+        assert_eq!(copy_covers(&db_file, &dest_db_file, &covers)?, 1);
+        assert_eq!(
+            fs::read(covers_dir(&dest_db_file).join("cover.png"))?,
+            [1u8; 10]
+        );
+        assert!(!covers_dir(&dest_db_file).join("missing.png").exists());
+        assert_eq!(copy_covers(&db_file, &db_file, &covers)?, 0);
+
+        let _ = fs::remove_dir_all(&base);
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_cover_paths() -> Result<()> {
+        let db_file = std::env::temp_dir()
+            .join(format!("bookshelf-prefetch-covers-{}", std::process::id()))
+            .join("books.db");
+        let dir = covers_dir(&db_file);
+        fs::create_dir_all(&dir)?;
+
+        fs::write(dir.join("1.jpg"), [1u8; 10])?;
+        fs::write(dir.join("2.png"), [2u8; 10])?;
+
+        let paths = cover_paths(
+            &db_file,
+            vec![
+                (1, "covers/1.jpg".to_owned()),
+                (2, "covers/2.png".to_owned()),
+                (3, "covers/missing.jpg".to_owned()),
+            ],
+        );
+        assert_eq!(paths.len(), 2);
+        assert_eq!(paths[&1], dir.join("1.jpg"));
+        assert_eq!(paths[&2], dir.join("2.png"));
+
+        let _ = fs::remove_dir_all(database_dir(&db_file));
+        Ok(())
+    }
+
+    #[test]
+    fn import_cover_by_content() -> Result<()> {
+        let db_file = std::env::temp_dir()
+            .join(format!("bookshelf-import-cover-{}", std::process::id()))
+            .join("books.db");
+        let dir = database_dir(&db_file).to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let png = [
+            &[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a][..],
+            &[7u8; 32],
+        ]
+        .concat();
+        fs::write(dir.join("cover.png"), &png)?;
+        fs::write(dir.join("copy.png"), &png)?;
+        fs::write(dir.join("notes.txt"), "no image")?;
+
+        let cover = import_cover(&db_file, &dir.join("cover.png"))?;
+        assert!(cover.starts_with("covers/") && cover.ends_with(".png"));
+        assert_eq!(fs::read(dir.join(&cover))?, png);
+        assert_eq!(import_cover(&db_file, &dir.join("copy.png"))?, cover);
+        assert_eq!(fs::read_dir(covers_dir(&db_file))?.count(), 1);
+
+        assert!(import_cover(&db_file, &dir.join("notes.txt")).is_err());
+
+        let _ = fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    #[test]
+    fn store_same_cover_once() -> Result<()> {
+        let dir =
+            std::env::temp_dir().join(format!("bookshelf-store-cover-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let jpg = [&[0xff, 0xd8, 0xff, 0xe0][..], &[3u8; 64]].concat();
+        let cover = store_cover(&jpg, &dir)?;
+        assert!(cover.starts_with("covers/") && cover.ends_with(".jpg"));
+        assert_eq!(store_cover(&jpg, &dir)?, cover);
+        assert_eq!(fs::read_dir(dir.join("covers"))?.count(), 1);
+        assert_eq!(fs::read(dir.join(&cover))?, jpg);
+
+        assert!(store_cover(b"no image", &dir).is_err());
+
+        let _ = fs::remove_dir_all(&dir);
+        Ok(())
+    }
+}
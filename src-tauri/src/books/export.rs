@@ -0,0 +1,798 @@
+// Copyright © 2023 Sandro Dallo
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+// This file contains the serialization of books into export files and back.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use chrono::{DateTime, Utc};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{de::IgnoredAny, Deserialize, Serialize};
+
+use super::models::{normalize_isbn, Book, BookDB, ConfigInitialized, ReadingStatus, SearchConfig};
+use super::Result;
+
+/// Magic bytes at the start of every gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Number of books fetched at once while exporting.
+const EXPORT_PAGE_SIZE: u64 = 500;
+
+/// Formats books can be exported to.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Json,
+    JsonGzip,
+    Csv,
+}
+
+/// Columns of the CSV format, one per field of a book.
+pub const CSV_COLUMNS: &[&str] = &[
+    "authors",
+    "cover_img",
+    "description",
+    "isbn",
+    "lang",
+    "tags",
+    "title",
+    "sub_title",
+    "publisher",
+    "publish_date",
+    "reading_status",
+    "started_at",
+    "finished_at",
+    "rating",
+    "owned",
+    "page_count",
+    "format",
+    "notes",
+    "series",
+    "series_index",
+    "acquired_date",
+    "id",
+    "created",
+    "updated",
+];
+
+/// Separator of multiple authors or tags within a CSV cell.
+const CSV_LIST_SEPARATOR: &str = ";";
+
+/// Columns a CSV file must contain to be imported.
+const CSV_REQUIRED_COLUMNS: &[&str] = &["authors", "isbn", "lang", "title"];
+
+/// Formats of files which can be imported.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportFormat {
+    Json,
+    Csv,
+}
+
+/// Summary of an import file, gathered without creating any book.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ImportInfo {
+    pub format: ImportFormat,
+    pub compressed: bool,
+    /// Number of books in the file, the CSV header is not counted.
+    pub row_count: u64,
+    /// False if a CSV header misses a required column or contains an unknown one.
+    pub header_ok: bool,
+}
+
+/// Outcome of an import, a dry run only validates the books.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    /// Number of books which passed the validation.
+    pub valid: u64,
+    /// Number of books which failed to parse or to validate.
+    pub failed: u64,
+    /// Number of books added to the database, zero for a dry run or if any book failed.
+    pub imported: u64,
+    pub errors: Vec<ImportError>,
+}
+
+/// Error of a single book within an import file, rows are counted from 1 without the CSV header.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ImportError {
+    pub row: u64,
+    pub message: String,
+}
+
+/// Streams all books matching `search` to `w`, e.g. stdout or a file. Books are fetched
+/// page by page ordered by id, so the library is never loaded at once. Sorting and paging
+/// of `search` are ignored. Returns the number of exported books.
+pub fn export_to_writer<W: Write>(
+    db: &mut dyn BookDB,
+    w: W,
+    format: ExportFormat,
+    search: &SearchConfig<ConfigInitialized>,
+) -> Result<u64> {
+    export_paged(db, w, format, search, EXPORT_PAGE_SIZE)
+}
+
+fn export_paged<W: Write>(
+    db: &mut dyn BookDB,
+    w: W,
+    format: ExportFormat,
+    search: &SearchConfig<ConfigInitialized>,
+    page_size: u64,
+) -> Result<u64> {
+    match format {
+        ExportFormat::Json => stream_books_json(db, w, search, page_size),
+        ExportFormat::JsonGzip => {
+            let mut enc = GzEncoder::new(w, Compression::default());
+            let count = stream_books_json(db, &mut enc, search, page_size)?;
+            enc.finish()?.flush()?;
+            Ok(count)
+        }
+        ExportFormat::Csv => stream_books_csv(db, w, search, page_size),
+    }
+}
+
+/// Writes the books as JSON array one by one, the result equals [write_books_json].
+fn stream_books_json<W: Write>(
+    db: &mut dyn BookDB,
+    mut w: W,
+    search: &SearchConfig<ConfigInitialized>,
+    page_size: u64,
+) -> Result<u64> {
+    let mut count = 0u64;
+    let mut cursor = None;
+
+    w.write_all(b"[")?;
+    loop {
+        let page = db.fetch_books(search.page_by_id(page_size, cursor))?;
+        for book in &page.items {
+            if count > 0 {
+                w.write_all(b",")?;
+            }
+            serde_json::to_writer(&mut w, book).map_err(io::Error::from)?;
+            count += 1;
+        }
+
+        match page.next_cursor {
+            Some(c) => cursor = Some(c),
+            None => break,
+        }
+    }
+    w.write_all(b"]")?;
+    w.flush()?;
+
+    Ok(count)
+}
+
+/// Writes the books as CSV one page after the other, the result equals [write_books_csv].
+fn stream_books_csv<W: Write>(
+    db: &mut dyn BookDB,
+    mut w: W,
+    search: &SearchConfig<ConfigInitialized>,
+    page_size: u64,
+) -> Result<u64> {
+    let mut count = 0u64;
+    let mut cursor = None;
+
+    write_csv_row(&mut w, CSV_COLUMNS.iter().map(|c| c.to_string()))?;
+    loop {
+        let page = db.fetch_books(search.page_by_id(page_size, cursor))?;
+        for book in &page.items {
+            write_csv_row(&mut w, csv_record(book))?;
+            count += 1;
+        }
+
+        match page.next_cursor {
+            Some(c) => cursor = Some(c),
+            None => break,
+        }
+    }
+    w.flush()?;
+
+    Ok(count)
+}
+
+/// Writes the books as CSV with a header of [CSV_COLUMNS]. Authors and tags are joined
+/// by `;`, dates are formatted as RFC 3339 and missing values are left empty.
+#[allow(dead_code)]
+pub fn write_books_csv<W: Write>(mut w: W, books: &[Book]) -> io::Result<()> {
+    write_csv_row(&mut w, CSV_COLUMNS.iter().map(|c| c.to_string()))?;
+    for book in books {
+        write_csv_row(&mut w, csv_record(book))?;
+    }
+    w.flush()
+}
+
+/// Returns the cells of a book in the order of [CSV_COLUMNS].
+fn csv_record(book: &Book) -> [String; 24] {
+    let date = |d: &Option<DateTime<Utc>>| d.map(|d| d.to_rfc3339()).unwrap_or_default();
+    [
+        book.authors.join(CSV_LIST_SEPARATOR),
+        book.cover_img.clone().unwrap_or_default(),
+        book.description.clone().unwrap_or_default(),
+        book.isbn.clone(),
+        book.lang.clone(),
+        book.tags
+            .as_ref()
+            .map(|t| t.join(CSV_LIST_SEPARATOR))
+            .unwrap_or_default(),
+        book.title.clone(),
+        book.sub_title.clone().unwrap_or_default(),
+        book.publisher.clone().unwrap_or_default(),
+        date(&book.publish_date),
+        book.reading_status.as_str().to_owned(),
+        date(&book.started_at),
+        date(&book.finished_at),
+        book.rating.map(|r| r.to_string()).unwrap_or_default(),
+        book.owned.to_string(),
+        book.page_count.map(|p| p.to_string()).unwrap_or_default(),
+        book.format.clone().unwrap_or_default(),
+        book.notes.clone().unwrap_or_default(),
+        book.series.clone().unwrap_or_default(),
+        book.series_index.map(|i| i.to_string()).unwrap_or_default(),
+        date(&book.acquired_date),
+        book.id.to_string(),
+        book.created.to_rfc3339(),
+        book.updated.to_rfc3339(),
+    ]
+}
+
+/// Writes a single CSV record terminated by CRLF. Cells containing separators, quotes or
+/// line breaks are quoted and their quotes doubled (RFC 4180).
+fn write_csv_row<W, I>(w: &mut W, cells: I) -> io::Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = String>,
+{
+    for (i, cell) in cells.into_iter().enumerate() {
+        if i > 0 {
+            w.write_all(b",")?;
+        }
+        if cell.contains([',', '"', '\n', '\r']) {
+            write!(w, "\"{}\"", cell.replace('"', "\"\""))?;
+        } else {
+            w.write_all(cell.as_bytes())?;
+        }
+    }
+    w.write_all(b"\r\n")
+}
+
+/// Writes the books as JSON array, gzip compressed if `compress` is set.
+#[allow(dead_code)]
+pub fn write_books_json<W: Write>(mut w: W, books: &[Book], compress: bool) -> io::Result<()> {
+    if compress {
+        let mut enc = GzEncoder::new(w, Compression::default());
+        serde_json::to_writer(&mut enc, books)?;
+        enc.finish()?.flush()
+    } else {
+        serde_json::to_writer(&mut w, books)?;
+        w.flush()
+    }
+}
+
+/// Returns a single book as pretty printed JSON, e.g. to share it. Dates are written as
+/// RFC 3339 strings.
+pub fn book_to_json(book: &Book) -> io::Result<String> {
+    Ok(serde_json::to_string_pretty(book)?)
+}
+
+/// Reads books written by [write_books_json], compressed input is
+/// detected by the gzip magic bytes.
+#[allow(dead_code)]
+pub fn read_books_json<R: Read>(r: R) -> io::Result<Vec<Book>> {
+    let mut r = BufReader::new(r);
+    if r.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        Ok(serde_json::from_reader(GzDecoder::new(r))?)
+    } else {
+        Ok(serde_json::from_reader(r)?)
+    }
+}
+
+/// Detects the format of an import file and counts its books, gzip compressed files
+/// are decompressed on the fly. Fails if a JSON file is not an array.
+pub fn inspect_import<R: Read>(r: R) -> io::Result<ImportInfo> {
+    let mut r = BufReader::new(r);
+    if r.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        let info = inspect_plain(BufReader::new(GzDecoder::new(r)))?;
+        return Ok(ImportInfo {
+            compressed: true,
+            ..info
+        });
+    }
+
+    inspect_plain(r)
+}
+
+/// Returns true if the input is a JSON array, leading whitespaces are consumed. JSON exports
+/// are arrays, everything else is treated as CSV.
+fn starts_as_json<R: BufRead>(r: &mut R) -> io::Result<bool> {
+    loop {
+        let buf = r.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(false);
+        }
+        match buf.iter().position(|b| !b.is_ascii_whitespace()) {
+            Some(i) => return Ok(buf[i] == b'['),
+            None => {
+                let n = buf.len();
+                r.consume(n);
+            }
+        }
+    }
+}
+
+fn inspect_plain<R: BufRead>(mut r: R) -> io::Result<ImportInfo> {
+    if starts_as_json(&mut r)? {
+        let rows: Vec<IgnoredAny> = serde_json::from_reader(r)?;
+        return Ok(ImportInfo {
+            format: ImportFormat::Json,
+            compressed: false,
+            row_count: rows.len() as u64,
+            header_ok: true,
+        });
+    }
+
+    let (header, records) = scan_csv(r)?;
+    let header_ok = !header.is_empty()
+        && header.iter().all(|c| CSV_COLUMNS.contains(&c.as_str()))
+        && CSV_REQUIRED_COLUMNS
+            .iter()
+            .all(|c| header.iter().any(|h| h.as_str() == *c));
+
+    Ok(ImportInfo {
+        format: ImportFormat::Csv,
+        compressed: false,
+        row_count: records.saturating_sub(1),
+        header_ok,
+    })
+}
+
+/// Reads the books of a JSON or CSV import file, gzip compressed files are decompressed on the fly.
+/// Each book is parsed and validated on its own, failures are collected in the report. Ids and
+/// timestamps of the file are not kept.
+pub fn read_import<R: Read>(r: R) -> io::Result<(Vec<Book>, ImportReport)> {
+    let mut r = BufReader::new(r);
+    let rows = if r.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        parse_plain(BufReader::new(GzDecoder::new(r)))?
+    } else {
+        parse_plain(r)?
+    };
+
+    let mut books = Vec::new();
+    let mut report = ImportReport::default();
+    for (i, row) in rows.into_iter().enumerate() {
+        match row.and_then(validate_import) {
+            Ok(book) => {
+                report.valid += 1;
+                books.push(Book { id: 0, ..book });
+            }
+            Err(message) => {
+                report.failed += 1;
+                report.errors.push(ImportError {
+                    row: i as u64 + 1,
+                    message,
+                });
+            }
+        }
+    }
+
+    Ok((books, report))
+}
+
+fn parse_plain<R: BufRead>(mut r: R) -> io::Result<Vec<std::result::Result<Book, String>>> {
+    if starts_as_json(&mut r)? {
+        let rows: Vec<serde_json::Value> = serde_json::from_reader(r)?;
+        return Ok(rows
+            .into_iter()
+            .map(|v| serde_json::from_value::<Book>(v).map_err(|e| e.to_string()))
+            .collect());
+    }
+
+    let mut records = parse_csv(r)?.into_iter();
+    let header = match records.next() {
+        Some(h) => h,
+        None => return Ok(Vec::new()),
+    };
+    if let Some(c) = header.iter().find(|c| !CSV_COLUMNS.contains(&c.as_str())) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unknown CSV column: {}", c),
+        ));
+    }
+
+    Ok(records.map(|rec| csv_book(&header, &rec)).collect())
+}
+
+/// Creates a book of a CSV record, the counterpart of [csv_record].
+fn csv_book(header: &[String], record: &[String]) -> std::result::Result<Book, String> {
+    let mut book = Book::default();
+    let list = |v: &str| -> Vec<String> {
+        v.split(CSV_LIST_SEPARATOR)
+            .map(|s| s.trim().to_owned())
+            .filter(|s| !s.is_empty())
+            .collect()
+    };
+    let text = |v: &str| Some(v.to_owned()).filter(|v| !v.is_empty());
+    let date = |v: &str| -> std::result::Result<Option<DateTime<Utc>>, String> {
+        match v {
+            "" => Ok(None),
+            v => DateTime::parse_from_rfc3339(v)
+                .map(|d| Some(d.with_timezone(&Utc)))
+                .map_err(|e| format!("invalid date {}: {}", v, e)),
+        }
+    };
+
+    for (column, value) in header.iter().zip(record) {
+        let value = value.trim();
+        match column.as_str() {
+            "authors" => book.authors = list(value),
+            "cover_img" => book.cover_img = text(value),
+            "description" => book.description = text(value),
+            "isbn" => book.isbn = value.to_owned(),
+            "lang" => book.lang = value.to_owned(),
+            "tags" => book.tags = Some(list(value)).filter(|t| !t.is_empty()),
+            "title" => book.title = value.to_owned(),
+            "sub_title" => book.sub_title = text(value),
+            "publisher" => book.publisher = text(value),
+            "publish_date" => book.publish_date = date(value)?,
+            "reading_status" => book.reading_status = ReadingStatus::from_str_or_default(value),
+            "started_at" => book.started_at = date(value)?,
+            "finished_at" => book.finished_at = date(value)?,
+            "rating" if value.is_empty() => book.rating = None,
+            "rating" => {
+                book.rating = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid rating: {}", value))?,
+                )
+            }
+            "page_count" if value.is_empty() => book.page_count = None,
+            "page_count" => {
+                book.page_count = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid page count: {}", value))?,
+                )
+            }
+            "format" => book.format = text(value),
+            "notes" => book.notes = text(value),
+            "series" => book.series = text(value),
+            "acquired_date" => book.acquired_date = date(value)?,
+            "series_index" if value.is_empty() => book.series_index = None,
+            "series_index" => {
+                book.series_index = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid series index: {}", value))?,
+                )
+            }
+            // Books are owned unless the column says otherwise
+            "owned" if value.is_empty() => book.owned = true,
+            "owned" => {
+                book.owned = value
+                    .parse()
+                    .map_err(|_| format!("invalid owned flag: {}", value))?
+            }
+            _ => {}
+        }
+    }
+
+    Ok(book)
+}
+
+/// Checks the fields a book requires to be added and normalizes its ISBN.
+fn validate_import(mut book: Book) -> std::result::Result<Book, String> {
+    let missing: Vec<&str> = [
+        ("title", book.title.trim().is_empty()),
+        ("authors", book.authors.is_empty()),
+        ("isbn", book.isbn.trim().is_empty()),
+        ("lang", book.lang.trim().is_empty()),
+    ]
+    .into_iter()
+    .filter_map(|(f, empty)| empty.then_some(f))
+    .collect();
+
+    if !missing.is_empty() {
+        return Err(format!("missing {}", missing.join(", ")));
+    }
+
+    if let Some(r) = book.rating.filter(|r| !(1..=5).contains(r)) {
+        return Err(format!("rating {} is not between 1 and 5", r));
+    }
+
+    if book.page_count == Some(0) {
+        return Err("page count must be greater than 0".to_owned());
+    }
+
+    if let Some(i) = book.series_index.filter(|i| !i.is_finite() || *i < 0.0) {
+        return Err(format!("series index {} is not a valid position", i));
+    }
+
+    book.isbn = normalize_isbn(&book.isbn).map_err(|e| e.to_string())?;
+    Ok(book)
+}
+
+/// Parses all records of a CSV file, quoted fields may contain separators, line breaks and
+/// doubled quotes (RFC 4180). Empty lines are skipped. The input is read byte by byte, so it
+/// must be buffered.
+fn parse_csv<R: BufRead>(r: R) -> io::Result<Vec<Vec<String>>> {
+    let mut records: Vec<Vec<String>> = Vec::new();
+    let mut record: Vec<String> = Vec::new();
+    let mut field: Vec<u8> = Vec::new();
+    let (mut in_quotes, mut line_empty) = (false, true);
+
+    let mut bytes = r.bytes().peekable();
+    while let Some(b) = bytes.next() {
+        match b? {
+            b'"' if in_quotes && matches!(bytes.peek(), Some(Ok(b'"'))) => {
+                bytes.next();
+                field.push(b'"');
+            }
+            b'"' => in_quotes = !in_quotes,
+            b',' if !in_quotes => {
+                record.push(String::from_utf8_lossy(&field).into_owned());
+                field.clear();
+            }
+            b'\n' if !in_quotes => {
+                if !line_empty {
+                    record.push(String::from_utf8_lossy(&field).into_owned());
+                    field.clear();
+                    records.push(std::mem::take(&mut record));
+                }
+                line_empty = true;
+                continue;
+            }
+            b'\r' if !in_quotes => continue,
+            b => field.push(b),
+        }
+        line_empty = false;
+    }
+
+    if !line_empty {
+        record.push(String::from_utf8_lossy(&field).into_owned());
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+/// Returns the column names of the first record and the number of records, including the
+/// header. Quoted fields may contain separators and line breaks (RFC 4180), empty lines are skipped.
+/// The input is read byte by byte, so it must be buffered.
+fn scan_csv<R: BufRead>(r: R) -> io::Result<(Vec<String>, u64)> {
+    let mut header: Vec<String> = Vec::new();
+    let mut field: Vec<u8> = Vec::new();
+    let (mut in_quotes, mut line_empty, mut records) = (false, true, 0u64);
+
+    let mut end_field = |field: &mut Vec<u8>, records: u64| {
+        if records == 0 {
+            header.push(String::from_utf8_lossy(field).trim().to_owned());
+        }
+        field.clear();
+    };
+
+    for b in r.bytes() {
+        match b? {
+            b'"' => in_quotes = !in_quotes,
+            b',' if !in_quotes => end_field(&mut field, records),
+            b'\n' if !in_quotes => {
+                if !line_empty {
+                    end_field(&mut field, records);
+                    records += 1;
+                }
+                line_empty = true;
+                continue;
+            }
+            b'\r' if !in_quotes => continue,
+            b => field.push(b),
+        }
+        line_empty = false;
+    }
+
+    if !line_empty {
+        end_field(&mut field, records);
+        records += 1;
+    }
+
+    Ok((header, records))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::{
+        book_to_json, export_paged, inspect_import, read_books_json, read_import, write_books_csv,
+        write_books_json, ExportFormat, ImportError, ImportFormat, GZIP_MAGIC,
+    };
+    use crate::books::models::Book;
+    use crate::books::models::{BookDB, SearchConfig};
+    use crate::books::store::SqliteStore;
+
+    fn books() -> Vec<Book> {
+        vec![
+            Book {
+                authors: vec!["David Lagercrantz".to_owned()],
+                description: Some("Lisbeth Salander is an unstoppable force!".to_owned()),
+                isbn: "9780857056429".to_owned(),
+                lang: "EN".to_owned(),
+                tags: Some(vec!["Suspense".to_owned(), "Thriller".to_owned()]),
+                title: "The Girl Who Takes an Eye for an Eye".to_owned(),
+                publisher: Some("McLehose Press".to_owned()),
+                publish_date: Some(Utc.timestamp_opt(1483523713, 0).unwrap()),
+                id: 1,
+                ..Default::default()
+            },
+            Book {
+                authors: vec!["Richard Dawkins".to_owned()],
+                isbn: "9783550087653".to_owned(),
+                lang: "DE".to_owned(),
+                title: "Es gibt keine Schöpfung".to_owned(),
+                id: 3,
+                ..Default::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn json_round_trip() -> std::io::Result<()> {
+        for compress in [false, true] {
+            let mut buf: Vec<u8> = Vec::new();
+            write_books_json(&mut buf, &books(), compress)?;
+            assert_eq!(buf.starts_with(&GZIP_MAGIC), compress);
+
+            let loaded = read_books_json(&buf[..])?;
+            assert_eq!(loaded, books());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn single_book_json_round_trip() -> std::io::Result<()> {
+        let book = books().remove(0);
+        let json = book_to_json(&book)?;
+        assert!(json.contains("\"publish_date\": \"2017-01-04T09:55:13Z\""));
+
+        let loaded: Book = serde_json::from_str(&json)?;
+        assert_eq!(loaded, book);
+
+        Ok(())
+    }
+
+    #[test]
+    fn inspect_csv_file() -> std::io::Result<()> {
+        let csv = "title,authors,isbn,lang,description\r\n\
+            \"Eye for an Eye, The\",David Lagercrantz,9780857056429,EN,\r\n\
+            Mobile Communications,Jochen Schiller,9780321123817,EN,\"Explains \"\"mobile\"\"\ncommunications\"\r\n\
+            Es gibt keine Schöpfung,Richard Dawkins,9783550087653,DE,\r\n\
+            \r\n";
+
+        let info = inspect_import(csv.as_bytes())?;
+        assert_eq!(info.format, ImportFormat::Csv);
+        assert!(!info.compressed);
+        assert_eq!(info.row_count, 3);
+        assert!(info.header_ok);
+
+        let info = inspect_import("title,pages\nDune,412\n".as_bytes())?;
+        assert_eq!(info.row_count, 1);
+        assert!(!info.header_ok);
+
+        let mut buf: Vec<u8> = Vec::new();
+        write_books_json(&mut buf, &books(), true)?;
+        let info = inspect_import(&buf[..])?;
+        assert_eq!(info.format, ImportFormat::Json);
+        assert!(info.compressed);
+        assert_eq!(info.row_count, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_csv_file() -> std::io::Result<()> {
+        let mut books = books();
+        books[1].title = "Schöpfung, \"keine\"".to_owned();
+
+        let mut buf: Vec<u8> = Vec::new();
+        write_books_csv(&mut buf, &books)?;
+        let csv = String::from_utf8(buf).unwrap();
+        let lines: Vec<&str> = csv.split("\r\n").collect();
+
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("authors,cover_img,description,isbn,"));
+        assert_eq!(
+            lines[1],
+            "David Lagercrantz,,Lisbeth Salander is an unstoppable force!,9780857056429,EN,\
+            Suspense;Thriller,The Girl Who Takes an Eye for an Eye,,McLehose Press,\
+            2017-01-04T09:55:13+00:00,unread,,,,true,,,,,,,1,\
+            1970-01-01T00:00:00+00:00,1970-01-01T00:00:00+00:00"
+        );
+        assert!(lines[2].contains(",\"Schöpfung, \"\"keine\"\"\",,,"));
+        assert_eq!(lines[3], "");
+
+        let info = inspect_import(csv.as_bytes())?;
+        assert_eq!(info.row_count, 2);
+        assert!(info.header_ok);
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_import_files() -> std::io::Result<()> {
+        let csv = "title,authors,isbn,lang,tags,publish_date\r\n\
+            \"Eye for an Eye, The\",David Lagercrantz,9780857056429,EN,Suspense; Thriller,2017-01-04T09:55:13Z\r\n\
+            Mobile Communications,,9780321123817,EN,,\r\n\
+            \"Say \"\"Hi\"\"\",Goethe;Schiller,978-3-16-148410-0,DE,,\r\n\
+            Es gibt keine Schöpfung,Richard Dawkins,9783550087653,DE,,yesterday\r\n";
+
+        let (parsed, report) = read_import(csv.as_bytes())?;
+        assert_eq!((report.valid, report.failed), (2, 2));
+        assert_eq!(parsed[0].title, "Eye for an Eye, The");
+        assert_eq!(
+            parsed[0].tags,
+            Some(vec!["Suspense".to_owned(), "Thriller".to_owned()])
+        );
+        assert_eq!(parsed[0].publish_date, books()[0].publish_date);
+        assert_eq!(parsed[1].title, "Say \"Hi\"");
+        assert_eq!(parsed[1].authors, vec!["Goethe", "Schiller"]);
+        assert_eq!(parsed[1].isbn, "9783161484100");
+        assert_eq!(parsed[1].tags, None);
+        assert_eq!(
+            report.errors[0],
+            ImportError {
+                row: 2,
+                message: "missing authors".to_owned(),
+            }
+        );
+        assert_eq!(report.errors[1].row, 4);
+
+        // Round trip of both export formats
+        let mut buf: Vec<u8> = Vec::new();
+        write_books_csv(&mut buf, &books())?;
+        let (imported, report) = read_import(&buf[..])?;
+        assert_eq!(report.failed, 0);
+        assert_eq!(imported[0].authors, books()[0].authors);
+        assert_eq!(imported[1].title, books()[1].title);
+
+        let mut buf: Vec<u8> = Vec::new();
+        write_books_json(&mut buf, &books(), true)?;
+        let (imported, report) = read_import(&buf[..])?;
+        assert_eq!(report.valid, 2);
+        assert_eq!(imported[0].id, 0);
+
+        assert!(read_import("title,pages\nDune,412\n".as_bytes()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn stream_export_to_buffer() -> crate::books::Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        let all = db.fetch_books(SearchConfig::new("").build())?.items;
+
+        for format in [ExportFormat::Json, ExportFormat::JsonGzip] {
+            let mut buf: Vec<u8> = Vec::new();
+            let search = SearchConfig::new("").build();
+            assert_eq!(export_paged(&mut db, &mut buf, format, &search, 2)?, 3);
+            assert_eq!(read_books_json(&buf[..])?, all);
+        }
+
+        let mut buf: Vec<u8> = Vec::new();
+        let search = SearchConfig::new("Dawkins").build();
+        assert_eq!(
+            export_paged(&mut db, &mut buf, ExportFormat::Json, &search, 2)?,
+            1
+        );
+        assert_eq!(read_books_json(&buf[..])?[0].id, 3);
+
+        let mut csv: Vec<u8> = Vec::new();
+        let search = SearchConfig::new("").build();
+        export_paged(&mut db, &mut csv, ExportFormat::Csv, &search, 2)?;
+        let mut expected: Vec<u8> = Vec::new();
+        write_books_csv(&mut expected, &all)?;
+        assert_eq!(csv, expected);
+
+        Ok(())
+    }
+}
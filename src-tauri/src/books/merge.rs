@@ -0,0 +1,333 @@
+// Copyright © 2023 Sandro Dallo
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+// This file contains the field level merging of books from one database into another.
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+
+use super::models::{Book, BookDB, Result, SearchConfig};
+use super::operation::CancellationToken;
+
+/// Editable fields of a book which are tracked and merged individually.
+pub const TRACKED_FIELDS: &[&str] = &[
+    "authors",
+    "cover_img",
+    "description",
+    "isbn",
+    "lang",
+    "tags",
+    "title",
+    "sub_title",
+    "publisher",
+    "publish_date",
+    "reading_status",
+    "started_at",
+    "finished_at",
+    "rating",
+    "owned",
+    "page_count",
+    "format",
+    "notes",
+    "series",
+    "series_index",
+    "acquired_date",
+];
+
+/// Summary of a merge, which contains the partial progress if it was cancelled.
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub struct MergeSummary {
+    pub added: u64,
+    pub updated: u64,
+    pub unchanged: u64,
+    pub cancelled: bool,
+}
+
+/// Returns the field modification timestamps of `book` after it replaced `stored`.
+/// Changed fields get the timestamp passed along with the book if it is newer than
+/// the stored one (e.g. taken over by a merge), otherwise `now`.
+pub fn track_field_updates(
+    stored: &Book,
+    book: &Book,
+    now: DateTime<Utc>,
+) -> Option<BTreeMap<String, DateTime<Utc>>> {
+    let (old, new) = (to_json(stored), to_json(book));
+    let mut times = stored.field_updated.clone().unwrap_or_default();
+
+    for field in TRACKED_FIELDS {
+        if old[field] == new[field] {
+            continue;
+        }
+
+        let stored_time = stored.field_updated(field);
+        let time = match book.field_updated.as_ref().and_then(|m| m.get(*field)) {
+            Some(t) if *t > stored_time => *t,
+            _ => now,
+        };
+        times.insert(field.to_string(), time);
+    }
+
+    Some(times).filter(|t| !t.is_empty())
+}
+
+/// Merges `remote` into `local` field by field, the last modified value wins and
+/// `local` wins on ties. Returns `None` if `local` is already up to date.
+pub fn merge_book(local: &Book, remote: &Book) -> Option<Book> {
+    let (mut merged, theirs) = (to_json(local), to_json(remote));
+    let mut times = local.field_updated.clone().unwrap_or_default();
+    let mut changed = false;
+
+    for field in TRACKED_FIELDS {
+        let remote_time = remote.field_updated(field);
+        if merged[field] != theirs[field] && remote_time > local.field_updated(field) {
+            merged[field] = theirs[field].clone();
+            times.insert(field.to_string(), remote_time);
+            changed = true;
+        }
+    }
+
+    if !changed {
+        return None;
+    }
+
+    // Values are taken from another book, so they always deserialize.
+    let mut book: Book = serde_json::from_value(merged).expect("Merged book is always valid");
+    book.field_updated = Some(times);
+    Some(book)
+}
+
+/// Combines two entries of the same book, authors and tags of both are united and
+/// empty fields of `keep` are filled from `remove`. All other values of `keep` win.
+pub fn combine_books(keep: &Book, remove: &Book) -> Book {
+    let mut book = keep.clone();
+
+    for (field, other) in [
+        (&mut book.title, &remove.title),
+        (&mut book.isbn, &remove.isbn),
+        (&mut book.lang, &remove.lang),
+    ] {
+        if field.trim().is_empty() {
+            *field = other.clone();
+        }
+    }
+
+    for author in &remove.authors {
+        if !book.authors.contains(author) {
+            book.authors.push(author.clone());
+        }
+    }
+
+    let mut tags = book.tags.take().unwrap_or_default();
+    for tag in remove.tags.iter().flatten() {
+        if !tags.contains(tag) {
+            tags.push(tag.clone());
+        }
+    }
+    book.tags = Some(tags).filter(|t| !t.is_empty());
+
+    book.cover_img = book.cover_img.or_else(|| remove.cover_img.clone());
+    book.description = book.description.or_else(|| remove.description.clone());
+    book.sub_title = book.sub_title.or_else(|| remove.sub_title.clone());
+    book.publisher = book.publisher.or_else(|| remove.publisher.clone());
+    book.publish_date = book.publish_date.or(remove.publish_date);
+    book.started_at = book.started_at.or(remove.started_at);
+    book.finished_at = book.finished_at.or(remove.finished_at);
+    book.rating = book.rating.or(remove.rating);
+    book.owned = book.owned || remove.owned;
+    book.page_count = book.page_count.or(remove.page_count);
+    book.format = book.format.or_else(|| remove.format.clone());
+    book.notes = book.notes.or_else(|| remove.notes.clone());
+    book.series = book.series.or_else(|| remove.series.clone());
+    book.series_index = book.series_index.or(remove.series_index);
+    book.acquired_date = book.acquired_date.or(remove.acquired_date);
+
+    book
+}
+
+/// Merges all books of `source` into `target`, books are matched by their ISBN.
+/// Books missing in `target` are added. Checks the token between two books.
+pub fn merge_from(
+    target: &mut dyn BookDB,
+    source: &mut dyn BookDB,
+    token: &CancellationToken,
+) -> Result<MergeSummary> {
+    let all = || SearchConfig::new("").build();
+    let local: HashMap<String, Book> = target
+        .fetch_books(all())?
+        .items
+        .into_iter()
+        .map(|b| (b.isbn.clone(), b))
+        .collect();
+
+    let mut summary = MergeSummary::default();
+    for remote in source.fetch_books(all())?.items {
+        if token.is_cancelled() {
+            summary.cancelled = true;
+            break;
+        }
+
+        match local.get(&remote.isbn) {
+            None => {
+                target.add_book(&mut remote.clone())?;
+                summary.added += 1;
+            }
+            Some(book) => match merge_book(book, &remote) {
+                Some(mut merged) => {
+                    target.update_book(&mut merged)?;
+                    summary.updated += 1;
+                }
+                None => summary.unchanged += 1,
+            },
+        }
+    }
+
+    Ok(summary)
+}
+
+fn to_json(book: &Book) -> Value {
+    serde_json::to_value(book.normalized()).expect("Serializing a book never fails")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use chrono::{TimeZone, Utc};
+
+    use super::{merge_from, MergeSummary};
+    use crate::books::models::{Book, BookDB, DbTuning, Result};
+    use crate::books::operation::CancellationToken;
+    use crate::books::store::SqliteStore;
+
+    fn book(isbn: &str) -> Book {
+        Book {
+            authors: vec!["Richard Dawkins".to_owned()],
+            description: Some("Old description".to_owned()),
+            isbn: isbn.to_owned(),
+            lang: "EN".to_owned(),
+            title: "Old title".to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn merge_newest_fields() -> Result<()> {
+        let later = Utc.with_ymd_and_hms(2100, 1, 1, 0, 0, 0).unwrap();
+        let mut a = SqliteStore::with_seed("db_file", false, &DbTuning::default())?;
+        let mut b = SqliteStore::with_seed("db_file", false, &DbTuning::default())?;
+
+        let mut in_a = book("9783550087653");
+        a.add_book(&mut in_a)?;
+        let mut in_b = book("9783550087653");
+        b.add_book(&mut in_b)?;
+        b.add_book(&mut book("9780321123817"))?;
+
+        // The title was changed last in a, the description in b
+        in_a.title = "Newer title".to_owned();
+        in_a.field_updated = Some(BTreeMap::from([("title".to_owned(), later)]));
+        a.update_book(&mut in_a)?;
+        in_b.title = "Older title".to_owned();
+        in_b.description = Some("Newer description".to_owned());
+        in_b.field_updated = Some(BTreeMap::from([("description".to_owned(), later)]));
+        b.update_book(&mut in_b)?;
+
+        let summary = merge_from(&mut a, &mut b, &CancellationToken::default())?;
+        assert_eq!(
+            summary,
+            MergeSummary {
+                added: 1,
+                updated: 1,
+                unchanged: 0,
+                cancelled: false,
+            }
+        );
+
+        let merged = a.get_book(in_a.id)?;
+        assert_eq!(merged.title, "Newer title");
+        assert_eq!(merged.description.as_deref(), Some("Newer description"));
+        assert_eq!(merged.field_updated("description"), later);
+
+        // Merging again changes nothing
+        let summary = merge_from(&mut a, &mut b, &CancellationToken::default())?;
+        assert_eq!(summary.updated, 0);
+        assert_eq!(summary.unchanged, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge_complementary_books() -> Result<()> {
+        let mut db = SqliteStore::with_seed("db_file", false, &DbTuning::default())?;
+
+        let mut keep = Book {
+            authors: vec!["Richard Dawkins".to_owned()],
+            tags: Some(vec!["Biologie".to_owned()]),
+            description: Some("Kept description".to_owned()),
+            ..book("9783550087653")
+        };
+        db.add_book(&mut keep)?;
+        let mut remove = Book {
+            authors: vec!["Sebastian Vogel".to_owned(), "Richard Dawkins".to_owned()],
+            tags: Some(vec!["Religion".to_owned(), "Biologie".to_owned()]),
+            publisher: Some("Ullstein Verlag".to_owned()),
+            ..book("080442957X")
+        };
+        db.add_book(&mut remove)?;
+
+        let merged = db.merge_books(keep.id, &[remove.id])?;
+        assert_eq!(merged.id, keep.id);
+        assert_eq!(merged.isbn, "9783550087653");
+        assert_eq!(merged.authors, vec!["Richard Dawkins", "Sebastian Vogel"]);
+        assert_eq!(
+            merged.tags,
+            Some(vec!["Biologie".to_owned(), "Religion".to_owned()])
+        );
+        assert_eq!(merged.description, Some("Kept description".to_owned()));
+        assert_eq!(merged.publisher, Some("Ullstein Verlag".to_owned()));
+
+        assert!(db.get_book(remove.id).is_err());
+        assert_eq!(db.get_book(keep.id)?, merged);
+        assert!(db.merge_books(keep.id, &[keep.id]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn merge_several_books() -> Result<()> {
+        let mut db = SqliteStore::with_seed("db_file", false, &DbTuning::default())?;
+        let mut add = |isbn: &str, tags: &[&str]| -> Result<i64> {
+            let mut book = Book {
+                tags: Some(tags.iter().map(|t| t.to_string()).collect()),
+                ..book(isbn)
+            };
+            db.add_book(&mut book)?;
+            Ok(book.id)
+        };
+        let keep = add("9783550087653", &["Biologie"])?;
+        let first = add("9780321123817", &["Religion"])?;
+        let second = add("080442957X", &["Wissenschaft", "Religion"])?;
+
+        let merged = db.merge_books(keep, &[first, second, first])?;
+        assert_eq!(
+            merged.tags,
+            Some(vec![
+                "Biologie".to_owned(),
+                "Religion".to_owned(),
+                "Wissenschaft".to_owned()
+            ])
+        );
+        assert_eq!(merged.authors, vec!["Richard Dawkins"]);
+        assert!(db.get_book(first).is_err());
+        assert!(db.get_book(second).is_err());
+
+        // Unknown books roll back the whole merge
+        assert!(db.merge_books(keep, &[42]).is_err());
+        assert_eq!(db.get_book(keep)?, merged);
+
+        Ok(())
+    }
+}
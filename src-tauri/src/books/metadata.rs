@@ -0,0 +1,538 @@
+// Copyright © 2023 Sandro Dallo
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+// This file contains the building blocks to query external book metadata providers.
+
+#[cfg(test)]
+use std::collections::HashMap;
+use std::{error::Error, fmt::Display, thread, time::Duration};
+
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use reqwest::blocking::Client;
+use serde::{de::DeserializeOwned, Deserialize};
+
+use super::models::{Book, BookError};
+
+/// Delay used if a provider rate limits without sending a `Retry-After` header.
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Number of retries if a provider rate limits a request.
+const MAX_RETRIES: u32 = 3;
+/// Timeout of a single request to a provider.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// OpenLibrary asks clients to identify themselves.
+const USER_AGENT: &str = concat!("bookshelf/", env!("CARGO_PKG_VERSION"));
+
+/// Base URL of the OpenLibrary API.
+pub const OPEN_LIBRARY_URL: &str = "https://openlibrary.org";
+/// Base URL of the OpenLibrary cover images.
+pub const OPEN_LIBRARY_COVERS_URL: &str = "https://covers.openlibrary.org";
+
+/// All known errors of a metadata provider.
+#[derive(Debug, PartialEq)]
+pub enum MetadataError {
+    /// The provider rejected the request with HTTP 429. Contains the delay
+    /// announced by the `Retry-After` header, if any.
+    RateLimited { retry_after: Option<Duration> },
+    /// The provider answered with an unexpected HTTP status.
+    Status(u16),
+    /// The request could not be sent or the response could not be read.
+    Network(String),
+    /// The provider answered with a body which could not be parsed.
+    InvalidResponse(String),
+}
+
+impl Error for MetadataError {}
+
+impl Display for MetadataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MetadataError::RateLimited {
+                retry_after: Some(d),
+            } => {
+                write!(
+                    f,
+                    "Rate limited by provider, retry after {} seconds",
+                    d.as_secs()
+                )
+            }
+            MetadataError::RateLimited { retry_after: None } => {
+                write!(f, "Rate limited by provider")
+            }
+            MetadataError::Status(s) => write!(f, "Provider answered with status {}", s),
+            MetadataError::Network(s) => write!(f, "Network error: {}", s),
+            MetadataError::InvalidResponse(s) => write!(f, "Invalid response: {}", s),
+        }
+    }
+}
+
+impl From<MetadataError> for BookError {
+    fn from(value: MetadataError) -> Self {
+        match value {
+            // Providers answer with 404 for unknown ISBNs.
+            MetadataError::Status(404) => BookError::NotFound,
+            e => BookError::MetadataLookup(e.to_string()),
+        }
+    }
+}
+
+pub type Result<T, E = MetadataError> = core::result::Result<T, E>;
+
+/// A transport agnostic view of a provider response, only carries what
+/// the metadata module needs to evaluate.
+#[derive(Debug, Default, Clone)]
+pub struct Response {
+    pub status: u16,
+    /// Raw value of the `Retry-After` header.
+    pub retry_after: Option<String>,
+    pub body: String,
+}
+
+impl Response {
+    /// Returns the response if the provider answered successfully, otherwise
+    /// the matching [MetadataError]. HTTP 429 results in [MetadataError::RateLimited].
+    pub fn check_status(self) -> Result<Self> {
+        match self.status {
+            200..=299 => Ok(self),
+            429 => Err(MetadataError::RateLimited {
+                retry_after: self
+                    .retry_after
+                    .as_deref()
+                    .and_then(|v| parse_retry_after(v, Utc::now())),
+            }),
+            s => Err(MetadataError::Status(s)),
+        }
+    }
+}
+
+/// Parses the value of a `Retry-After` header, which is either a number of
+/// seconds or a HTTP date. Dates in the past result in a zero delay.
+pub fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    DateTime::parse_from_rfc2822(value).ok().map(|d| {
+        (d.with_timezone(&Utc) - now)
+            .to_std()
+            .unwrap_or(Duration::ZERO)
+    })
+}
+
+/// Calls `op` and, as long as the provider reports a rate limit, waits for the
+/// announced delay before calling it again. Gives up after `max_retries` retries
+/// and returns the last [MetadataError::RateLimited], so batch operations (e.g.
+/// filling missing covers) back off instead of hammering the provider.
+pub fn with_backoff<T, F>(max_retries: u32, mut op: F) -> Result<T>
+where
+    F: FnMut() -> Result<T>,
+{
+    let mut retries = 0;
+    loop {
+        match op() {
+            Err(MetadataError::RateLimited { retry_after }) if retries < max_retries => {
+                retries += 1;
+                thread::sleep(retry_after.unwrap_or(DEFAULT_RETRY_DELAY));
+            }
+            res => return res,
+        }
+    }
+}
+
+/// The metadata of a book as known by a provider, used to prefill a new book.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct BookMetadata {
+    pub isbn: String,
+    pub title: String,
+    pub sub_title: Option<String>,
+    pub authors: Vec<String>,
+    pub publisher: Option<String>,
+    pub publish_date: Option<DateTime<Utc>>,
+    pub cover_url: Option<String>,
+}
+
+impl From<BookMetadata> for Book {
+    /// Creates a book which is not stored yet, fields unknown to providers (e.g. `lang`)
+    /// are left empty.
+    fn from(value: BookMetadata) -> Self {
+        Book {
+            isbn: value.isbn,
+            title: value.title,
+            sub_title: value.sub_title,
+            authors: value.authors,
+            publisher: value.publisher,
+            publish_date: value.publish_date,
+            cover_img: value.cover_url,
+            ..Default::default()
+        }
+    }
+}
+
+/// A source of book metadata, e.g. OpenLibrary. The trait is object safe, so the
+/// active provider can be replaced at runtime.
+pub trait MetadataProvider {
+    /// Looks up the metadata of the book with the given ISBN. Unknown ISBNs result
+    /// in [MetadataError::Status] 404.
+    fn by_isbn(&self, isbn: &str) -> Result<BookMetadata>;
+}
+
+/// Removes separators from an ISBN, e.g. `978-3-453-27085-5` becomes `9783453270855`.
+fn normalize_isbn(isbn: &str) -> String {
+    isbn.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .collect()
+}
+
+/// Sends a GET request to the given URL, allows to stub the provider in tests.
+pub type Fetch = Box<dyn Fn(&str) -> Result<Response> + Send + Sync>;
+
+/// Looks up books by the OpenLibrary API, no API key required.
+/// See <https://openlibrary.org/dev/docs/api/books>.
+pub struct OpenLibraryProvider {
+    base_url: String,
+    fetch: Fetch,
+}
+
+impl OpenLibraryProvider {
+    /// Creates a provider which queries [OPEN_LIBRARY_URL].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the TLS backend cannot be initialized, like [Client::new].
+    pub fn new() -> Self {
+        let client = Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .user_agent(USER_AGENT)
+            .build()
+            .expect("failed to initialize http client");
+
+        Self::with_fetch(
+            OPEN_LIBRARY_URL,
+            Box::new(move |url| http_get(&client, url)),
+        )
+    }
+
+    /// Creates a provider which sends all requests for `base_url` to `fetch`.
+    pub fn with_fetch(base_url: &str, fetch: Fetch) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            fetch,
+        }
+    }
+
+    fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let url = format!("{}{}", self.base_url, path);
+        let resp = with_backoff(MAX_RETRIES, || (self.fetch)(&url)?.check_status())?;
+        serde_json::from_str(&resp.body)
+            .map_err(|e| MetadataError::InvalidResponse(format!("{}: {}", url, e)))
+    }
+}
+
+/// The fields of an OpenLibrary edition used by bookshelf.
+#[derive(Debug, Deserialize)]
+struct Edition {
+    title: String,
+    subtitle: Option<String>,
+    #[serde(default)]
+    publishers: Vec<String>,
+    publish_date: Option<String>,
+    #[serde(default)]
+    covers: Vec<i64>,
+    #[serde(default)]
+    authors: Vec<Reference>,
+}
+
+/// A reference to another OpenLibrary resource, e.g. `/authors/OL34184A`.
+#[derive(Debug, Deserialize)]
+struct Reference {
+    key: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Author {
+    name: String,
+}
+
+impl Default for OpenLibraryProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MetadataProvider for OpenLibraryProvider {
+    fn by_isbn(&self, isbn: &str) -> Result<BookMetadata> {
+        let isbn = normalize_isbn(isbn);
+        let edition: Edition = self.get(&format!("/isbn/{}.json", isbn))?;
+
+        // Editions only reference their authors, the names require a request each.
+        let authors = edition
+            .authors
+            .iter()
+            .map(|a| {
+                self.get::<Author>(&format!("{}.json", a.key))
+                    .map(|a| a.name)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(BookMetadata {
+            authors,
+            isbn,
+            title: edition.title,
+            sub_title: edition.subtitle,
+            publisher: edition.publishers.into_iter().next(),
+            publish_date: edition.publish_date.as_deref().and_then(parse_publish_date),
+            // OpenLibrary uses -1 for removed covers.
+            cover_url: edition
+                .covers
+                .iter()
+                .find(|&&id| id > 0)
+                .map(|id| format!("{}/b/id/{}-L.jpg", OPEN_LIBRARY_COVERS_URL, id)),
+        })
+    }
+}
+
+/// A provider answering from a fixed set of books, used to test without network.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct StaticProvider(pub HashMap<String, BookMetadata>);
+
+#[cfg(test)]
+impl StaticProvider {
+    /// Creates a provider which knows the given books by their ISBN.
+    pub fn new(books: impl IntoIterator<Item = BookMetadata>) -> Self {
+        Self(books.into_iter().map(|b| (b.isbn.clone(), b)).collect())
+    }
+}
+
+#[cfg(test)]
+impl MetadataProvider for StaticProvider {
+    fn by_isbn(&self, isbn: &str) -> Result<BookMetadata> {
+        self.0
+            .get(&normalize_isbn(isbn))
+            .cloned()
+            .ok_or(MetadataError::Status(404))
+    }
+}
+
+/// Sends a GET request with the given client, used by all providers outside of tests.
+fn http_get(client: &Client, url: &str) -> Result<Response> {
+    let network = |e: reqwest::Error| MetadataError::Network(e.to_string());
+    let resp = client.get(url).send().map_err(network)?;
+
+    Ok(Response {
+        status: resp.status().as_u16(),
+        retry_after: resp
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_owned),
+        body: resp.text().map_err(network)?,
+    })
+}
+
+/// Parses a free text publish date as returned by OpenLibrary, e.g. `2008`,
+/// `March 2008` or `March 1, 2008`. A missing month or day defaults to the first.
+pub fn parse_publish_date(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim().trim_end_matches('.');
+    let date = ["%Y-%m-%d", "%B %d, %Y", "%d %B %Y"]
+        .iter()
+        .find_map(|f| NaiveDate::parse_from_str(value, f).ok())
+        .or_else(|| NaiveDate::parse_from_str(&format!("1 {}", value), "%d %B %Y").ok())
+        .or_else(|| {
+            value
+                .parse::<i32>()
+                .ok()
+                .and_then(|y| NaiveDate::from_ymd_opt(y, 1, 1))
+        })?;
+
+    Some(Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::{TimeZone, Utc};
+
+    use super::{
+        parse_publish_date, parse_retry_after, with_backoff, BookMetadata, MetadataError,
+        MetadataProvider, OpenLibraryProvider, Response, StaticProvider,
+    };
+    use crate::books::models::{Book, BookError};
+
+    const EDITION: &str = r#"{
+        "title": "Millennium",
+        "subtitle": "Verschwörung",
+        "publishers": ["Heyne Verlag", "Random House"],
+        "publish_date": "March 1, 2015",
+        "covers": [-1, 8231856],
+        "authors": [{"key": "/authors/OL7445948A"}],
+        "number_of_pages": 608
+    }"#;
+
+    /// Returns a provider which answers all known paths with a fixed payload.
+    fn stub_provider(responses: Vec<(&'static str, u16, &'static str)>) -> OpenLibraryProvider {
+        OpenLibraryProvider::with_fetch(
+            "http://stub/",
+            Box::new(move |url| {
+                responses
+                    .iter()
+                    .find(|(path, ..)| url == format!("http://stub{}", path))
+                    .map(|(_, status, body)| Response {
+                        status: *status,
+                        retry_after: None,
+                        body: body.to_string(),
+                    })
+                    .ok_or_else(|| MetadataError::Network(format!("unexpected url {}", url)))
+            }),
+        )
+    }
+
+    #[test]
+    fn rate_limited_response() {
+        let resp = Response {
+            status: 429,
+            retry_after: Some("120".to_owned()),
+            body: "Too Many Requests".to_owned(),
+        };
+
+        assert_eq!(
+            resp.check_status().unwrap_err(),
+            MetadataError::RateLimited {
+                retry_after: Some(Duration::from_secs(120))
+            }
+        );
+
+        let resp = Response {
+            status: 429,
+            ..Default::default()
+        };
+        assert_eq!(
+            resp.check_status().unwrap_err(),
+            MetadataError::RateLimited { retry_after: None }
+        );
+
+        let resp = Response {
+            status: 503,
+            ..Default::default()
+        };
+        assert_eq!(resp.check_status().unwrap_err(), MetadataError::Status(503));
+    }
+
+    #[test]
+    fn retry_after_http_date() {
+        let now = Utc.with_ymd_and_hms(2015, 10, 21, 7, 27, 30).unwrap();
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT", now),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn backoff_honors_rate_limit() {
+        let mut calls = 0;
+        let res = with_backoff(3, || {
+            calls += 1;
+            match calls {
+                1 | 2 => Err(MetadataError::RateLimited {
+                    retry_after: Some(Duration::ZERO),
+                }),
+                _ => Ok(calls),
+            }
+        });
+        assert_eq!(res, Ok(3));
+
+        let mut calls = 0;
+        let res: super::Result<()> = with_backoff(1, || {
+            calls += 1;
+            Err(MetadataError::RateLimited {
+                retry_after: Some(Duration::ZERO),
+            })
+        });
+        assert_eq!(calls, 2);
+        assert!(matches!(res, Err(MetadataError::RateLimited { .. })));
+    }
+
+    #[test]
+    fn open_library_maps_edition() {
+        let provider = stub_provider(vec![
+            ("/isbn/9783453270855.json", 200, EDITION),
+            (
+                "/authors/OL7445948A.json",
+                200,
+                r#"{"name": "David Lagercrantz"}"#,
+            ),
+        ]);
+
+        let metadata = provider.by_isbn("978-3-453-27085-5").unwrap();
+        assert_eq!(
+            metadata,
+            BookMetadata {
+                authors: vec!["David Lagercrantz".to_owned()],
+                isbn: "9783453270855".to_owned(),
+                title: "Millennium".to_owned(),
+                sub_title: Some("Verschwörung".to_owned()),
+                publisher: Some("Heyne Verlag".to_owned()),
+                publish_date: Some(Utc.with_ymd_and_hms(2015, 3, 1, 0, 0, 0).unwrap()),
+                cover_url: Some("https://covers.openlibrary.org/b/id/8231856-L.jpg".to_owned()),
+            }
+        );
+    }
+
+    #[test]
+    fn open_library_errors() {
+        let provider = stub_provider(vec![
+            ("/isbn/1.json", 404, "Not Found"),
+            ("/isbn/2.json", 200, "<html></html>"),
+            ("/isbn/3.json", 200, EDITION),
+        ]);
+
+        let err: BookError = provider.by_isbn("1").unwrap_err().into();
+        assert!(matches!(err, BookError::NotFound));
+
+        let err = provider.by_isbn("2").unwrap_err();
+        assert!(matches!(err, MetadataError::InvalidResponse(_)));
+
+        // The author is unknown to the stub, which fails like an unreachable provider.
+        let err: BookError = provider.by_isbn("3").unwrap_err().into();
+        assert!(matches!(err, BookError::MetadataLookup(_)));
+    }
+
+    #[test]
+    fn publish_date_formats() {
+        let date = |y, m, d| Some(Utc.with_ymd_and_hms(y, m, d, 0, 0, 0).unwrap());
+        assert_eq!(parse_publish_date("2008"), date(2008, 1, 1));
+        assert_eq!(parse_publish_date("March 2008"), date(2008, 3, 1));
+        assert_eq!(parse_publish_date("Mar 12, 2008"), date(2008, 3, 12));
+        assert_eq!(parse_publish_date("12 March 2008."), date(2008, 3, 12));
+        assert_eq!(parse_publish_date("2008-03-12"), date(2008, 3, 12));
+        assert_eq!(parse_publish_date("unknown"), None);
+    }
+
+    #[test]
+    fn static_provider_as_trait_object() {
+        let provider: Box<dyn MetadataProvider + Send + Sync> =
+            Box::new(StaticProvider::new([BookMetadata {
+                isbn: "9783550087653".to_owned(),
+                title: "Der Gotteswahn".to_owned(),
+                authors: vec!["Richard Dawkins".to_owned()],
+                ..Default::default()
+            }]));
+
+        let book: Book = provider.by_isbn("978-3-550-08765-3").unwrap().into();
+        assert_eq!(book.title, "Der Gotteswahn");
+        assert_eq!(book.authors, vec!["Richard Dawkins"]);
+        assert_eq!(book.isbn, "9783550087653");
+        assert_eq!(book.id, 0);
+
+        let err: BookError = provider.by_isbn("9783453270855").unwrap_err().into();
+        assert!(matches!(err, BookError::NotFound));
+    }
+}
@@ -7,10 +7,14 @@
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::marker::PhantomData;
+use std::path::Path;
 
-use std::{error::Error, fmt::Display};
+use thiserror::Error;
 
 /// A simple macro to create an array of SortDescriptors.
 /// Educational purpose.
@@ -29,45 +33,42 @@ macro_rules! sort_desc {
 }
 
 /// All known error for the books module.
-/// Probably use crate `thiserror` as soon as familiar
-/// enough with error handling and implementation.
 #[non_exhaustive]
-#[derive(Debug)]
+#[derive(Debug, Error)]
 pub enum BookError {
     /// A generic error of the books modul.
+    #[error("{0}")]
     Generic(String),
     /// Error is returned if no item with given id were found.
+    #[error("Did not find item with given id")]
     NotFound,
     /// An error returned from the underlying database runtime.
-    DBError(Box<dyn std::error::Error>),
+    #[error("Database error: {0}")]
+    DBError(Box<dyn std::error::Error + Send + Sync>),
     /// An error if authors is empty.
+    #[error("Book requires at least one author")]
     EmptyAuthors,
-    InvalidBook {
-        field: String,
-        reason: String,
-    },
+    #[error("invalid field: {field}, reason: {reason}")]
+    InvalidBook { field: String, reason: String },
+    /// Another book with the given ISBN exists.
+    #[error("A book with ISBN {0} already exists")]
+    DuplicateIsbn(String),
+    /// The file is not a bookshelf database, e.g. another SQLite database.
+    #[error("Not a bookshelf database: {0}")]
+    IncompatibleDatabase(String),
+    /// The book was changed since it was loaded, see [BookDB::update_book].
+    #[error("Book was changed in the meantime, please reload it")]
+    Conflict,
+    /// Another connection kept the database locked longer than the busy timeout.
+    #[error("Database is locked by another connection, please try again")]
+    Busy,
+    /// Looking up book metadata from an external provider failed.
+    #[error("Metadata lookup failed: {0}")]
+    MetadataLookup(String),
 }
 
-impl Error for BookError {}
-unsafe impl Send for BookError {}
-unsafe impl Sync for BookError {}
-
 pub type Result<T, E = BookError> = core::result::Result<T, E>;
 
-impl Display for BookError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            BookError::Generic(s) => s.fmt(f),
-            BookError::NotFound => write!(f, "Did not find item with given id"),
-            BookError::DBError(e) => write!(f, "Database error: {}", *e),
-            BookError::EmptyAuthors => write!(f, "Book requires at least one author"),
-            BookError::InvalidBook { field, reason } => {
-                write!(f, "invalid field: {}, reason: {}", field, reason)
-            }
-        }
-    }
-}
-
 /// SortOrder defines the direction of a query.
 #[derive(Debug, PartialEq, Deserialize, Serialize)]
 pub enum SortOrder {
@@ -90,21 +91,310 @@ impl From<&str> for SortOrder {
     }
 }
 
+/// Reading progress of a book.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadingStatus {
+    #[default]
+    Unread,
+    Reading,
+    #[serde(alias = "finished")]
+    Read,
+}
+
+impl ReadingStatus {
+    pub const ALL: [ReadingStatus; 3] = [
+        ReadingStatus::Unread,
+        ReadingStatus::Reading,
+        ReadingStatus::Read,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReadingStatus::Unread => "unread",
+            ReadingStatus::Reading => "reading",
+            ReadingStatus::Read => "read",
+        }
+    }
+
+    /// Parses a stored status, unknown values are treated as unread.
+    /// `finished` is accepted for [ReadingStatus::Read].
+    pub fn from_str_or_default(value: &str) -> ReadingStatus {
+        if value == "finished" {
+            return ReadingStatus::Read;
+        }
+
+        ReadingStatus::ALL
+            .into_iter()
+            .find(|s| s.as_str() == value)
+            .unwrap_or_default()
+    }
+}
+
+/// Journal mode of a database, see <https://sqlite.org/pragma.html#pragma_journal_mode>.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JournalMode {
+    /// Write-ahead log, requires `-wal` and `-shm` files next to the database.
+    #[default]
+    Wal,
+    /// Rollback journal which is deleted after each transaction, no files are left behind.
+    Delete,
+    /// Rollback journal which is truncated instead of deleted.
+    Truncate,
+}
+
+impl JournalMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JournalMode::Wal => "wal",
+            JournalMode::Delete => "delete",
+            JournalMode::Truncate => "truncate",
+        }
+    }
+}
+
+/// How often SQLite syncs to disk, see <https://sqlite.org/pragma.html#pragma_synchronous>.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Synchronous {
+    /// Safe in WAL mode, a power loss may only roll back the last transactions.
+    #[default]
+    Normal,
+    /// Syncs on every commit, e.g. for network drives.
+    Full,
+    /// Like `Full`, also syncs the directory after a rollback journal was deleted.
+    Extra,
+}
+
+impl Synchronous {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Synchronous::Normal => "normal",
+            Synchronous::Full => "full",
+            Synchronous::Extra => "extra",
+        }
+    }
+}
+
+/// Default time in milliseconds a connection waits for a lock of another connection.
+pub const DEFAULT_BUSY_TIMEOUT_MS: u32 = 5000;
+
+/// Pragmas applied to writable connections. The values are enums, so no arbitrary
+/// pragma value ends up in a query.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DbTuning {
+    pub journal_mode: JournalMode,
+    pub synchronous: Synchronous,
+    /// Milliseconds to wait for a lock of another connection, fails immediately if not set.
+    pub busy_timeout: Option<u32>,
+}
+
+impl Default for DbTuning {
+    fn default() -> Self {
+        Self {
+            journal_mode: JournalMode::default(),
+            synchronous: Synchronous::default(),
+            busy_timeout: Some(DEFAULT_BUSY_TIMEOUT_MS),
+        }
+    }
+}
+
+/// Defines how author names and tags are ordered.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NameCollation {
+    /// Byte order, "Z" sorts before "a" and "ä".
+    #[default]
+    Binary,
+    /// Case insensitive for ASCII letters only, like SQLite's `NOCASE`.
+    NoCase,
+    /// Case and accent insensitive, "ä" sorts with "a".
+    Unicode,
+}
+
+impl NameCollation {
+    /// Compares two names, names which are equal under the collation are
+    /// ordered binary, so the order is always total.
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        let ord = match self {
+            NameCollation::Binary => Ordering::Equal,
+            NameCollation::NoCase => a
+                .bytes()
+                .map(|c| c.to_ascii_lowercase())
+                .cmp(b.bytes().map(|c| c.to_ascii_lowercase())),
+            NameCollation::Unicode => fold_name(a).cmp(fold_name(b)),
+        };
+        ord.then_with(|| a.cmp(b))
+    }
+}
+
+/// Lowercases the name and replaces common latin letters with diacritics by their base letters.
+fn fold_name(name: &str) -> impl Iterator<Item = char> + '_ {
+    name.chars().flat_map(char::to_lowercase).flat_map(|c| {
+        let base: &str = match c {
+            'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' => "a",
+            'æ' => "ae",
+            'ç' => "c",
+            'è' | 'é' | 'ê' | 'ë' => "e",
+            'ì' | 'í' | 'î' | 'ï' => "i",
+            'ñ' => "n",
+            'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' => "o",
+            'œ' => "oe",
+            'ß' => "ss",
+            'ù' | 'ú' | 'û' | 'ü' => "u",
+            'ý' | 'ÿ' => "y",
+            _ => "",
+        };
+        match base {
+            "" => vec![c],
+            b => b.chars().collect(),
+        }
+    })
+}
+
+/// Direction to walk through a sorted list of books.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+/// Columns which take part in the general text search.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchColumn {
+    Title,
+    SubTitle,
+    Publisher,
+    Isbn,
+    Description,
+    Authors,
+    Tags,
+    Notes,
+}
+
+impl SearchColumn {
+    pub const ALL: [SearchColumn; 8] = [
+        SearchColumn::Title,
+        SearchColumn::SubTitle,
+        SearchColumn::Publisher,
+        SearchColumn::Isbn,
+        SearchColumn::Description,
+        SearchColumn::Authors,
+        SearchColumn::Tags,
+        SearchColumn::Notes,
+    ];
+}
+
 /// SortDescriptor describes a column and which sort order to use.
 #[derive(Debug, Deserialize, Serialize)]
 pub struct SortDescriptor(pub String, pub SortOrder);
 
+/// Position of the last item of a page, used for keyset (cursor) pagination.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Cursor {
+    /// Value of the sort column of the last item, `None` if sorted by id.
+    pub key: Option<CursorKey>,
+    pub id: i64,
+}
+
+/// Value of a sort column within a [Cursor].
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum CursorKey {
+    Integer(i64),
+    Text(String),
+}
+
 /// StoreResult a generic store result.
 #[derive(Debug, Deserialize, Serialize, Default)]
 pub struct StoreResult<T> {
     pub total: u64,
+    /// Number of skipped pages, see [SearchConfig::use_skip_page].
     pub skipped: u64,
     pub items: Vec<T>,
+    /// Cursor to fetch the next page with, only set if the page is full
+    /// and the query supports keyset pagination.
+    #[serde(default)]
+    pub next_cursor: Option<Cursor>,
+    /// Whether more items follow the current page.
+    #[serde(default)]
+    pub has_more: bool,
+}
+
+impl<T> StoreResult<T> {
+    /// Returns the number of pages with `take` items needed for all items, 0 if `take` is 0.
+    #[allow(dead_code)]
+    pub fn page_count(&self, take: u64) -> u64 {
+        match take {
+            0 => 0,
+            t => self.total.div_ceil(t),
+        }
+    }
+}
+
+/// Figures of all books of a library, books in the trash are not counted.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct LibraryStats {
+    pub total_books: u64,
+    pub books_by_lang: HashMap<String, u64>,
+    /// Most used tags, most books first.
+    pub top_tags: Vec<(String, u64)>,
+    /// Authors with the most books, most books first.
+    pub top_authors: Vec<(String, u64)>,
+    /// Number of books per decade of the publish date, e.g. `1990`. Books without
+    /// publish date are not counted.
+    pub books_by_decade: Vec<(i32, u64)>,
 }
 
 pub struct ConfigNew;
 pub struct ConfigInitialized;
 
+/// A reusable set of filters, e.g. of a saved search. All set filters must match.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Filters {
+    /// Books carrying at least one of these tags.
+    pub tags_any: Option<Vec<String>>,
+    /// Books carrying all of these tags.
+    pub tags_all: Option<Vec<String>>,
+    /// Books of this author, the name must match exactly.
+    pub author: Option<String>,
+    /// Books with this reading status.
+    pub reading_status: Option<ReadingStatus>,
+    /// Books in this language.
+    pub lang: Option<String>,
+    /// Books published at or after this date.
+    pub published_from: Option<DateTime<Utc>>,
+    /// Books published at or before this date.
+    pub published_to: Option<DateTime<Utc>>,
+    /// Owned books if true, books on the wishlist if false.
+    pub owned: Option<bool>,
+    /// Books in this format, e.g. `EPUB`.
+    pub format: Option<String>,
+    /// Books of this series, the name must match exactly.
+    pub series: Option<String>,
+    /// Books acquired at or after this date.
+    pub acquired_from: Option<DateTime<Utc>>,
+    /// Books acquired at or before this date.
+    pub acquired_to: Option<DateTime<Utc>>,
+}
+
+impl Filters {
+    /// Removes filters without effect, like empty tag lists or a blank author.
+    fn normalize(mut self) -> Self {
+        self.tags_any = self.tags_any.filter(|t| !t.is_empty());
+        self.tags_all = self.tags_all.filter(|t| !t.is_empty());
+        self.author = self.author.filter(|a| !a.trim().is_empty());
+        self.lang = self.lang.filter(|l| !l.trim().is_empty());
+        self.format = self.format.filter(|f| !f.trim().is_empty());
+        self.series = self.series.filter(|s| !s.trim().is_empty());
+        self
+    }
+}
+
 /**
 Configuration for searching in the BookDB.
 
@@ -119,6 +409,12 @@ pub struct SearchConfig<State = ConfigNew> {
     sort: Option<Vec<SortDescriptor>>,
     take: Option<u64>,
     text: String,
+    after: Option<Cursor>,
+    #[serde(flatten)]
+    filters: Filters,
+    search_columns: Option<Vec<SearchColumn>>,
+    #[serde(default)]
+    prefix: bool,
 }
 
 impl<State> fmt::Debug for SearchConfig<State> {
@@ -129,6 +425,10 @@ impl<State> fmt::Debug for SearchConfig<State> {
             .field("sort", &self.sort)
             .field("take", &self.take)
             .field("text", &self.text)
+            .field("after", &self.after)
+            .field("filters", &self.filters)
+            .field("search_columns", &self.search_columns)
+            .field("prefix", &self.prefix)
             .finish()
     }
 }
@@ -162,6 +462,10 @@ impl SearchConfig<ConfigNew> {
             text: txt.to_owned(),
             skip: None,
             sort: None,
+            after: None,
+            filters: Filters::default(),
+            search_columns: None,
+            prefix: false,
         }
     }
 
@@ -171,6 +475,10 @@ impl SearchConfig<ConfigNew> {
             take,
             sort,
             text,
+            after,
+            filters,
+            search_columns,
+            prefix,
             state: _,
         } = self;
         SearchConfig {
@@ -178,6 +486,10 @@ impl SearchConfig<ConfigNew> {
             take,
             sort,
             text,
+            after,
+            filters: filters.normalize(),
+            search_columns,
+            prefix,
             state: PhantomData::<ConfigInitialized>,
         }
     }
@@ -205,6 +517,139 @@ impl SearchConfig<ConfigNew> {
         self.sort = Some(sort);
         self
     }
+
+    /// Continue after the given cursor (keyset pagination) instead of skipping
+    /// pages. Requires sorting by at most one column without NULL values.
+    #[allow(dead_code)]
+    pub fn use_cursor(mut self, cursor: Cursor) -> Self {
+        self.after = Some(cursor);
+        self
+    }
+
+    /// Only include books carrying at least one of the given tags.
+    #[allow(dead_code)]
+    pub fn use_tags_any(mut self, tags: Vec<String>) -> Self {
+        self.filters.tags_any = Some(tags);
+        self
+    }
+
+    /// Only include books carrying all of the given tags.
+    #[allow(dead_code)]
+    pub fn use_tags_all(mut self, tags: Vec<String>) -> Self {
+        self.filters.tags_all = Some(tags);
+        self
+    }
+
+    /// Only include books of the given author, the name must match exactly.
+    #[allow(dead_code)]
+    pub fn use_author(mut self, author: &str) -> Self {
+        self.filters.author = Some(author.to_owned());
+        self
+    }
+
+    /// Only include books in the given language.
+    #[allow(dead_code)]
+    pub fn use_lang(mut self, lang: &str) -> Self {
+        self.filters.lang = Some(lang.to_owned());
+        self
+    }
+
+    /// Only include books carrying the given tag, multiple tags must all match.
+    #[allow(dead_code)]
+    pub fn use_tag(mut self, tag: &str) -> Self {
+        self.filters
+            .tags_all
+            .get_or_insert_with(Vec::new)
+            .push(tag.to_owned());
+        self
+    }
+
+    /// Only include books published between `start` and `end`, both inclusive.
+    /// Books without a publish date are excluded.
+    #[allow(dead_code)]
+    pub fn use_publish_between(mut self, start: DateTime<Utc>, end: DateTime<Utc>) -> Self {
+        self.filters.published_from = Some(start);
+        self.filters.published_to = Some(end);
+        self
+    }
+
+    /// Only include books acquired between `start` and `end`, both inclusive.
+    /// Books without an acquired date are excluded.
+    #[allow(dead_code)]
+    pub fn use_acquired_between(mut self, start: DateTime<Utc>, end: DateTime<Utc>) -> Self {
+        self.filters.acquired_from = Some(start);
+        self.filters.acquired_to = Some(end);
+        self
+    }
+
+    /// Only include books with the given reading status.
+    #[allow(dead_code)]
+    pub fn use_reading_status(mut self, status: ReadingStatus) -> Self {
+        self.filters.reading_status = Some(status);
+        self
+    }
+
+    /// Only include books of the given series, sort by `series_index` to get them in order.
+    #[allow(dead_code)]
+    pub fn use_series(mut self, series: &str) -> Self {
+        self.filters.series = Some(series.to_owned());
+        self
+    }
+
+    /// Only include owned books if true, only books on the wishlist if false.
+    #[allow(dead_code)]
+    pub fn use_owned(mut self, owned: bool) -> Self {
+        self.filters.owned = Some(owned);
+        self
+    }
+
+    /// Applies all filters of the bundle, filters which are not set in the
+    /// bundle keep their current value.
+    #[allow(dead_code)]
+    pub fn use_filters(mut self, filters: Filters) -> Self {
+        let Filters {
+            tags_any,
+            tags_all,
+            author,
+            reading_status,
+            lang,
+            published_from,
+            published_to,
+            owned,
+            format,
+            series,
+            acquired_from,
+            acquired_to,
+        } = filters;
+        self.filters.tags_any = tags_any.or(self.filters.tags_any);
+        self.filters.tags_all = tags_all.or(self.filters.tags_all);
+        self.filters.author = author.or(self.filters.author);
+        self.filters.reading_status = reading_status.or(self.filters.reading_status);
+        self.filters.lang = lang.or(self.filters.lang);
+        self.filters.published_from = published_from.or(self.filters.published_from);
+        self.filters.published_to = published_to.or(self.filters.published_to);
+        self.filters.owned = owned.or(self.filters.owned);
+        self.filters.format = format.or(self.filters.format);
+        self.filters.series = series.or(self.filters.series);
+        self.filters.acquired_from = acquired_from.or(self.filters.acquired_from);
+        self.filters.acquired_to = acquired_to.or(self.filters.acquired_to);
+        self
+    }
+
+    /// Restricts the text search to the given columns, all columns are searched by default.
+    #[allow(dead_code)]
+    pub fn use_search_columns(mut self, columns: Vec<SearchColumn>) -> Self {
+        self.search_columns = Some(columns);
+        self
+    }
+
+    /// Lists tags and authors starting with the search text before the ones which only
+    /// contain it, e.g. for type-ahead. Only applies if no sort order is given.
+    #[allow(dead_code)]
+    pub fn use_prefix(mut self, prefix: bool) -> Self {
+        self.prefix = prefix;
+        self
+    }
 }
 
 impl SearchConfig<ConfigInitialized> {
@@ -223,6 +668,85 @@ impl SearchConfig<ConfigInitialized> {
     pub fn get_text(&self) -> &str {
         &self.text
     }
+
+    pub fn get_cursor(&self) -> Option<&Cursor> {
+        self.after.as_ref()
+    }
+
+    pub fn get_tags_any(&self) -> Option<&Vec<String>> {
+        self.filters.tags_any.as_ref()
+    }
+
+    pub fn get_tags_all(&self) -> Option<&Vec<String>> {
+        self.filters.tags_all.as_ref()
+    }
+
+    pub fn get_author(&self) -> Option<&str> {
+        self.filters.author.as_deref()
+    }
+
+    pub fn get_reading_status(&self) -> Option<ReadingStatus> {
+        self.filters.reading_status
+    }
+
+    pub fn get_lang(&self) -> Option<&str> {
+        self.filters.lang.as_deref()
+    }
+
+    pub fn get_owned(&self) -> Option<bool> {
+        self.filters.owned
+    }
+
+    pub fn get_format(&self) -> Option<&str> {
+        self.filters.format.as_deref()
+    }
+
+    pub fn get_series(&self) -> Option<&str> {
+        self.filters.series.as_deref()
+    }
+
+    /// Returns the range of publish dates, open ends are `None`.
+    pub fn get_publish_range(&self) -> (Option<DateTime<Utc>>, Option<DateTime<Utc>>) {
+        (self.filters.published_from, self.filters.published_to)
+    }
+
+    /// Returns the range of acquired dates, open ends are `None`.
+    pub fn get_acquired_range(&self) -> (Option<DateTime<Utc>>, Option<DateTime<Utc>>) {
+        (self.filters.acquired_from, self.filters.acquired_to)
+    }
+
+    #[allow(dead_code)]
+    pub fn get_filters(&self) -> &Filters {
+        &self.filters
+    }
+
+    /// Returns the columns used by the text search, all if none were specified.
+    pub fn get_search_columns(&self) -> &[SearchColumn] {
+        match &self.search_columns {
+            Some(c) if !c.is_empty() => c,
+            _ => &SearchColumn::ALL,
+        }
+    }
+
+    pub fn get_prefix(&self) -> bool {
+        self.prefix
+    }
+
+    /// Returns a config with the same search text and filters, which fetches `take` books
+    /// ordered by id after the given cursor. Used to walk through all matching books.
+    pub fn page_by_id(&self, take: u64, after: Option<Cursor>) -> SearchConfig<ConfigInitialized> {
+        SearchConfig {
+            state: PhantomData::<ConfigInitialized>,
+            skip: None,
+            sort: Some(vec![SortDescriptor("id".to_owned(), SortOrder::Asc)]),
+            take: Some(take),
+            text: self.text.clone(),
+            after,
+            filters: self.filters.clone(),
+            search_columns: self.search_columns.clone(),
+            prefix: self.prefix,
+        }
+    }
 }
 
 /// BookDB provides functions to store and retrieve books from the underlying data store.
@@ -230,23 +754,197 @@ impl SearchConfig<ConfigInitialized> {
 /// I might use a type alias like `type Result<T, E = BookError> = core::Result<T, E>;`.
 pub trait BookDB: Send {
     fn add_book(&mut self, book: &mut Book) -> Result<()>;
+    /// Adds all books within one transaction, if a single book fails none is added.
+    fn add_books(&mut self, books: &mut [Book]) -> Result<()>;
     fn get_book(&mut self, id: i64) -> Result<Book>;
+    /// Returns the book with the given ISBN, which is normalized before the lookup. Books
+    /// in the trash are not found.
+    fn get_book_by_isbn(&mut self, isbn: &str) -> Result<Book>;
+    /// Updates the book and reloads it. Fails with [BookError::Conflict] if the stored
+    /// book was updated after the passed one was loaded, its `updated` must match.
     fn update_book(&mut self, book: &mut Book) -> Result<()>;
     fn delete_book(&mut self, book: &Book) -> Result<()>;
     fn delete_book_by_id(&mut self, id: i64) -> Result<()>;
+    /// Deletes all books with the given ids permanently within one transaction. Returns
+    /// the number of deleted books, unknown ids are ignored.
+    fn delete_books_by_ids(&mut self, ids: &[i64]) -> Result<u64>;
+    /// Moves the book with given id to the trash, books in the trash are excluded from
+    /// all searches and counts but can be restored.
+    fn trash_book(&mut self, id: i64) -> Result<()>;
+    /// Moves all books with the given ids to the trash. Returns the number of moved
+    /// books, unknown ids and books already in the trash are ignored.
+    fn trash_books(&mut self, ids: &[i64]) -> Result<u64>;
+    /// Restores the book with given id from the trash.
+    fn restore_book(&mut self, id: i64) -> Result<()>;
+    /// Returns the books in the trash, most recently deleted first.
+    fn list_trash(&mut self) -> Result<Vec<Book>>;
+    /// Permanently deletes books which are in the trash for at least `older_than_days`
+    /// days. Returns the number of deleted books. Duplicates moved to the trash when
+    /// unique ISBNs were introduced are kept until they are restored or deleted by id.
+    fn purge_trash(&mut self, older_than_days: u32) -> Result<u64>;
     fn fetch_books(&mut self, search: SearchConfig<ConfigInitialized>)
         -> Result<StoreResult<Book>>;
+    /// Counts the books matching the search, pagination is ignored.
+    fn count_books(&mut self, search: SearchConfig<ConfigInitialized>) -> Result<u64>;
+    /// Returns up to `count` books next to the book with given id in the given direction,
+    /// under the sort order and filters of the search config. Books are ordered nearest first.
+    fn get_neighbors(
+        &mut self,
+        id: i64,
+        search: SearchConfig<ConfigInitialized>,
+        direction: Direction,
+        count: u64,
+    ) -> Result<Vec<Book>>;
 
     fn get_tags(&mut self, search: SearchConfig<ConfigInitialized>) -> Result<StoreResult<String>>;
     fn get_authors(
         &mut self,
         search: SearchConfig<ConfigInitialized>,
     ) -> Result<StoreResult<String>>;
+
+    /// Returns all distinct cover image references of the stored books.
+    fn get_cover_references(&mut self) -> Result<Vec<String>>;
+    /// Sets the order of authors and tags within a book.
+    fn set_name_collation(&mut self, collation: NameCollation) -> Result<()>;
+    /// Returns the ids of all books whose stored order of authors or tags differs from
+    /// the current collation. Should always be empty.
+    fn check_name_order(&mut self) -> Result<Vec<i64>>;
+    /// Sets the dates the book with given id was started and finished reading. A finish date
+    /// marks the book as read, a start date marks an unread book as reading.
+    fn log_reading(
+        &mut self,
+        id: i64,
+        started: Option<DateTime<Utc>>,
+        finished: Option<DateTime<Utc>>,
+    ) -> Result<Book>;
+    /// Returns all books finished within the given year, ordered by the finish date.
+    fn finished_in_year(&mut self, year: i32) -> Result<Vec<Book>>;
+    /// Merges the books `merge_ids` into the book `keep_id` and deletes them within one
+    /// transaction. Authors and tags are united, empty fields of the kept book are filled
+    /// in the given order. Returns the merged book.
+    fn merge_books(&mut self, keep_id: i64, merge_ids: &[i64]) -> Result<Book>;
+    /// Sets or clears the cover of the book with given id, other fields are not
+    /// written. Returns the previous cover.
+    fn set_cover(&mut self, id: i64, cover: Option<String>) -> Result<Option<String>>;
+    /// Returns the tags of all given books in one query, books without tags map to an
+    /// empty list. Unknown ids are not part of the result.
+    fn tags_for_books(&mut self, ids: &[i64]) -> Result<HashMap<i64, Vec<String>>>;
+    /// Renames the tag `from` to `to` in all books, books which already have `to` keep
+    /// it once. Returns the number of affected books.
+    fn rename_tag(&mut self, from: &str, to: &str) -> Result<u64>;
+    /// Removes the tag from all books. Returns the number of affected books.
+    fn delete_tag(&mut self, tag: &str) -> Result<u64>;
+    /// Renames the author `from` to `to` in all books, a book never ends up with the
+    /// same author twice. Returns the number of affected books.
+    fn rename_author(&mut self, from: &str, to: &str) -> Result<u64>;
+    /// Returns the earliest and latest publish year of all books, `None` if no book
+    /// has a publish date.
+    fn publish_year_range(&mut self) -> Result<Option<(i32, i32)>>;
+    /// Returns groups of books sharing the same trimmed, case-folded title. Only titles
+    /// of two or more books are returned, ordered by title.
+    fn title_duplicates(&mut self) -> Result<Vec<(String, Vec<Book>)>>;
+    /// Returns groups of probable duplicates, books are grouped by their normalized ISBN
+    /// or, if they have none, by case-folded title and first author. Groups are ordered
+    /// by their first added book.
+    fn find_duplicates(&mut self) -> Result<Vec<Vec<Book>>>;
+    /// Returns the number of books per reading status, statuses without books are included.
+    fn status_counts(&mut self) -> Result<Vec<(ReadingStatus, u64)>>;
+    /// Returns figures of the whole library for the statistics dashboard.
+    fn statistics(&mut self) -> Result<LibraryStats>;
+    /// Sets missing or invalid `created` and `updated` timestamps to the current time.
+    /// Returns the number of repaired timestamps.
+    fn repair_timestamps(&mut self) -> Result<u64>;
+
+    /// Returns the number of WAL pages after which a checkpoint is run automatically.
+    fn get_wal_autocheckpoint(&mut self) -> Result<i64>;
+    /// Sets the number of WAL pages after which a checkpoint is run automatically,
+    /// zero disables automatic checkpoints.
+    fn set_wal_autocheckpoint(&mut self, pages: i64) -> Result<()>;
+    /// Reclaims free pages if the database is larger than `threshold_bytes` and a
+    /// large fraction of its pages is unused. Returns true if a vacuum was run,
+    /// read-only databases are always skipped.
+    fn vacuum_if_needed(&mut self, threshold_bytes: u64) -> Result<bool>;
+    /// Checkpoints the WAL and rebuilds the database file, so it only takes the
+    /// space of its content.
+    fn compact(&mut self) -> Result<()>;
+    /// Deletes all books including trashed ones, their authors and tags are removed
+    /// along with them. Cover files are kept, see `purge_orphaned_covers`.
+    fn clear_all(&mut self) -> Result<()>;
+    /// Writes a consistent snapshot of the database to `dest`, including changes
+    /// which are not yet checkpointed from the WAL.
+    fn backup_to(&self, dest: &Path) -> Result<()>;
+    /// Returns true if the connection still answers queries and has no transaction
+    /// left open.
+    fn is_healthy(&self) -> bool;
+}
+
+/// Validates the given ISBN-10 or ISBN-13 by its check digit and returns it as ISBN-13
+/// without hyphens and spaces. An empty ISBN stays empty.
+pub fn normalize_isbn(isbn: &str) -> Result<String> {
+    let invalid = |reason: &str| BookError::InvalidBook {
+        field: "isbn".to_owned(),
+        reason: format!("{}: {}", reason, isbn),
+    };
+
+    let digits: Vec<char> = isbn
+        .chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+
+    // Each digit of an ISBN-10 is weighted by its distance to the end, X stands for 10
+    let digits = match digits.len() {
+        0 => return Ok(String::new()),
+        10 => {
+            let mut sum = 0;
+            for (i, c) in digits.iter().enumerate() {
+                let value = match c {
+                    'X' if i == 9 => 10,
+                    c => c.to_digit(10).ok_or_else(|| invalid("not a number"))?,
+                };
+                sum += value * (10 - i as u32);
+            }
+            if !sum.is_multiple_of(11) {
+                return Err(invalid("wrong check digit"));
+            }
+
+            let mut digits: Vec<u32> = "978"
+                .chars()
+                .chain(digits[..9].iter().copied())
+                .filter_map(|c| c.to_digit(10))
+                .collect();
+            digits.push((10 - isbn13_sum(&digits) % 10) % 10);
+            digits
+        }
+        13 => {
+            let digits = digits
+                .iter()
+                .map(|c| c.to_digit(10))
+                .collect::<Option<Vec<u32>>>()
+                .ok_or_else(|| invalid("not a number"))?;
+            if !isbn13_sum(&digits).is_multiple_of(10) {
+                return Err(invalid("wrong check digit"));
+            }
+            digits
+        }
+        _ => return Err(invalid("must have 10 or 13 digits")),
+    };
+
+    Ok(digits.iter().map(|d| d.to_string()).collect())
+}
+
+/// Sums the digits of an ISBN-13 weighted alternately by 1 and 3.
+fn isbn13_sum(digits: &[u32]) -> u32 {
+    digits
+        .iter()
+        .enumerate()
+        .map(|(i, d)| if i.is_multiple_of(2) { *d } else { d * 3 })
+        .sum()
 }
 
 /// A book representation for the bookshelf application.
 /// IDEA (learning purpose): Create a derive macro to create a validation function.
-#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Book {
     pub authors: Vec<String>,
     pub cover_img: Option<String>,
@@ -258,16 +956,146 @@ pub struct Book {
     pub sub_title: Option<String>,
     pub publisher: Option<String>,
     pub publish_date: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub reading_status: ReadingStatus,
+    #[serde(default)]
+    pub started_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Number of stars from 1 to 5.
+    #[serde(default)]
+    pub rating: Option<u8>,
+    /// False if the book is on the wishlist.
+    #[serde(default = "default_owned")]
+    pub owned: bool,
+    #[serde(default)]
+    pub page_count: Option<u32>,
+    /// Free text, e.g. `Hardcover` or `EPUB`.
+    #[serde(default)]
+    pub format: Option<String>,
+    /// Personal notes or review, unlike the description of the publisher.
+    #[serde(default)]
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub series: Option<String>,
+    /// Position within the series, e.g. `1.5` for a novella between the first two books.
+    #[serde(default)]
+    pub series_index: Option<f32>,
+    /// When the book was bought or received, unlike `created` which is set by the database.
+    #[serde(default)]
+    pub acquired_date: Option<DateTime<Utc>>,
 
     // Required for Database
     pub id: i64,
     pub created: DateTime<Utc>,
     pub updated: DateTime<Utc>,
+    /// Last modification of single fields, used to merge databases field by field.
+    /// Fields without an entry were last modified at `updated`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field_updated: Option<BTreeMap<String, DateTime<Utc>>>,
+}
+
+fn default_owned() -> bool {
+    true
+}
+
+impl Default for Book {
+    /// Returns an empty book, which is owned.
+    fn default() -> Self {
+        Self {
+            authors: Vec::new(),
+            cover_img: None,
+            description: None,
+            isbn: String::new(),
+            lang: String::new(),
+            tags: None,
+            title: String::new(),
+            sub_title: None,
+            publisher: None,
+            publish_date: None,
+            reading_status: ReadingStatus::default(),
+            started_at: None,
+            finished_at: None,
+            rating: None,
+            owned: default_owned(),
+            page_count: None,
+            format: None,
+            notes: None,
+            series: None,
+            series_index: None,
+            acquired_date: None,
+            id: 0,
+            created: DateTime::default(),
+            updated: DateTime::default(),
+            field_updated: None,
+        }
+    }
+}
+
+impl Book {
+    /// Returns the primary author, which is the first one of `authors`. Stored books list
+    /// their authors in the order of the configured [NameCollation].
+    #[allow(dead_code)]
+    pub fn first_author(&self) -> Option<&str> {
+        self.authors.first().map(|a| a.as_str())
+    }
+
+    /// Returns a stable SHA-256 hash (hex encoded) over the content of the book.
+    /// `id`, `created` and `updated` (including `field_updated`) are not part of the
+    /// content, the order of authors and tags doesn't matter.
+    pub fn content_hash(&self) -> String {
+        let json = serde_json::to_vec(&self.content()).expect("Serializing a book never fails");
+        format!("{:x}", Sha256::digest(json))
+    }
+
+    /// Returns true if both books have the same content, the same fields as in
+    /// [Book::content_hash] are ignored.
+    pub fn content_eq(&self, other: &Book) -> bool {
+        self.content() == other.content()
+    }
+
+    /// Returns a normalized copy without `id`, `created` and `updated`.
+    fn content(&self) -> Book {
+        Book {
+            id: 0,
+            created: DateTime::default(),
+            updated: DateTime::default(),
+            field_updated: None,
+            ..self.normalized()
+        }
+    }
+
+    /// Returns a copy with sorted authors and sorted, deduplicated tags. Empty tags are `None`.
+    pub fn normalized(&self) -> Book {
+        let mut book = self.clone();
+        book.authors.sort();
+        book.tags = book
+            .tags
+            .take()
+            .map(|mut t| {
+                t.sort();
+                t.dedup();
+                t
+            })
+            .filter(|t| !t.is_empty());
+        book
+    }
+
+    /// Returns the last modification of the given field.
+    pub fn field_updated(&self, field: &str) -> DateTime<Utc> {
+        self.field_updated
+            .as_ref()
+            .and_then(|m| m.get(field))
+            .copied()
+            .unwrap_or(self.updated)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::SortOrder;
+    use chrono::{TimeZone, Utc};
+
+    use super::{normalize_isbn, Book, BookError, NameCollation, SortOrder};
 
     // This test exists only to get familiar with Rust testing
     #[test]
@@ -281,4 +1109,71 @@ mod tests {
         assert_eq!(SortOrder::from("desc"), SortOrder::Desc);
         assert_eq!(SortOrder::from("dEsC"), SortOrder::Desc);
     }
+
+    #[test]
+    fn test_name_collation() {
+        let sorted = |c: NameCollation| {
+            let mut names = vec!["Zola", "ärger", "Abel", "Öhler", "abel", "Orwell"];
+            names.sort_by(|a, b| c.compare(a, b));
+            names
+        };
+
+        assert_eq!(
+            sorted(NameCollation::Binary),
+            vec!["Abel", "Orwell", "Zola", "abel", "Öhler", "ärger"]
+        );
+        assert_eq!(
+            sorted(NameCollation::NoCase),
+            vec!["Abel", "abel", "Orwell", "Zola", "Öhler", "ärger"]
+        );
+        assert_eq!(
+            sorted(NameCollation::Unicode),
+            vec!["Abel", "abel", "ärger", "Öhler", "Orwell", "Zola"]
+        );
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let book = Book {
+            authors: vec!["Jochen Schiller".to_owned()],
+            isbn: "9780321123817".to_owned(),
+            lang: "EN".to_owned(),
+            tags: Some(vec!["Wireless".to_owned(), "Communications".to_owned()]),
+            title: "Mobile Communications".to_owned(),
+            publish_date: Some(Utc.timestamp_opt(1062150913, 0).unwrap()),
+            id: 2,
+            ..Default::default()
+        };
+
+        let mut same = book.clone();
+        same.id = 42;
+        same.created = Utc::now();
+        same.updated = Utc::now();
+        same.tags = Some(vec!["Communications".to_owned(), "Wireless".to_owned()]);
+        assert_eq!(book.content_hash(), same.content_hash());
+        assert!(book.content_eq(&same));
+
+        same.title = "Mobile Communications 2".to_owned();
+        assert_ne!(book.content_hash(), same.content_hash());
+        assert!(!book.content_eq(&same));
+    }
+
+    #[test]
+    fn normalize_isbns() {
+        assert_eq!(
+            normalize_isbn("978-0-85705-642-9").unwrap(),
+            "9780857056429"
+        );
+        assert_eq!(normalize_isbn("0 321 12381 6").unwrap(), "9780321123817");
+        assert_eq!(normalize_isbn("3-550-08765-9").unwrap(), "9783550087653");
+        assert_eq!(normalize_isbn("080442957x").unwrap(), "9780804429573");
+        assert_eq!(normalize_isbn("").unwrap(), "");
+
+        for isbn in ["9780857056428", "355008765X", "123456789", "978085705642A"] {
+            assert!(matches!(
+                normalize_isbn(isbn),
+                Err(BookError::InvalidBook { field, .. }) if field == "isbn"
+            ));
+        }
+    }
 }
@@ -0,0 +1,117 @@
+// Copyright © 2023 Sandro Dallo
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+// This file contains the building blocks for long running bulk operations, which can be cancelled by the user.
+
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use log::{debug, warn};
+use serde::Serialize;
+
+/// A shared flag to cancel a running bulk operation. Cancellation is only
+/// checked between two items, so an item is either processed completely or not at all.
+#[derive(Debug, Default, Clone)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// Requests the cancellation of the running operation.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Clears a previous cancellation, must be called before an operation starts.
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::SeqCst)
+    }
+}
+
+/// Summary of a bulk operation, which contains the partial progress if it was cancelled.
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub struct BulkSummary {
+    pub total: u64,
+    pub completed: u64,
+    pub failed: u64,
+    pub cancelled: bool,
+}
+
+/// Applies `f` to all items until done or cancelled by the token. A failing item
+/// doesn't abort the operation, `f` must leave no partial changes behind on error
+/// (e.g. by using one transaction per item).
+pub fn run_bulk<T, E, F>(items: Vec<T>, token: &CancellationToken, mut f: F) -> BulkSummary
+where
+    E: Debug,
+    F: FnMut(T) -> Result<(), E>,
+{
+    let mut summary = BulkSummary {
+        total: items.len() as u64,
+        ..Default::default()
+    };
+
+    for item in items {
+        if token.is_cancelled() {
+            debug!(
+                "bulk operation cancelled after {} of {} items",
+                summary.completed + summary.failed,
+                summary.total
+            );
+            summary.cancelled = true;
+            break;
+        }
+
+        match f(item) {
+            Ok(_) => summary.completed += 1,
+            Err(e) => {
+                warn!("bulk operation failed on item: {:?}", e);
+                summary.failed += 1;
+            }
+        }
+    }
+
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run_bulk, BulkSummary, CancellationToken};
+
+    #[test]
+    fn cancel_stops_processing() {
+        let token = CancellationToken::default();
+        let mut processed = Vec::new();
+
+        let summary = run_bulk(vec![1, 2, 3, 4, 5], &token, |i| {
+            processed.push(i);
+            if i == 2 {
+                token.cancel();
+            }
+            if i == 1 {
+                return Err("invalid item");
+            }
+            Ok(())
+        });
+
+        assert_eq!(processed, vec![1, 2]);
+        assert_eq!(
+            summary,
+            BulkSummary {
+                total: 5,
+                completed: 1,
+                failed: 1,
+                cancelled: true,
+            }
+        );
+
+        token.reset();
+        let summary = run_bulk(vec![1, 2, 3, 4, 5], &token, |_| Ok::<(), ()>(()));
+        assert_eq!(summary.completed, 5);
+        assert!(!summary.cancelled);
+    }
+}
@@ -0,0 +1,175 @@
+// Copyright © 2023 Sandro Dallo
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+// This file contains the parser of the search text entered by the user.
+
+/// A search text split into terms. A book must match at least one term of every group
+/// and none of the excluded terms.
+///
+/// Supported syntax:
+/// - `wireless communications` both terms must match, in any column
+/// - `"wireless communications"` the phrase must match
+/// - `thriller OR suspense` one of the terms must match
+/// - `-thriller` the term must not match
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SearchQuery {
+    pub groups: Vec<Vec<String>>,
+    pub excluded: Vec<String>,
+}
+
+impl SearchQuery {
+    /// Parses the given search text. If the quotes are unbalanced, the text without
+    /// quotes is searched as a whole.
+    pub fn parse(text: &str) -> SearchQuery {
+        let mut query = SearchQuery::default();
+
+        if !text.matches('"').count().is_multiple_of(2) {
+            let term = text.replace('"', "");
+            if !term.trim().is_empty() {
+                query.groups.push(vec![term.trim().to_owned()]);
+            }
+            return query;
+        }
+
+        let mut pending_or = false;
+        for (term, quoted, negated) in tokenize(text) {
+            if !quoted && !negated {
+                match term.as_str() {
+                    "OR" => {
+                        pending_or = true;
+                        continue;
+                    }
+                    "AND" => continue,
+                    _ => {}
+                }
+            }
+
+            if term.is_empty() {
+                continue;
+            }
+
+            if negated {
+                query.excluded.push(term);
+            } else {
+                match query.groups.last_mut() {
+                    Some(group) if pending_or => group.push(term),
+                    _ => query.groups.push(vec![term]),
+                }
+            }
+            pending_or = false;
+        }
+
+        query
+    }
+
+    /// Returns true if the query neither requires nor excludes any term.
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty() && self.excluded.is_empty()
+    }
+}
+
+/// Splits the text at whitespaces, double quoted runs are kept together. Returns each
+/// token along with whether it was quoted and whether it was prefixed by `-`.
+fn tokenize(text: &str) -> Vec<(String, bool, bool)> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let mut negated = false;
+        if c == '-' {
+            chars.next();
+            match chars.peek() {
+                Some(n) if !n.is_whitespace() => negated = true,
+                _ => {
+                    tokens.push(("-".to_owned(), false, false));
+                    continue;
+                }
+            }
+        }
+
+        let mut term = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            for c in chars.by_ref() {
+                if c == '"' {
+                    break;
+                }
+                term.push(c);
+            }
+            tokens.push((term, true, negated));
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == '"' {
+                    break;
+                }
+                term.push(c);
+                chars.next();
+            }
+            tokens.push((term, false, negated));
+        }
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SearchQuery;
+
+    fn terms(t: &[&str]) -> Vec<String> {
+        t.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_search_text() {
+        let query = SearchQuery::parse(r#" wireless "mobile communications" -thriller "#);
+        assert_eq!(
+            query.groups,
+            vec![terms(&["wireless"]), terms(&["mobile communications"])]
+        );
+        assert_eq!(query.excluded, terms(&["thriller"]));
+
+        let query =
+            SearchQuery::parse("thriller OR suspense AND Lagercrantz -\"Die Verschwörung\"");
+        assert_eq!(
+            query.groups,
+            vec![terms(&["thriller", "suspense"]), terms(&["Lagercrantz"])]
+        );
+        assert_eq!(query.excluded, terms(&["Die Verschwörung"]));
+
+        // Keywords are only recognized unquoted and in upper case
+        let query = SearchQuery::parse(r#"war "OR" peace or - x"#);
+        assert_eq!(
+            query.groups,
+            vec![
+                terms(&["war"]),
+                terms(&["OR"]),
+                terms(&["peace"]),
+                terms(&["or"]),
+                terms(&["-"]),
+                terms(&["x"])
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_edge_cases() {
+        let query = SearchQuery::parse(r#""mobile communications"#);
+        assert_eq!(query.groups, vec![terms(&["mobile communications"])]);
+        assert!(query.excluded.is_empty());
+
+        let query = SearchQuery::parse("-thriller");
+        assert!(query.groups.is_empty());
+        assert_eq!(query.excluded, terms(&["thriller"]));
+
+        assert!(SearchQuery::parse(r#"  "" OR "#).is_empty());
+        assert!(SearchQuery::parse("\"").is_empty());
+    }
+}
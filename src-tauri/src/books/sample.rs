@@ -0,0 +1,112 @@
+// Copyright © 2023 Sandro Dallo
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+// This file contains the sample books used for demos and screenshots.
+
+use chrono::{TimeZone, Utc};
+
+use super::models::{Book, BookDB, BookError, Result, SearchConfig};
+
+/// Returns the sample books, the same ones as in `scripts/dummy_data.sql`.
+pub fn sample_books() -> Vec<Book> {
+    vec![
+        Book {
+            authors: vec!["David Lagercrantz".to_owned()],
+            description: Some("Lisbeth Salander is an unstoppable force!".to_owned()),
+            isbn: "9780857056429".to_owned(),
+            lang: "EN".to_owned(),
+            tags: Some(vec!["Thriller".to_owned(), "Suspense".to_owned()]),
+            title: "The Girl Who Takes an Eye for an Eye".to_owned(),
+            publisher: Some("McLehose Press".to_owned()),
+            publish_date: Utc.timestamp_opt(1483523713, 0).single(),
+            ..Default::default()
+        },
+        Book {
+            authors: vec!["Jochen Schiller".to_owned()],
+            description: Some("Explains mobile communications in details.".to_owned()),
+            isbn: "9780321123817".to_owned(),
+            lang: "EN".to_owned(),
+            tags: Some(vec![
+                "Data Transmission Systems".to_owned(),
+                "Communications".to_owned(),
+                "Wireless".to_owned(),
+            ]),
+            title: "Mobile Communications".to_owned(),
+            sub_title: Some("Second Edition".to_owned()),
+            publisher: Some("Addison Wesley".to_owned()),
+            publish_date: Utc.timestamp_opt(1062150913, 0).single(),
+            ..Default::default()
+        },
+        Book {
+            authors: vec!["Richard Dawkins".to_owned()],
+            description: Some(
+                "Richard Dawkins provozierendes Buch beseitigt jeden Zweifel an Darwins Theorie."
+                    .to_owned(),
+            ),
+            isbn: "9783550087653".to_owned(),
+            lang: "DE".to_owned(),
+            tags: Some(vec![
+                "Wissenschaft".to_owned(),
+                "Biologie".to_owned(),
+                "Religion".to_owned(),
+            ]),
+            title: "Es gibt keine Schöpfung".to_owned(),
+            publisher: Some("Ullstein Verlag".to_owned()),
+            publish_date: Utc.timestamp_opt(1283075713, 0).single(),
+            ..Default::default()
+        },
+    ]
+}
+
+/// Adds the sample books to the store and returns their ids. Fails if the store
+/// already contains books, so real data is never mixed with the samples.
+pub fn load_sample_data(db: &mut dyn BookDB) -> Result<Vec<i64>> {
+    if db.count_books(SearchConfig::new("").build())? > 0 {
+        return Err(BookError::Generic(
+            "Sample data can only be loaded into an empty database".to_owned(),
+        ));
+    }
+
+    let mut ids = Vec::new();
+    for mut book in sample_books() {
+        db.add_book(&mut book)?;
+        ids.push(book.id);
+    }
+
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_sample_data, sample_books};
+    use crate::books::models::{BookDB, DbTuning, Result, SearchConfig};
+    use crate::books::store::SqliteStore;
+
+    #[test]
+    fn load_into_empty_store() -> Result<()> {
+        let mut db = SqliteStore::with_seed("db_file", false, &DbTuning::default())?;
+
+        let ids = load_sample_data(&mut db)?;
+        assert_eq!(ids.len(), 3);
+
+        let books = db.fetch_books(SearchConfig::new("").build())?;
+        assert_eq!(books.total, 3);
+        for sample in sample_books() {
+            let book = books
+                .items
+                .iter()
+                .find(|b| b.isbn == sample.isbn)
+                .expect("sample book is missing");
+            assert_eq!(book.title, sample.title);
+            assert_eq!(book.authors, sample.authors);
+        }
+
+        // Never loaded into a database with books
+        assert!(load_sample_data(&mut db).is_err());
+        assert_eq!(db.count_books(SearchConfig::new("").build())?, 3);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,10 @@
+/*
+ * Script:      acquired_date.sql
+ * Description: Adds the date a book was acquired, which may predate adding it.
+ *
+ * Author:      Sandro Dallo
+ * Date:        16.11.2023
+ */
+
+-- Unix timestamp, NULL if unknown
+ALTER TABLE books ADD COLUMN acquired_date INTEGER;
@@ -0,0 +1,12 @@
+/*
+ * Script:      book_format.sql
+ * Description: Adds the page count and the format of a book, e.g. Hardcover or EPUB.
+ *
+ * Author:      Sandro Dallo
+ * Date:        10.11.2023
+ */
+
+-- NULL if the page count is unknown
+ALTER TABLE books ADD COLUMN page_count INTEGER CHECK (page_count > 0);
+-- Free text, e.g. 'Hardcover', 'Paperback' or 'EPUB'
+ALTER TABLE books ADD COLUMN format TEXT;
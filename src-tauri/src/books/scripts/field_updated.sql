@@ -0,0 +1,10 @@
+/*
+ * Script:      field_updated.sql
+ * Description: Adds the per-field modification timestamps used for merging databases.
+ *
+ * Author:      Sandro Dallo
+ * Date:        20.10.2023
+ */
+
+-- JSON object which maps a field name to the timestamp of its last modification
+ALTER TABLE books ADD COLUMN field_updated TEXT;
@@ -0,0 +1,65 @@
+/*
+ * Script:      fts.sql
+ * Description: Adds a full-text index over the searchable columns of books, authors and tags.
+ *              The trigram tokenizer matches substrings of at least three characters.
+ *
+ * Author:      Sandro Dallo
+ * Date:        02.11.2023
+ */
+
+CREATE VIRTUAL TABLE books_fts USING fts5(
+    title, sub_title, publisher, isbn, description, authors, tags,
+    tokenize = 'trigram'
+);
+
+-- Authors and tags are separated by line breaks, so a search never spans two of them
+INSERT INTO books_fts (rowid, title, sub_title, publisher, isbn, description, authors, tags)
+SELECT id, title, sub_title, publisher, isbn, description,
+    (SELECT group_concat(name, char(10)) FROM authors WHERE book_id = books.id),
+    (SELECT group_concat(tag, char(10)) FROM tags WHERE book_id = books.id)
+FROM books;
+
+CREATE TRIGGER books_fts_insert AFTER INSERT ON books BEGIN
+    INSERT INTO books_fts (rowid, title, sub_title, publisher, isbn, description)
+    VALUES (NEW.id, NEW.title, NEW.sub_title, NEW.publisher, NEW.isbn, NEW.description);
+END;
+
+CREATE TRIGGER books_fts_update AFTER UPDATE OF title, sub_title, publisher, isbn, description ON books BEGIN
+    UPDATE books_fts SET title = NEW.title, sub_title = NEW.sub_title, publisher = NEW.publisher,
+        isbn = NEW.isbn, description = NEW.description
+    WHERE rowid = NEW.id;
+END;
+
+CREATE TRIGGER books_fts_delete AFTER DELETE ON books BEGIN
+    DELETE FROM books_fts WHERE rowid = OLD.id;
+END;
+
+CREATE TRIGGER authors_fts_insert AFTER INSERT ON authors BEGIN
+    UPDATE books_fts SET authors = (SELECT group_concat(name, char(10)) FROM authors WHERE book_id = NEW.book_id)
+    WHERE rowid = NEW.book_id;
+END;
+
+CREATE TRIGGER authors_fts_update AFTER UPDATE ON authors BEGIN
+    UPDATE books_fts SET authors = (SELECT group_concat(name, char(10)) FROM authors WHERE book_id = books_fts.rowid)
+    WHERE rowid IN (OLD.book_id, NEW.book_id);
+END;
+
+CREATE TRIGGER authors_fts_delete AFTER DELETE ON authors BEGIN
+    UPDATE books_fts SET authors = (SELECT group_concat(name, char(10)) FROM authors WHERE book_id = OLD.book_id)
+    WHERE rowid = OLD.book_id;
+END;
+
+CREATE TRIGGER tags_fts_insert AFTER INSERT ON tags BEGIN
+    UPDATE books_fts SET tags = (SELECT group_concat(tag, char(10)) FROM tags WHERE book_id = NEW.book_id)
+    WHERE rowid = NEW.book_id;
+END;
+
+CREATE TRIGGER tags_fts_update AFTER UPDATE ON tags BEGIN
+    UPDATE books_fts SET tags = (SELECT group_concat(tag, char(10)) FROM tags WHERE book_id = books_fts.rowid)
+    WHERE rowid IN (OLD.book_id, NEW.book_id);
+END;
+
+CREATE TRIGGER tags_fts_delete AFTER DELETE ON tags BEGIN
+    UPDATE books_fts SET tags = (SELECT group_concat(tag, char(10)) FROM tags WHERE book_id = OLD.book_id)
+    WHERE rowid = OLD.book_id;
+END;
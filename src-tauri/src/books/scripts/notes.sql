@@ -0,0 +1,39 @@
+/*
+ * Script:      notes.sql
+ * Description: Adds personal notes of a book and includes them in the full-text index.
+ *              FTS5 tables can't be altered, therefore the index is rebuilt.
+ *
+ * Author:      Sandro Dallo
+ * Date:        12.11.2023
+ */
+
+-- Free text, NULL if there are no notes
+ALTER TABLE books ADD COLUMN notes TEXT;
+
+DROP TRIGGER books_fts_insert;
+DROP TRIGGER books_fts_update;
+DROP TABLE books_fts;
+
+CREATE VIRTUAL TABLE books_fts USING fts5(
+    title, sub_title, publisher, isbn, description, authors, tags, notes,
+    tokenize = 'trigram'
+);
+
+-- Authors and tags are separated by line breaks, so a search never spans two of them
+INSERT INTO books_fts (rowid, title, sub_title, publisher, isbn, description, authors, tags, notes)
+SELECT id, title, sub_title, publisher, isbn, description,
+    (SELECT group_concat(name, char(10)) FROM authors WHERE book_id = books.id),
+    (SELECT group_concat(tag, char(10)) FROM tags WHERE book_id = books.id),
+    notes
+FROM books;
+
+CREATE TRIGGER books_fts_insert AFTER INSERT ON books BEGIN
+    INSERT INTO books_fts (rowid, title, sub_title, publisher, isbn, description, notes)
+    VALUES (NEW.id, NEW.title, NEW.sub_title, NEW.publisher, NEW.isbn, NEW.description, NEW.notes);
+END;
+
+CREATE TRIGGER books_fts_update AFTER UPDATE OF title, sub_title, publisher, isbn, description, notes ON books BEGIN
+    UPDATE books_fts SET title = NEW.title, sub_title = NEW.sub_title, publisher = NEW.publisher,
+        isbn = NEW.isbn, description = NEW.description, notes = NEW.notes
+    WHERE rowid = NEW.id;
+END;
@@ -0,0 +1,10 @@
+/*
+ * Script:      owned.sql
+ * Description: Adds the owned flag of a book, books on the wishlist are not owned.
+ *
+ * Author:      Sandro Dallo
+ * Date:        08.11.2023
+ */
+
+-- 1 if the book is owned, 0 if it is on the wishlist. Existing books are owned.
+ALTER TABLE books ADD COLUMN owned INTEGER NOT NULL DEFAULT 1 CHECK (owned IN (0, 1));
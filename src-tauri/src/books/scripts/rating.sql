@@ -0,0 +1,10 @@
+/*
+ * Script:      rating.sql
+ * Description: Adds the rating of a book.
+ *
+ * Author:      Sandro Dallo
+ * Date:        03.11.2023
+ */
+
+-- Number of stars from 1 to 5, NULL if the book is not rated
+ALTER TABLE books ADD COLUMN rating INTEGER CHECK (rating BETWEEN 1 AND 5);
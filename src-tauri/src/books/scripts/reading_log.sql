@@ -0,0 +1,13 @@
+/*
+ * Script:      reading_log.sql
+ * Description: Adds the dates a book was started and finished reading.
+ *
+ * Author:      Sandro Dallo
+ * Date:        24.10.2023
+ */
+
+-- Unix timestamps, NULL if not logged
+ALTER TABLE books ADD COLUMN started_at INTEGER;
+ALTER TABLE books ADD COLUMN finished_at INTEGER;
+
+CREATE INDEX idx_books_finished_at ON books (finished_at);
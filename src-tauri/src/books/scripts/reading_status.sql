@@ -0,0 +1,10 @@
+/*
+ * Script:      reading_status.sql
+ * Description: Adds the reading status of a book.
+ *
+ * Author:      Sandro Dallo
+ * Date:        22.10.2023
+ */
+
+-- One of 'unread', 'reading' or 'read'
+ALTER TABLE books ADD COLUMN reading_status TEXT NOT NULL DEFAULT 'unread';
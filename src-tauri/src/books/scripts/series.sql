@@ -0,0 +1,12 @@
+/*
+ * Script:      series.sql
+ * Description: Adds the series of a book and its position within the series.
+ *
+ * Author:      Sandro Dallo
+ * Date:        14.11.2023
+ */
+
+-- NULL if the book is not part of a series
+ALTER TABLE books ADD COLUMN series TEXT;
+-- Position within the series, fractions are used for novellas between two books (e.g. 1.5)
+ALTER TABLE books ADD COLUMN series_index REAL CHECK (series_index >= 0);
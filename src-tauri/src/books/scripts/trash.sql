@@ -0,0 +1,10 @@
+/*
+ * Script:      trash.sql
+ * Description: Adds the deletion date of books moved to the trash.
+ *
+ * Author:      Sandro Dallo
+ * Date:        26.10.2023
+ */
+
+-- Unix timestamp, NULL if the book is not in the trash
+ALTER TABLE books ADD COLUMN deleted_at INTEGER;
@@ -0,0 +1,28 @@
+/*
+ * Script:      unique_isbn.sql
+ * Description: Prevents adding the same book twice, books without ISBN and books in
+ *              the trash are exempt.
+ *
+ * Author:      Sandro Dallo
+ * Date:        06.11.2023
+ */
+
+-- Books moved to the trash by this migration, they are kept until the user restores
+-- or deletes them
+CREATE TABLE isbn_conflicts (
+   book_id INTEGER NOT NULL PRIMARY KEY,
+   CONSTRAINT FK_books_isbn_conflicts FOREIGN KEY(book_id) REFERENCES books(id) ON DELETE CASCADE
+);
+
+-- Existing duplicates are moved to the trash, the book added first is kept. ISBNs are
+-- normalized before this migration, so only identical ISBNs need to be compared.
+INSERT INTO isbn_conflicts (book_id)
+SELECT id FROM books
+WHERE deleted_at IS NULL AND isbn <> '' AND EXISTS (
+    SELECT 1 FROM books AS B
+    WHERE B.isbn = books.isbn AND B.deleted_at IS NULL AND B.id < books.id
+);
+
+UPDATE books SET deleted_at = unixepoch() WHERE id IN (SELECT book_id FROM isbn_conflicts);
+
+CREATE UNIQUE INDEX idx_books_isbn ON books (isbn) WHERE isbn <> '' AND deleted_at IS NULL;
@@ -3,76 +3,296 @@
 // Use of this source code is governed by an BSD-style
 // license that can be found in the LICENSE file.
 
-use std::ops::Add;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
 
-use chrono::{DateTime, TimeZone, Utc};
-use rusqlite::{named_params, params, Connection, ToSql};
+use chrono::{DateTime, Datelike, TimeZone, Utc};
+use log::{debug, warn};
+use rusqlite::types::{Type, Value};
+use rusqlite::{
+    named_params, params, params_from_iter, Connection, DatabaseName, ErrorCode, OpenFlags,
+    Transaction, TransactionBehavior,
+};
 use rusqlite_migration::{Migrations, M};
 
+use super::merge::{combine_books, track_field_updates};
 use super::models::{
-    Book, BookDB, BookError, ConfigInitialized, Result, SearchConfig, SortOrder, StoreResult,
+    normalize_isbn, Book, BookDB, BookError, ConfigInitialized, Cursor, CursorKey, DbTuning,
+    Direction, LibraryStats, NameCollation, ReadingStatus, Result, SearchColumn, SearchConfig,
+    SortOrder, StoreResult,
 };
+use super::query::SearchQuery;
 
+/// Selects all columns of a book. `first_author` is the first author in the order of
+/// [NAME_COLLATION], which is also the order of [Book::authors].
 const SELECT_BOOKS_QUERY: &str = r#"SELECT id, cover_img, description, isbn, lang, title, sub_title,
-publisher, publish_date, created, updated FROM books"#;
+publisher, publish_date, reading_status, started_at, finished_at, rating, owned, page_count, format,
+notes, series, series_index, acquired_date, created, updated, field_updated,
+(SELECT name FROM authors WHERE book_id = books.id ORDER BY name COLLATE BOOKSHELF_NAMES LIMIT 1)
+AS first_author FROM books"#;
 const SELECT_AUTHORS_QUERY: &str = "SELECT DISTINCT name FROM authors";
 const SELECT_TAGS_QUERY: &str = "SELECT DISTINCT tag FROM tags";
 
-/// Maps a sqlite row to a Book.
-/// Requires a connection reference,
-macro_rules! map_sqlite_row_to_book {
-    ($conn:expr, $row:ident) => {{
-        let id: i64 = $row.get("id")?;
-        Book {
-            authors: load_authors_of_book($conn, &id)?,
-            cover_img: $row.get("cover_img")?,
-            description: $row.get("description")?,
-            isbn: $row.get("isbn")?,
-            lang: $row.get("lang")?,
-            tags: load_tags_of_book($conn, &id).map(|v| match v.len() {
-                0 => None,
-                _ => Some(v),
-            })?,
-            title: $row.get("title")?,
-            sub_title: $row.get("sub_title")?,
-            publisher: $row.get("publisher")?,
-            publish_date: $row
-                .get::<&str, i64>("publish_date")
-                .map(|r| {
-                    convert_timestamp(r).expect("Conversion database integer to DateTime failed")
-                })
-                .ok(),
-            id,
-            created: convert_timestamp($row.get::<&str, i64>("created")?)
-                .expect("Conversion database integer to DateTime failed"),
-            updated: convert_timestamp($row.get::<&str, i64>("updated")?)
-                .expect("Conversion database integer to DateTime failed"),
-        }
-    }};
+/// Condition on the books table which excludes books in the trash.
+const NOT_TRASHED: &str = "deleted_at IS NULL";
+/// Number of entries of the top lists of the library statistics.
+const TOP_STATISTICS: u32 = 10;
+
+/// Minimum length of a search text matched by the full-text index, its trigram tokenizer
+/// can't match shorter texts.
+const FTS_MIN_CHARS: usize = 3;
+
+/// Name of the collation used to order authors and tags, its behavior is set per connection.
+const NAME_COLLATION: &str = "BOOKSHELF_NAMES";
+
+/// Maximum number of book ids bound to one batched query, well below SQLite's limit
+/// of host parameters.
+const MAX_BATCHED_IDS: usize = 500;
+
+/// Latest timestamp considered valid (9999-12-31 23:59:59 UTC), larger ones are repaired.
+const MAX_VALID_TIMESTAMP: i64 = 253402300799;
+
+/// Environment variable which seeds empty databases of debug builds with dummy data, if set to `1`.
+const SEED_ENV: &str = "BOOKSHELF_SEED";
+
+/// Minimal fraction of free pages before a database is vacuumed on close.
+const VACUUM_FREE_PAGE_FRACTION: f64 = 0.25;
+
+/// All schema migrations, the number of scripts is the latest schema version.
+const SCHEMA_SCRIPTS: &[&str] = &[
+    include_str!("scripts/init.sql"),
+    include_str!("scripts/field_updated.sql"),
+    include_str!("scripts/reading_status.sql"),
+    include_str!("scripts/reading_log.sql"),
+    include_str!("scripts/trash.sql"),
+    include_str!("scripts/fts.sql"),
+    include_str!("scripts/rating.sql"),
+    include_str!("scripts/unique_isbn.sql"),
+    include_str!("scripts/owned.sql"),
+    include_str!("scripts/book_format.sql"),
+    include_str!("scripts/notes.sql"),
+    include_str!("scripts/series.sql"),
+    include_str!("scripts/acquired_date.sql"),
+];
+
+/// Schema version before `unique_isbn.sql`, ISBNs stored until then are normalized
+/// when a database is migrated past it.
+const UNIQUE_ISBN_VERSION: usize = 7;
+
+/// Returns the latest schema version known to this application.
+pub fn schema_version() -> u32 {
+    SCHEMA_SCRIPTS.len() as u32
 }
 
-/// Opens or creates a new books database and returns it.
-fn open_sqlite_connection(db_file: &str) -> Result<Connection> {
-    // Add all required sql scripts to the migrator
-    let mut scripts = vec![M::up(include_str!("scripts/init.sql"))];
+/// Returns the version of the linked SQLite library.
+pub fn sqlite_version() -> &'static str {
+    rusqlite::version()
+}
 
-    // Add only for debug mode dummy data
-    if cfg!(debug_assertions) {
-        scripts.push(M::up(include_str!("scripts/dummy_data.sql")));
+/// Opens or creates a new books database and returns it.
+/// Falls back to a read-only connection if the database can't be written.
+/// If `seed` is set, the dummy data is inserted into an empty database.
+fn open_sqlite_connection(db_file: &str, seed: bool, tuning: &DbTuning) -> Result<Connection> {
+    check_bookshelf_db(db_file)?;
+    if is_read_only(db_file) {
+        return open_read_only_sqlite_connection(db_file, tuning);
     }
 
     let mut conn = create_sqlite_connection(db_file)?;
+    init_sqlite_connection(&mut conn, seed, tuning)?;
+
+    Ok(conn)
+}
+
+/// Applies the busy timeout of the tuning, so SQLite retries for a while before a lock of
+/// another connection results in [BookError::Busy]. Without timeout it fails immediately.
+fn set_busy_timeout(conn: &Connection, tuning: &DbTuning) -> Result<()> {
+    let ms = tuning.busy_timeout.unwrap_or(0);
+    conn.busy_timeout(Duration::from_millis(ms.into()))?;
+    Ok(())
+}
+
+/// Applies all schema migrations and pragmas to a writable connection.
+/// If `seed` is set, the dummy data is inserted into an empty database.
+fn init_sqlite_connection(conn: &mut Connection, seed: bool, tuning: &DbTuning) -> Result<()> {
+    // Wait for other connections while migrating as well
+    set_busy_timeout(conn, tuning)?;
+
+    migrate_schema(conn)?;
+
+    conn.pragma_update(None, "journal_mode", tuning.journal_mode.as_str())?;
+    conn.pragma_update(None, "synchronous", tuning.synchronous.as_str())?;
+    conn.pragma_update(None, "foreign_keys", "on")?;
+
+    if seed {
+        seed_dummy_data(conn)?;
+    }
+
+    Ok(())
+}
+
+/// Applies all schema migrations. ISBNs of books added before ISBNs were validated are
+/// normalized on the way, see [normalize_stored_isbns], and the books moved to the trash
+/// for a duplicate ISBN are logged.
+fn migrate_schema(conn: &mut Connection) -> Result<()> {
+    // Add all required sql scripts to the migrator
+    let scripts: Vec<M> = SCHEMA_SCRIPTS.iter().map(|s| M::up(s)).collect();
     let migrations = Migrations::new(scripts);
 
-    migrations.to_latest(&mut conn)?;
+    let version: u32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version as usize > UNIQUE_ISBN_VERSION {
+        migrations.to_latest(conn)?;
+        return Ok(());
+    }
+
+    migrations.to_version(conn, UNIQUE_ISBN_VERSION)?;
+    normalize_stored_isbns(conn)?;
+    migrations.to_latest(conn)?;
+
+    let conflicts = conn
+        .prepare("SELECT book_id FROM isbn_conflicts ORDER BY book_id")?
+        .query_map([], |row| row.get::<usize, i64>(0))?
+        .collect::<rusqlite::Result<Vec<i64>>>()?;
+    if !conflicts.is_empty() {
+        warn!(
+            "moved books with a duplicate ISBN to the trash for review: {:?}",
+            conflicts
+        );
+    }
+
+    Ok(())
+}
+
+/// Stores the valid ISBNs of all books in their normalized form, so they are found by
+/// [BookDB::get_book_by_isbn]. Invalid ISBNs are kept as they are, they are only
+/// validated once they are changed.
+fn normalize_stored_isbns(conn: &mut Connection) -> Result<()> {
+    let tx = write_transaction(conn)?;
+    let stored = tx
+        .prepare("SELECT id, isbn FROM books WHERE isbn <> ''")?
+        .query_map([], |row| {
+            Ok((row.get::<usize, i64>(0)?, row.get::<usize, String>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<(i64, String)>>>()?;
+    for (id, isbn) in stored {
+        match normalize_isbn(&isbn) {
+            Ok(normalized) if normalized != isbn => {
+                tx.execute(
+                    "UPDATE books SET isbn = ? WHERE id = ?",
+                    params![normalized, id],
+                )?;
+            }
+            Ok(_) => {}
+            Err(e) => warn!("keeping ISBN of book {}: {}", id, e),
+        }
+    }
+    tx.commit()?;
+
+    Ok(())
+}
+
+/// Starts an immediate transaction for writes. A deferred transaction upgrading from a read
+/// to a write fails with SQLITE_BUSY, instead of waiting for the lock with the busy timeout.
+fn write_transaction(conn: &mut Connection) -> rusqlite::Result<Transaction<'_>> {
+    conn.transaction_with_behavior(TransactionBehavior::Immediate)
+}
+
+/// Inserts the dummy data, but only into an empty database. The dummy data is
+/// not part of the schema migrations, so it never ends up in a database (or its
+/// search index) unless explicitly requested.
+fn seed_dummy_data(conn: &mut Connection) -> Result<()> {
+    let tx = write_transaction(conn)?;
+    let count: u64 = tx.query_row("SELECT COUNT(*) FROM books", [], |row| row.get(0))?;
+    if count == 0 {
+        tx.execute_batch(include_str!("scripts/dummy_data.sql"))?;
+    }
+    tx.commit()?;
+
+    Ok(())
+}
+
+/// Returns true if the dummy data should be seeded, which requires a debug build and
+/// the value `1` of [SEED_ENV].
+fn seed_enabled(value: Option<&str>) -> bool {
+    cfg!(debug_assertions) && value == Some("1")
+}
 
-    conn.pragma_update(None, "journal_mode", "wal")?;
-    conn.pragma_update(None, "synchronous", "normal")?;
+/// Opens an existing books database without write access, e.g. a library on a
+/// read-only network share. Migrations are not applied and pragmas which require
+/// write access (`journal_mode`, `synchronous`) are skipped, so the journal
+/// mode stored in the database file is used.
+fn open_read_only_sqlite_connection(db_file: &str, tuning: &DbTuning) -> Result<Connection> {
+    let conn = Connection::open_with_flags(
+        db_file,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
+    set_busy_timeout(&conn, tuning)?;
     conn.pragma_update(None, "foreign_keys", "on")?;
 
     Ok(conn)
 }
 
+/// Verifies that an existing, non-empty file is a bookshelf database before any migration
+/// touches it. A database is accepted if it has a `books` table or no tables at all, and
+/// its schema version is not newer than [schema_version].
+fn check_bookshelf_db(db_file: &str) -> Result<()> {
+    let path = Path::new(db_file);
+    if fs::metadata(path).map(|m| m.len() == 0).unwrap_or(true) {
+        return Ok(());
+    }
+
+    let inspect = || -> rusqlite::Result<(u32, bool, bool)> {
+        let conn = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        let version = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        let has_tables = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table')",
+            [],
+            |row| row.get(0),
+        )?;
+        let has_books = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'books')",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok((version, has_tables, has_books))
+    };
+
+    let (version, has_tables, has_books) =
+        inspect().map_err(|e| BookError::IncompatibleDatabase(e.to_string()))?;
+    if version > schema_version() {
+        return Err(BookError::IncompatibleDatabase(format!(
+            "schema version {} is newer than the supported version {}",
+            version,
+            schema_version()
+        )));
+    }
+    if has_tables && !has_books {
+        return Err(BookError::IncompatibleDatabase(String::from(
+            "missing books table",
+        )));
+    }
+
+    Ok(())
+}
+
+/// Returns true if the database file exists but either the file or its directory
+/// is not writable. WAL requires write access to the directory for its side files.
+fn is_read_only(db_file: &str) -> bool {
+    let read_only = |p: &Path| {
+        fs::metadata(p)
+            .map(|m| m.permissions().readonly())
+            .unwrap_or(false)
+    };
+
+    let path = Path::new(db_file);
+    path.is_file() && (read_only(path) || path.parent().map(read_only).unwrap_or(false))
+}
+
 #[cfg(debug_assertions)]
 fn create_sqlite_connection(_: &str) -> Result<Connection> {
     Ok(Connection::open_in_memory()?)
@@ -86,110 +306,144 @@ fn create_sqlite_connection(db_file: &str) -> Result<Connection> {
 #[derive(Debug)]
 pub struct SqliteStore {
     conn: Connection,
+    collation: NameCollation,
 }
 
 impl SqliteStore {
-    pub fn new(db_file: &str) -> Result<Self> {
-        Ok(Self {
-            conn: open_sqlite_connection(db_file)?,
-        })
+    /// Opens the store with the given pragmas, debug builds are seeded with dummy data
+    /// if [SEED_ENV] is set.
+    pub fn new(db_file: &str, tuning: &DbTuning) -> Result<Self> {
+        let seed = std::env::var(SEED_ENV).ok();
+        Self::with_seed(db_file, seed_enabled(seed.as_deref()), tuning)
     }
-}
 
-impl BookDB for SqliteStore {
-    /// Add a new book to the store.
-    /// TODO: Write a unit test to ensure functionality.
-    fn add_book(&mut self, book: &mut Book) -> Result<()> {
-        let tx = self.conn.transaction()?;
-        validate_book(book)?;
+    /// Opens the store with the given pragmas and seeds an empty database with
+    /// dummy data if `seed` is set.
+    pub fn with_seed(db_file: &str, seed: bool, tuning: &DbTuning) -> Result<Self> {
+        Self::from_connection(open_sqlite_connection(db_file, seed, tuning)?)
+    }
 
-        let mut books_stmt = tx.prepare(r#"INSERT INTO books (cover_img, description, isbn, lang, title, sub_title, publisher, publish_date, created, updated)
-        VALUES (:img, :desc, :isbn, :lang , :title, :subt, :pub, :pubd, unixepoch(), unixepoch())"#)?;
+    /// Opens a new in-memory database seeded with the dummy data, which is not shared
+    /// with any other store.
+    #[allow(dead_code)]
+    pub fn new_in_memory() -> Result<Self> {
+        Self::in_memory(true)
+    }
 
-        let book_id = books_stmt.insert(named_params! {
-            ":img": book.cover_img,
-            ":desc": book.description,
-            ":isbn": book.isbn,
-            ":lang": book.lang,
-            ":title": book.title,
-            ":subt": book.sub_title,
-            ":pub": book.publisher,
-            ":pubd": book.publish_date.as_ref().map(|d| d.timestamp())
-        })?;
-        drop(books_stmt);
+    /// Opens a new in-memory database, seeded with the dummy data if `seed` is set.
+    fn in_memory(seed: bool) -> Result<Self> {
+        let mut conn = Connection::open_in_memory()?;
+        init_sqlite_connection(&mut conn, seed, &DbTuning::default())?;
+        Self::from_connection(conn)
+    }
 
-        if book_id <= 0 {
-            return Err(BookError::Generic(format!(
-                "return row id is invalid: {}",
-                book_id
-            )));
-        }
-        book.id = book_id;
+    /// Opens an existing database read-only, regardless of the file permissions.
+    #[allow(dead_code)]
+    pub fn new_read_only(db_file: &str) -> Result<Self> {
+        Self::from_connection(open_read_only_sqlite_connection(
+            db_file,
+            &DbTuning::default(),
+        )?)
+    }
 
-        {
-            let mut authors_stmt =
-                tx.prepare("INSERT INTO authors (book_id, name) VALUES (?1, ?2)")?;
-            for author in &book.authors {
-                authors_stmt.execute(params![book_id, author])?;
-            }
+    fn from_connection(conn: Connection) -> Result<Self> {
+        let mut store = Self {
+            conn,
+            collation: NameCollation::default(),
+        };
+        store.set_name_collation(NameCollation::default())?;
+        Ok(store)
+    }
+}
 
-            {
-                if let Some(tags) = &book.tags {
-                    let mut tags_stmt =
-                        tx.prepare("INSERT INTO tags (book_id, tag) VALUES (?1, ?2)")?;
-                    for tag in tags {
-                        tags_stmt.execute(params![book_id, tag])?;
-                    }
-                }
-            }
+impl Drop for SqliteStore {
+    /// Checkpoints the WAL before the connection is closed, so it doesn't keep growing while
+    /// other connections keep the database open. The checkpoint is passive, it never waits for
+    /// the busy timeout, and the last connection removes the WAL anyway.
+    fn drop(&mut self) {
+        // Fails on read-only databases, which have nothing to checkpoint anyway
+        if let Err(e) = self
+            .conn
+            .query_row("PRAGMA wal_checkpoint(PASSIVE)", [], |_| Ok(()))
+        {
+            debug!("failed to checkpoint WAL on close: {}", e);
         }
+    }
+}
 
-        let dates: (i64, i64) = tx.query_row(
-            "SELECT created, updated FROM books WHERE id = ?1",
-            [&book_id],
-            |row| Ok((row.get::<usize, i64>(0)?, row.get::<usize, i64>(1)?)),
-        )?;
+impl BookDB for SqliteStore {
+    /// Add a new book to the store.
+    /// TODO: Write a unit test to ensure functionality.
+    fn add_book(&mut self, book: &mut Book) -> Result<()> {
+        let collation = self.collation;
+        let tx = write_transaction(&mut self.conn)?;
+        insert_book(&tx, book, collation)?;
+        tx.commit()?;
 
-        book.created = convert_timestamp(dates.0)?;
-        book.updated = convert_timestamp(dates.1)?;
+        Ok(())
+    }
 
-        book.authors.sort();
-        if let Some(tags) = book.tags.as_mut() {
-            tags.sort();
+    fn add_books(&mut self, books: &mut [Book]) -> Result<()> {
+        let collation = self.collation;
+        let tx = write_transaction(&mut self.conn)?;
+        for book in books.iter_mut() {
+            insert_book(&tx, book, collation)?;
         }
-
         tx.commit()?;
 
         Ok(())
     }
 
     fn update_book(&mut self, book: &mut Book) -> Result<()> {
-        let query = r#"UPDATE books SET cover_img = :img, description = :desc, isbn = :isbn, lang = :lang, 
-            title = :title, sub_title = :sub, publisher = :pub, 'publish_date' = :pdate, updated = unixepoch() WHERE id = :id"#;
+        let tx = write_transaction(&mut self.conn)?;
+        let stored = load_book(&tx, book.id)?;
+        // An invalid ISBN stored before validation doesn't prevent other changes
+        if book.isbn != stored.isbn {
+            book.isbn = normalize_isbn(&book.isbn)?;
+        }
+        // Saving an unmodified book must not touch `updated`
+        if stored.content_eq(book) {
+            *book = stored;
+            return Ok(());
+        }
+        write_book_update(&tx, book, self.collation)?;
+        // Returns the timestamps set by the database
+        *book = load_book(&tx, book.id)?;
+        tx.commit()?;
 
-        let tx = self.conn.transaction()?;
+        Ok(())
+    }
 
-        tx.execute(
-            query,
-            named_params! {
-                ":img": book.cover_img,
-                ":desc": book.description,
-                ":isbn": book.isbn,
-                ":lang": book.lang,
-                ":title": book.title,
-                ":sub": book.sub_title,
-                ":pub": book.publisher,
-                ":pdate": book.publish_date.as_ref().map(|d| d.timestamp()),
-                ":id": book.id
-            },
-        )?;
+    fn merge_books(&mut self, keep_id: i64, merge_ids: &[i64]) -> Result<Book> {
+        if merge_ids.contains(&keep_id) {
+            return Err(BookError::Generic(format!(
+                "Book {} can't be merged with itself",
+                keep_id
+            )));
+        }
+
+        let tx = write_transaction(&mut self.conn)?;
+        let mut merged = load_book(&tx, keep_id)?;
+        let mut removed: Vec<i64> = Vec::new();
+        for &id in merge_ids {
+            if !removed.contains(&id) {
+                merged = combine_books(&merged, &load_book(&tx, id)?);
+                removed.push(id);
+            }
+        }
 
-        update_book_tags(&tx, book)?;
-        update_book_authors(&tx, book)?;
+        // Merged books are deleted first, as the kept book may take over their ISBN
+        for id in &removed {
+            tx.execute("DELETE FROM books WHERE id = ?", [id])?;
+        }
+        if !removed.is_empty() {
+            write_book_update(&tx, &mut merged, self.collation)?;
+        }
+        let merged = load_book(&tx, keep_id)?;
 
         tx.commit()?;
 
-        Ok(())
+        Ok(merged)
     }
 
     fn delete_book(&mut self, book: &Book) -> Result<()> {
@@ -201,41 +455,103 @@ impl BookDB for SqliteStore {
         Ok(())
     }
 
+    fn delete_books_by_ids(&mut self, ids: &[i64]) -> Result<u64> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+
+        let tx = write_transaction(&mut self.conn)?;
+        let deleted = tx.execute(
+            &format!(
+                "DELETE FROM books WHERE id IN ({})",
+                placeholders(ids.len())
+            ),
+            params_from_iter(ids.iter()),
+        )?;
+        tx.commit()?;
+
+        Ok(deleted as u64)
+    }
+
+    fn trash_book(&mut self, id: i64) -> Result<()> {
+        let query = format!(
+            "UPDATE books SET deleted_at = unixepoch() WHERE id = ? AND {}",
+            NOT_TRASHED
+        );
+        match self.conn.execute(&query, [id])? {
+            0 => Err(BookError::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    fn trash_books(&mut self, ids: &[i64]) -> Result<u64> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+
+        let query = format!(
+            "UPDATE books SET deleted_at = unixepoch() WHERE id IN ({}) AND {}",
+            placeholders(ids.len()),
+            NOT_TRASHED
+        );
+        Ok(self.conn.execute(&query, params_from_iter(ids.iter()))? as u64)
+    }
+
+    fn restore_book(&mut self, id: i64) -> Result<()> {
+        match self.conn.execute(
+            "UPDATE books SET deleted_at = NULL WHERE id = ? AND deleted_at IS NOT NULL",
+            [id],
+        ) {
+            Ok(0) => Err(BookError::NotFound),
+            Ok(_) => {
+                // A restored duplicate of the migration is reviewed
+                self.conn
+                    .execute("DELETE FROM isbn_conflicts WHERE book_id = ?", [id])?;
+                Ok(())
+            }
+            // Another book with the same ISBN was added in the meantime
+            Err(e) => {
+                let isbn: String =
+                    self.conn
+                        .query_row("SELECT isbn FROM books WHERE id = ?", [id], |row| {
+                            row.get(0)
+                        })?;
+                Err(map_duplicate_isbn(e, &isbn))
+            }
+        }
+    }
+
+    fn list_trash(&mut self) -> Result<Vec<Book>> {
+        let query = format!(
+            "{} WHERE deleted_at IS NOT NULL ORDER BY deleted_at DESC, id",
+            SELECT_BOOKS_QUERY
+        );
+        let mut stmt = self.conn.prepare(&query)?;
+        let mut books = stmt
+            .query_map([], map_book_columns)?
+            .collect::<rusqlite::Result<Vec<Book>>>()?;
+        load_authors_and_tags(&self.conn, &mut books)?;
+        Ok(books)
+    }
+
+    fn purge_trash(&mut self, older_than_days: u32) -> Result<u64> {
+        let deleted = self.conn.execute(
+            "DELETE FROM books WHERE deleted_at IS NOT NULL AND deleted_at <= unixepoch() - ? * 86400
+            AND id NOT IN (SELECT book_id FROM isbn_conflicts)",
+            [older_than_days],
+        )?;
+        Ok(deleted as u64)
+    }
+
     fn fetch_books(
         &mut self,
         search: SearchConfig<ConfigInitialized>,
     ) -> Result<StoreResult<Book>> {
-        // This is quite naive implementation, use FTS5 to improve search performance.
-        let query = if search.get_text() != "" {
-            SELECT_BOOKS_QUERY.to_owned().add(
-                r#" WHERE id IN (
-                SELECT DISTINCT B.id
-                FROM books as B
-                    LEFT JOIN authors AS A ON A.book_id = B.id
-                    LEFT JOIN tags AS T ON T.book_id = B.id
-                WHERE B.title LIKE ?
-                    OR B.sub_title LIKE ?
-                    OR B.publisher LIKE ?
-                    OR B.isbn LIKE ?
-                    OR B.description LIKE ?
-                    OR A.name LIKE ?
-                    OR T.tag LIKE ?
-            );"#,
-            )
-        } else {
-            SELECT_BOOKS_QUERY.to_owned()
-        };
-
-        let mut builder = QueryBuilder::new(&query, &search);
-        let txt = format!("%{}%", search.get_text());
-        if search.get_text() != "" {
-            builder.use_params(vec![&txt, &txt, &txt, &txt, &txt, &txt, &txt, &txt, &txt])?;
-        }
+        let builder = books_query_builder(&search)?;
 
         let mut books: StoreResult<Book> = StoreResult::default();
-        builder.fetch(&self.conn, &mut books, |row| {
-            Ok(map_sqlite_row_to_book!(&self.conn, row))
-        })?;
+        builder.fetch(&self.conn, &mut books, map_book_columns)?;
+        load_authors_and_tags(&self.conn, &mut books.items)?;
 
         Ok(books)
     }
@@ -243,8 +559,18 @@ impl BookDB for SqliteStore {
     /// Gets a result of stored tags.
     /// TODO: USe FTS5 for improve the performance of this naive implementation.
     fn get_tags(&mut self, search: SearchConfig<ConfigInitialized>) -> Result<StoreResult<String>> {
-        let mut builder = QueryBuilder::new(SELECT_TAGS_QUERY, search.as_ref());
-        builder.use_where_clause(|txt| ("tag LIKE ?".to_owned(), vec![format!("%{}%", txt)]))?;
+        let mut builder = QueryBuilder::new(SELECT_TAGS_QUERY, TAG_SORT_COLUMNS, search.as_ref())?;
+        builder.use_condition(
+            format!("book_id IN (SELECT id FROM books WHERE {})", NOT_TRASHED),
+            vec![],
+        );
+        builder.use_where_clause(|txt| {
+            (
+                r"tag LIKE ? ESCAPE '\'".to_owned(),
+                vec![format!("%{}%", escape_like(txt))],
+            )
+        })?;
+        use_prefix_rank(&mut builder, "tag");
 
         let mut authors: StoreResult<String> = StoreResult::default();
         builder.fetch(&self.conn, &mut authors, |row| {
@@ -260,15 +586,24 @@ impl BookDB for SqliteStore {
         &mut self,
         search: SearchConfig<ConfigInitialized>,
     ) -> Result<StoreResult<String>> {
-        let mut builder = QueryBuilder::new(SELECT_AUTHORS_QUERY, search.as_ref());
+        let mut builder =
+            QueryBuilder::new(SELECT_AUTHORS_QUERY, AUTHOR_SORT_COLUMNS, search.as_ref())?;
+        builder.use_condition(
+            format!("book_id IN (SELECT id FROM books WHERE {})", NOT_TRASHED),
+            vec![],
+        );
         builder.use_where_clause(|txt| {
-            let parts: Vec<String> = txt.split(' ').map(|s| format!("%{}%", s)).collect();
+            let parts: Vec<String> = txt
+                .split(' ')
+                .map(|s| format!("%{}%", escape_like(s)))
+                .collect();
             let q = (0..parts.len())
-                .map(|_| "name LIKE ?")
+                .map(|_| r"name LIKE ? ESCAPE '\'")
                 .collect::<Vec<&str>>()
                 .join(" AND ");
             (q, parts)
         })?;
+        use_prefix_rank(&mut builder, "name");
 
         let mut authors: StoreResult<String> = StoreResult::default();
         builder.fetch(&self.conn, &mut authors, |row| {
@@ -278,457 +613,3864 @@ impl BookDB for SqliteStore {
         Ok(authors)
     }
 
-    fn get_book(&mut self, id: i64) -> Result<Book> {
-        let query = format!("{} WHERE id = ?1", SELECT_BOOKS_QUERY);
+    fn count_books(&mut self, search: SearchConfig<ConfigInitialized>) -> Result<u64> {
+        books_query_builder(&search)?.count(&self.conn)
+    }
 
-        let book = self.conn.query_row(&query, [id], |row| {
-            Ok(map_sqlite_row_to_book!(&self.conn, row))
-        })?;
+    fn get_neighbors(
+        &mut self,
+        id: i64,
+        search: SearchConfig<ConfigInitialized>,
+        direction: Direction,
+        count: u64,
+    ) -> Result<Vec<Book>> {
+        let builder = books_query_builder(&search)?;
+        let cursor = builder.cursor_of(&self.conn, id)?;
 
-        Ok(book)
-    }
-}
+        let mut books = builder.fetch_neighbors(
+            &self.conn,
+            &cursor,
+            direction == Direction::Backward,
+            count,
+            map_book_columns,
+        )?;
+        load_authors_and_tags(&self.conn, &mut books)?;
 
-fn update_book_authors(conn: &Connection, book: &mut Book) -> Result<()> {
-    if book.authors.is_empty() {
-        return Err(BookError::EmptyAuthors);
+        Ok(books)
     }
 
-    conn.execute("DELETE FROM authors WHERE book_id = ?1", [&book.id])?;
-    book.authors.sort();
+    fn repair_timestamps(&mut self) -> Result<u64> {
+        let tx = write_transaction(&mut self.conn)?;
+        let mut repaired = 0;
+        for column in ["created", "updated"] {
+            repaired += tx.execute(
+                &format!(
+                    "UPDATE books SET {0} = unixepoch() WHERE {0} IS NULL OR {0} <= 0 OR {0} > ?",
+                    column
+                ),
+                [MAX_VALID_TIMESTAMP],
+            )? as u64;
+        }
+        tx.commit()?;
 
-    let mut stmt = conn.prepare("INSERT INTO authors (book_id, name) VALUES (:id, :name)")?;
-    for a in &book.authors {
-        stmt.execute(named_params! {":id": &book.id, ":name": a})?;
+        Ok(repaired)
     }
 
-    Ok(())
-}
+    fn set_name_collation(&mut self, collation: NameCollation) -> Result<()> {
+        // Replaces a previously registered collation of the same name
+        self.conn
+            .create_collation(NAME_COLLATION, move |a, b| collation.compare(a, b))?;
+        self.collation = collation;
+        Ok(())
+    }
 
-fn update_book_tags(conn: &Connection, book: &mut Book) -> Result<()> {
-    conn.execute("DELETE FROM tags WHERE book_id = ?1", [&book.id])?;
+    fn check_name_order(&mut self) -> Result<Vec<i64>> {
+        let ids: Vec<i64> = {
+            let mut stmt = self.conn.prepare("SELECT id FROM books ORDER BY id")?;
+            let rows = stmt.query_map([], |row| row.get::<usize, i64>(0))?;
+            rows.collect::<rusqlite::Result<Vec<i64>>>()?
+        };
 
-    match book.tags.as_mut() {
-        None => return Ok(()),
-        Some(t) if t.is_empty() => {
-            book.tags = None;
-            return Ok(());
+        let is_sorted = |names: &[String]| {
+            names
+                .windows(2)
+                .all(|w| self.collation.compare(&w[0], &w[1]).is_le())
+        };
+
+        let mut inconsistent = Vec::new();
+        for id in ids {
+            let authors = load_authors_of_book(&self.conn, &id)?;
+            let tags = load_tags_of_book(&self.conn, &id)?;
+            if !is_sorted(&authors) || !is_sorted(&tags) {
+                inconsistent.push(id);
+            }
         }
-        Some(_) => (),
+
+        Ok(inconsistent)
     }
 
-    book.tags.as_mut().unwrap().sort();
-    book.tags.as_mut().unwrap().dedup();
+    fn log_reading(
+        &mut self,
+        id: i64,
+        started: Option<DateTime<Utc>>,
+        finished: Option<DateTime<Utc>>,
+    ) -> Result<Book> {
+        let mut book = self.get_book(id)?;
+        book.started_at = started;
+        book.finished_at = finished;
+        if finished.is_some() {
+            book.reading_status = ReadingStatus::Read;
+        } else if started.is_some() && book.reading_status == ReadingStatus::Unread {
+            book.reading_status = ReadingStatus::Reading;
+        }
 
-    let mut stmt = conn.prepare("INSERT INTO tags (book_id, tag) VALUES (:id, :tag)")?;
-    for t in book.tags.as_ref().unwrap() {
-        stmt.execute(named_params! { ":id": &book.id, ":tag": t })?;
+        self.update_book(&mut book)?;
+        Ok(book)
     }
 
-    Ok(())
-}
-
-fn load_authors_of_book(conn: &Connection, id: &i64) -> Result<Vec<String>, rusqlite::Error> {
-    let query = "SELECT name FROM authors WHERE book_id = ?1 ORDER BY name ASC";
+    fn finished_in_year(&mut self, year: i32) -> Result<Vec<Book>> {
+        let start = |y: i32| {
+            Utc.with_ymd_and_hms(y, 1, 1, 0, 0, 0)
+                .single()
+                .map(|d| d.timestamp())
+                .ok_or_else(|| BookError::Generic(format!("Invalid year: {}", y)))
+        };
+        let (from, to) = (start(year)?, start(year + 1)?);
 
-    let mut stmt = conn.prepare(query)?;
-    let rows = stmt.query_map([id], |row| row.get::<usize, String>(0))?;
+        let query = format!(
+            "{} WHERE {} AND finished_at >= ?1 AND finished_at < ?2 ORDER BY finished_at ASC, id ASC",
+            SELECT_BOOKS_QUERY, NOT_TRASHED
+        );
+        let mut stmt = self.conn.prepare(&query)?;
+        let mut books = stmt
+            .query_map([from, to], map_book_columns)?
+            .collect::<rusqlite::Result<Vec<Book>>>()?;
+        load_authors_and_tags(&self.conn, &mut books)?;
 
-    let mut authors: Vec<String> = Vec::new();
-    for tag in rows {
-        authors.push(tag?);
+        Ok(books)
     }
 
-    Ok(authors)
-}
+    fn set_cover(&mut self, id: i64, cover: Option<String>) -> Result<Option<String>> {
+        let tx = write_transaction(&mut self.conn)?;
 
-fn load_tags_of_book(conn: &Connection, id: &i64) -> Result<Vec<String>, rusqlite::Error> {
-    let query = "SELECT tag FROM tags WHERE book_id = ?1 ORDER BY tag ASC";
+        let stored = load_book(&tx, id)?;
+        let book = Book {
+            cover_img: cover,
+            ..stored.clone()
+        };
+        let field_updated = track_field_updates(&stored, &book, Utc::now())
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()
+            .map_err(|e| BookError::Generic(format!("Failed to serialize field_updated: {}", e)))?;
 
-    let mut stmt = conn.prepare(query)?;
-    let rows = stmt.query_map([id], |row| row.get::<usize, String>(0))?;
+        tx.execute(
+            "UPDATE books SET cover_img = ?1, field_updated = ?2, updated = unixepoch() WHERE id = ?3",
+            params![book.cover_img, field_updated, id],
+        )?;
+        tx.commit()?;
 
-    let mut tags: Vec<String> = Vec::new();
-    for tag in rows {
-        tags.push(tag?);
+        Ok(stored.cover_img)
     }
 
-    Ok(tags)
-}
-
-impl From<rusqlite::Error> for BookError {
-    fn from(value: rusqlite::Error) -> Self {
-        // Todo: If necessary transform [rusqlite::Error] errors into database agnostic errors.
-        match value {
-            rusqlite::Error::QueryReturnedNoRows => BookError::NotFound,
-            _ => BookError::DBError(value.into()),
-        }
+    fn rename_tag(&mut self, from: &str, to: &str) -> Result<u64> {
+        rename_book_names(&mut self.conn, "tags", "tag", from, to)
     }
-}
 
-impl From<rusqlite_migration::Error> for BookError {
-    fn from(value: rusqlite_migration::Error) -> Self {
-        // Todo: If necessary transform [rusqlite_migration::Error] errors into database agnostic errors.
-        BookError::DBError(value.into())
+    fn delete_tag(&mut self, tag: &str) -> Result<u64> {
+        let tx = write_transaction(&mut self.conn)?;
+        tx.execute(
+            "UPDATE books SET updated = unixepoch() WHERE id IN (SELECT book_id FROM tags WHERE tag = ?)",
+            [tag],
+        )?;
+        let deleted = tx.execute("DELETE FROM tags WHERE tag = ?", [tag])?;
+        tx.commit()?;
+        Ok(deleted as u64)
     }
-}
 
-fn convert_timestamp(timestamp: i64) -> Result<DateTime<Utc>, BookError> {
-    match Utc.timestamp_opt(timestamp, 0) {
-        chrono::LocalResult::Single(dt) => Ok(dt),
-        _ => Err(BookError::Generic(
-            "Invalid timestamp conversion".to_owned(),
-        )),
+    fn rename_author(&mut self, from: &str, to: &str) -> Result<u64> {
+        rename_book_names(&mut self.conn, "authors", "name", from, to)
     }
-}
 
-fn validate_book(book: &Book) -> Result<(), BookError> {
-    if book.title == "" || book.lang == "" || book.isbn == "" || book.authors.is_empty() {
-        return Err(BookError::InvalidBook {
-            field: String::from("title isbn lang authors"),
-            reason: String::from("empty value is not valid"),
-        });
+    fn tags_for_books(&mut self, ids: &[i64]) -> Result<HashMap<i64, Vec<String>>> {
+        if ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let query = format!(
+            r#"SELECT B.id, T.tag FROM books AS B LEFT JOIN tags AS T ON T.book_id = B.id
+            WHERE B.id IN ({}) ORDER BY B.id, T.tag COLLATE {} ASC"#,
+            placeholders(ids.len()),
+            NAME_COLLATION
+        );
+        let mut stmt = self.conn.prepare(&query)?;
+        let rows = stmt.query_map(params_from_iter(ids.iter()), |row| {
+            Ok((
+                row.get::<usize, i64>(0)?,
+                row.get::<usize, Option<String>>(1)?,
+            ))
+        })?;
+
+        let mut tags: HashMap<i64, Vec<String>> = HashMap::new();
+        for row in rows {
+            let (id, tag) = row?;
+            let entry = tags.entry(id).or_default();
+            entry.extend(tag);
+        }
+
+        Ok(tags)
     }
-    Ok(())
-}
 
-struct QueryBuilder<'a> {
-    query: &'a str,
-    text: &'a str,
-    filter: Option<String>,
-    skipped: &'a u64,
-    sort_limit: String,
-    search_params: Option<Vec<String>>,
-    params: Option<Vec<&'a dyn ToSql>>,
-}
+    fn publish_year_range(&mut self) -> Result<Option<(i32, i32)>> {
+        let query = format!(
+            "SELECT MIN(publish_date), MAX(publish_date) FROM books WHERE {} AND publish_date IS NOT NULL",
+            NOT_TRASHED
+        );
+        let range: (Option<i64>, Option<i64>) = self.conn.query_row(&query, [], |row| {
+            Ok((
+                row.get::<usize, Option<i64>>(0)?,
+                row.get::<usize, Option<i64>>(1)?,
+            ))
+        })?;
 
-impl<'a> QueryBuilder<'a> {
-    fn new(query: &'a str, config: &'a SearchConfig<ConfigInitialized>) -> Self {
-        let mut sf = "".to_string();
-
-        if let Some(sort) = config.get_sort_desc() {
-            if !sort.is_empty() {
-                sf.push_str("ORDER BY");
-                for d in sort {
-                    match d.1 {
-                        SortOrder::Asc => sf.push_str(format!(" {} ASC,", d.0).as_ref()),
-                        SortOrder::Desc => sf.push_str(format!(" {} DESC,", d.0).as_ref()),
-                    }
-                }
-                sf.pop();
-                sf.push(' ');
+        match range {
+            (Some(min), Some(max)) => Ok(Some((
+                convert_timestamp(min)?.year(),
+                convert_timestamp(max)?.year(),
+            ))),
+            _ => Ok(None),
+        }
+    }
+
+    fn title_duplicates(&mut self) -> Result<Vec<(String, Vec<Book>)>> {
+        let mut groups: BTreeMap<String, Vec<i64>> = BTreeMap::new();
+        {
+            let query = format!(
+                "SELECT id, title FROM books WHERE {} ORDER BY id",
+                NOT_TRASHED
+            );
+            let mut stmt = self.conn.prepare(&query)?;
+            let rows = stmt.query_map([], |row| {
+                Ok((row.get::<usize, i64>(0)?, row.get::<usize, String>(1)?))
+            })?;
+            for row in rows {
+                let (id, title) = row?;
+                groups
+                    .entry(title.trim().to_lowercase())
+                    .or_default()
+                    .push(id);
             }
         }
 
-        let mut skipped = &0u64;
-        if let Some(l) = config.get_take() {
-            match config.get_skip_page() {
-                Some(s) if *s > 0 => {
-                    sf.push_str(format!("LIMIT {}, {}", l, s).as_ref());
-                    skipped = s;
+        let mut duplicates = Vec::new();
+        for (title, ids) in groups.into_iter().filter(|(_, ids)| ids.len() > 1) {
+            let books = ids
+                .into_iter()
+                .map(|id| load_book(&self.conn, id))
+                .collect::<Result<Vec<Book>>>()?;
+            duplicates.push((title, books));
+        }
+
+        Ok(duplicates)
+    }
+
+    fn find_duplicates(&mut self) -> Result<Vec<Vec<Book>>> {
+        let mut groups: Vec<Vec<i64>> = Vec::new();
+        {
+            let query = format!(
+                r#"SELECT id, isbn, title, (SELECT name FROM authors WHERE book_id = books.id
+                ORDER BY name COLLATE {} LIMIT 1) FROM books WHERE {} ORDER BY id"#,
+                NAME_COLLATION, NOT_TRASHED
+            );
+            let mut stmt = self.conn.prepare(&query)?;
+            let rows = stmt.query_map([], |row| {
+                Ok((
+                    row.get::<usize, i64>(0)?,
+                    row.get::<usize, String>(1)?,
+                    row.get::<usize, String>(2)?,
+                    row.get::<usize, Option<String>>(3)?,
+                ))
+            })?;
+
+            let mut index: HashMap<String, usize> = HashMap::new();
+            for row in rows {
+                let (id, isbn, title, author) = row?;
+                let isbn = isbn.trim();
+                let key = match normalize_isbn(isbn) {
+                    Ok(n) if !n.is_empty() => format!("isbn:{}", n),
+                    // Invalid ISBNs stored before validation are compared as they are
+                    _ if !isbn.is_empty() => format!("isbn:{}", isbn),
+                    _ => format!(
+                        "title:{}\n{}",
+                        title.trim().to_lowercase(),
+                        author.unwrap_or_default().trim().to_lowercase()
+                    ),
+                };
+                match index.get(&key) {
+                    Some(&i) => groups[i].push(id),
+                    None => {
+                        index.insert(key, groups.len());
+                        groups.push(vec![id]);
+                    }
                 }
-                _ => sf.push_str(format!("LIMIT {}", l).as_ref()),
             }
         }
 
-        Self {
-            query,
-            text: config.get_text(),
-            skipped,
-            filter: None,
-            search_params: None,
-            sort_limit: sf,
-            params: None,
+        groups
+            .into_iter()
+            .filter(|ids| ids.len() > 1)
+            .map(|ids| {
+                ids.into_iter()
+                    .map(|id| load_book(&self.conn, id))
+                    .collect::<Result<Vec<Book>>>()
+            })
+            .collect()
+    }
+
+    fn status_counts(&mut self) -> Result<Vec<(ReadingStatus, u64)>> {
+        let query = format!(
+            "SELECT reading_status, COUNT(*) FROM books WHERE {} GROUP BY reading_status",
+            NOT_TRASHED
+        );
+        let mut stmt = self.conn.prepare(&query)?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<usize, Option<String>>(0)?,
+                row.get::<usize, u64>(1)?,
+            ))
+        })?;
+
+        let mut counts: Vec<(ReadingStatus, u64)> =
+            ReadingStatus::ALL.into_iter().map(|s| (s, 0)).collect();
+        for row in rows {
+            let (status, count) = row?;
+            let status = status
+                .map(|s| ReadingStatus::from_str_or_default(&s))
+                .unwrap_or_default();
+            if let Some(c) = counts.iter_mut().find(|c| c.0 == status) {
+                c.1 += count;
+            }
         }
+
+        Ok(counts)
     }
 
-    /// Use given function to construct the where clause.
-    /// Uses first value of tuple to construct the where clause ` WHERE [first value of tuple]` and
-    /// second argument is used as parameter in the given order.
-    /// Use either `use_params` or `use_where_clause`.
-    ///
-    /// Example:
-    /// ```rust
-    /// builder.use_where_clause(|txt| ("name LIKE ? AND typ = ? ".to_string(), vec![txt.to_owned(), "employee".to_owned()]));
-    /// ```
-    fn use_where_clause<F>(&mut self, transform: F) -> Result<()>
-    where
-        F: FnOnce(&str) -> (String, Vec<String>),
-    {
-        if self.params.is_some() {
-            return Err(BookError::Generic(
-                "Either use 'use_where_clause' or 'use_params'".to_owned(),
-            ));
+    fn statistics(&mut self) -> Result<LibraryStats> {
+        fn grouped<K: rusqlite::types::FromSql>(
+            conn: &Connection,
+            query: &str,
+        ) -> Result<Vec<(K, u64)>> {
+            let mut stmt = conn.prepare(query)?;
+            let rows = stmt.query_map([], |row| Ok((row.get::<usize, K>(0)?, row.get(1)?)))?;
+            Ok(rows.collect::<rusqlite::Result<Vec<(K, u64)>>>()?)
         }
 
-        if !self.text.is_empty() {
-            let fl = transform(self.text);
-            if fl.0.is_empty() {
-                return Ok(());
-            }
+        let active = format!("SELECT id FROM books WHERE {}", NOT_TRASHED);
+        Ok(LibraryStats {
+            total_books: self.conn.query_row(
+                &format!("SELECT COUNT(*) FROM books WHERE {}", NOT_TRASHED),
+                [],
+                |row| row.get(0),
+            )?,
+            books_by_lang: grouped(
+                &self.conn,
+                &format!(
+                    "SELECT lang, COUNT(*) FROM books WHERE {} GROUP BY lang",
+                    NOT_TRASHED
+                ),
+            )?
+            .into_iter()
+            .collect(),
+            top_tags: grouped(
+                &self.conn,
+                &format!(
+                    "SELECT tag, COUNT(*) AS c FROM tags WHERE book_id IN ({}) GROUP BY tag ORDER BY c DESC, tag LIMIT {}",
+                    active, TOP_STATISTICS
+                ),
+            )?,
+            top_authors: grouped(
+                &self.conn,
+                &format!(
+                    "SELECT name, COUNT(*) AS c FROM authors WHERE book_id IN ({}) GROUP BY name ORDER BY c DESC, name LIMIT {}",
+                    active, TOP_STATISTICS
+                ),
+            )?,
+            books_by_decade: grouped(
+                &self.conn,
+                &format!(
+                    r#"SELECT CAST(strftime('%Y', publish_date, 'unixepoch') AS INTEGER) / 10 * 10 AS decade, COUNT(*)
+                    FROM books WHERE {} AND publish_date IS NOT NULL GROUP BY decade ORDER BY decade"#,
+                    NOT_TRASHED
+                ),
+            )?,
+        })
+    }
 
-            self.filter = Some(format!("WHERE {}", fl.0));
-            self.search_params = Some(fl.1);
+    fn get_cover_references(&mut self) -> Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT DISTINCT cover_img FROM books WHERE cover_img IS NOT NULL")?;
+        let rows = stmt.query_map([], |row| row.get::<usize, String>(0))?;
+
+        let mut covers: Vec<String> = Vec::new();
+        for cover in rows {
+            covers.push(cover?);
         }
 
-        Ok(())
+        Ok(covers)
     }
 
-    /// Use given parameters for constructed query. Use either `use_params` or `use_where_clause`.
-    fn use_params(&mut self, params: Vec<&'a dyn ToSql>) -> Result<()> {
-        if self.filter.is_some() {
-            return Err(BookError::Generic(
-                "Either use 'use_where_clause' or 'use_params'".to_owned(),
-            ));
+    fn get_wal_autocheckpoint(&mut self) -> Result<i64> {
+        Ok(self
+            .conn
+            .pragma_query_value(None, "wal_autocheckpoint", |row| row.get(0))?)
+    }
+
+    fn set_wal_autocheckpoint(&mut self, pages: i64) -> Result<()> {
+        if pages < 0 {
+            return Err(BookError::Generic(format!(
+                "WAL auto-checkpoint pages must not be negative: {}",
+                pages
+            )));
         }
 
-        self.params = Some(params);
+        self.conn.pragma_update(None, "wal_autocheckpoint", pages)?;
         Ok(())
     }
 
-    /// Fetch queries the database with given connection and fills passed result struct.
-    fn fetch<T, F>(&'a self, conn: &Connection, result: &mut StoreResult<T>, map: F) -> Result<()>
-    where
-        F: FnMut(&rusqlite::Row) -> rusqlite::Result<T>,
-    {
-        let mut query = self.query.to_owned();
+    fn vacuum_if_needed(&mut self, threshold_bytes: u64) -> Result<bool> {
+        if self.conn.is_readonly(DatabaseName::Main)? {
+            return Ok(false);
+        }
+
+        let pragma = |name: &str| -> Result<u64> {
+            Ok(self.conn.pragma_query_value(None, name, |row| row.get(0))?)
+        };
 
-        if let Some(f) = &self.filter {
-            query.push(' ');
-            query.push_str(f);
+        let page_count = pragma("page_count")?;
+        let free_pages = pragma("freelist_count")?;
+        if page_count == 0
+            || page_count * pragma("page_size")? <= threshold_bytes
+            || (free_pages as f64 / page_count as f64) < VACUUM_FREE_PAGE_FRACTION
+        {
+            return Ok(false);
         }
 
-        let count = if let Some(p) = &self.search_params {
-            conn.query_row(
-                format!("SELECT COUNT(*) FROM ({});", query).as_ref(),
-                &p.iter()
-                    .map(|s| s as &dyn rusqlite::ToSql)
-                    .collect::<Vec<&dyn ToSql>>()[..],
-                |row| row.get::<usize, u64>(0),
-            )?
+        // Changing auto_vacuum of an existing database only takes effect after a
+        // full VACUUM, afterwards free pages can be reclaimed incrementally.
+        if pragma("auto_vacuum")? != 2 {
+            self.conn
+                .pragma_update(None, "auto_vacuum", "incremental")?;
+            self.conn.execute_batch("VACUUM")?;
         } else {
-            conn.query_row(
-                format!("SELECT COUNT(*) FROM ({});", query).as_ref(),
-                [],
-                |row| row.get::<usize, u64>(0),
-            )?
-        };
+            // Each freed page is reported as a row, so the statement must be stepped to the end.
+            let mut stmt = self.conn.prepare("PRAGMA incremental_vacuum")?;
+            let mut rows = stmt.query([])?;
+            while rows.next()?.is_some() {}
+        }
 
-        query.push(' ');
-        query.push_str(&self.sort_limit);
+        Ok(true)
+    }
 
-        let mut clause_params: Vec<&dyn ToSql> = Vec::new();
-        let all_params = {
-            if let Some(e) = &self.search_params {
-                for (pos, _) in e.iter().enumerate() {
-                    clause_params.push(&e[pos]);
-                }
-                &clause_params[..]
-            } else if let Some(p) = &self.params {
-                &p[..]
-            } else {
-                params![]
-            }
-        };
+    fn compact(&mut self) -> Result<()> {
+        let checkpoint =
+            |conn: &Connection| conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()));
 
-        let mut stmt = conn.prepare(&query)?;
+        checkpoint(&self.conn)?;
+        self.conn.execute_batch("VACUUM")?;
+        // In WAL mode the rebuilt database is written to the WAL first
+        checkpoint(&self.conn)?;
+        Ok(())
+    }
 
-        let res = stmt.query_map(all_params, map)?;
-        result.total = count;
-        result.skipped = *self.skipped;
+    fn clear_all(&mut self) -> Result<()> {
+        // Authors and tags are deleted by the foreign keys of the schema
+        let tx = write_transaction(&mut self.conn)?;
+        tx.execute("DELETE FROM books", [])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn backup_to(&self, dest: &Path) -> Result<()> {
+        self.conn.backup(DatabaseName::Main, dest, None)?;
+        Ok(())
+    }
 
-        for item in res {
-            result.items.push(item?);
+    fn is_healthy(&self) -> bool {
+        self.conn.is_autocommit()
+            && self
+                .conn
+                .query_row("SELECT 1", [], |row| row.get::<_, i64>(0))
+                .is_ok()
+    }
+
+    fn get_book(&mut self, id: i64) -> Result<Book> {
+        load_book(&self.conn, id)
+    }
+
+    fn get_book_by_isbn(&mut self, isbn: &str) -> Result<Book> {
+        let isbn = normalize_isbn(isbn)?;
+        if isbn.is_empty() {
+            return Err(BookError::NotFound);
         }
 
-        Ok(())
+        let query = format!(
+            "{} WHERE isbn = ?1 AND {} ORDER BY id LIMIT 1",
+            SELECT_BOOKS_QUERY, NOT_TRASHED
+        );
+        Ok(self
+            .conn
+            .query_row(&query, [&isbn], |row| map_book_row(&self.conn, row))?)
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::SqliteStore;
-    use crate::books::models::SearchConfig;
-    use crate::books::models::{Book, BookDB};
-    use chrono::prelude::*;
-    use chrono::Utc;
-    use std::error::Error;
+fn load_book(conn: &Connection, id: i64) -> Result<Book> {
+    let query = format!("{} WHERE id = ?1", SELECT_BOOKS_QUERY);
 
-    type Result<T = (), E = Box<dyn Error>> = std::result::Result<T, E>;
+    let book = conn.query_row(&query, [id], |row| map_book_row(conn, row))?;
 
-    macro_rules! cmp_book {
-        (@Vec $a:expr, $b:expr, $comment:literal) => {{
-            let mut aa: Vec<String> = $a.clone();
-            let mut bb: Vec<String> = $b.clone();
-            aa.sort();
-            bb.sort();
-            assert_eq!(aa, bb);
-        }};
-        ($a:expr, $b:expr) => {
-            assert_eq!($a.id, $b.id, "Book id mismatch");
-            assert_eq!($a.cover_img, $b.cover_img, "Cover image mismatch");
-            assert_eq!($a.description, $b.description, "Description mismatch");
-            assert_eq!($a.isbn, $b.isbn, "ISBN mismatch");
-            assert_eq!($a.lang, $b.lang, "Language mismatch");
-            assert_eq!($a.title, $b.title, "Title mismatch");
-            assert_eq!($a.sub_title, $b.sub_title, "Sub title mismatch");
-            assert_eq!($a.publisher, $b.publisher, "Publisher mismatch");
-            assert_eq!($a.publish_date, $b.publish_date, "Publisher date mismatch");
+    Ok(book)
+}
 
-            cmp_book!(@Vec $a.authors, $b.authors, "Authors mismatched");
+/// Creates a query builder for books, which applies the search text and all filters of the given config.
+/// The search text is parsed by [SearchQuery], each term is matched against the full-text index and
+/// results are ranked by relevance if no sort order is given. Terms shorter than a trigram fall back
+/// to a `LIKE` search.
+fn books_query_builder(search: &SearchConfig<ConfigInitialized>) -> Result<QueryBuilder<'_>> {
+    let mut builder = QueryBuilder::new(SELECT_BOOKS_QUERY, BOOK_SORT_COLUMNS, search)?;
+    builder.use_keyset("id");
+    builder.use_condition(NOT_TRASHED.to_owned(), vec![]);
 
-            assert_eq!($a.tags.is_some(), $b.tags.is_some(), "Tags mismatch");
-            if $a.tags.is_some() {
-                cmp_book!(@Vec $a.tags.as_ref().unwrap(), $b.tags.as_ref().unwrap(), "Tags mismatched");
-            }
+    let columns = search.get_search_columns();
+    let query = SearchQuery::parse(search.get_text());
+    builder.use_where_clause(|_| search_query_clause(&query, columns))?;
 
-        };
+    if let Some(expr) = fts_rank_expr(&query, columns) {
+        builder.use_rank(
+            "(SELECT bm25(books_fts) FROM books_fts WHERE books_fts MATCH ? AND rowid = books.id)"
+                .to_owned(),
+            vec![Value::Text(expr)],
+        );
     }
 
-    macro_rules! cmp_vec_books {
-        ($testee:expr, $expected:expr) => {
-            assert!($testee.len() == $expected.len(), "Books count mismatch");
+    // Deserialized configs are not normalized by `build`
+    if let Some(tags) = search.get_tags_any().filter(|t| !t.is_empty()) {
+        builder.use_condition(
+            format!(
+                "EXISTS (SELECT 1 FROM tags WHERE book_id = books.id AND tag IN ({}))",
+                placeholders(tags.len())
+            ),
+            tags.iter().map(|t| Value::Text(t.clone())).collect(),
+        );
+    }
 
-            $expected.sort_by(|a, b| a.id.cmp(&b.id));
-            $testee.sort_by(|a, b| a.id.cmp(&b.id));
+    for tag in search.get_tags_all().into_iter().flatten() {
+        builder.use_condition(
+            "EXISTS (SELECT 1 FROM tags WHERE book_id = books.id AND tag = ?)".to_owned(),
+            vec![Value::Text(tag.clone())],
+        );
+    }
 
-            for (pos, item) in $testee.iter().enumerate() {
-                cmp_book!(item, $expected[pos]);
-            }
-        };
+    if let Some(author) = search.get_author() {
+        builder.use_condition(
+            "EXISTS (SELECT 1 FROM authors WHERE book_id = books.id AND name = ?)".to_owned(),
+            vec![Value::Text(author.to_owned())],
+        );
     }
 
-    #[test]
-    fn fetch_books() -> Result {
-        let mut db = SqliteStore::new("db_file")?;
+    if let Some(status) = search.get_reading_status() {
+        builder.use_condition(
+            "reading_status = ?".to_owned(),
+            vec![Value::Text(status.as_str().to_owned())],
+        );
+    }
 
-        let mut books = db.fetch_books(SearchConfig::new("").build())?;
-        assert_eq!(books.total, 3);
-        assert_eq!(books.skipped, 0);
-        assert_eq!(books.items.len(), 3);
+    if let Some(lang) = search.get_lang() {
+        builder.use_condition("lang = ?".to_owned(), vec![Value::Text(lang.to_owned())]);
+    }
 
-        cmp_vec_books!(
-            books.items,
-            vec![Book {
-                authors: vec!["David Lagercrantz".to_owned()],
-                cover_img: None,
-                description: Some("Lisbeth Salander is an unstoppable force!".to_owned()),
-                isbn: "9780857056429".to_owned(),
-                lang: "EN".to_owned(),
-                tags: Some(vec!["Thriller".to_owned(), "Suspense".to_owned()]),
-                title: "The Girl Who Takes an Eye for an Eye".to_owned(),
-                sub_title: None,
-                publisher: Some("McLehose Press".to_owned()),
-                publish_date: Some(Utc.timestamp_opt(1483523713, 0).unwrap()),
-                id: 1,
+    if let Some(format) = search.get_format() {
+        builder.use_condition(
+            "format = ?".to_owned(),
+            vec![Value::Text(format.to_owned())],
+        );
+    }
 
-                ..Default::default()
-            }, Book {
-                authors: vec!["Jochen Schiller".to_owned()],
-                cover_img: None,
-                description: Some("Explains mobile communications in details.".to_owned()),
-                isbn: "9780321123817".to_owned(),
-                lang: "EN".to_owned(),
-                tags: Some(vec!["Data Transmission Systems".to_owned(), "Wireless".to_owned(), "Communications".to_owned()]),
-                title: "Mobile Communications".to_owned(),
-                sub_title: Some("Second Edition".to_owned()),
-                publisher: Some("Addison Wesley".to_owned()),
-                publish_date: Some(Utc.timestamp_opt(1062150913, 0).unwrap()),
-                id: 2,
+    if let Some(series) = search.get_series() {
+        builder.use_condition(
+            "series = ?".to_owned(),
+            vec![Value::Text(series.to_owned())],
+        );
+    }
 
-                ..Default::default()
-            }, Book {
-                authors: vec!["Richard Dawkins".to_owned()],
-                cover_img: None,
-                description: Some("Richard Dawkins provozierendes Buch beseitigt jeden Zweifel an Darwins Theorie.".to_owned()),
-                isbn: "9783550087653".to_owned(),
-                lang: "DE".to_owned(),
-                tags: Some(vec!["Wissenschaft".to_owned(), "Biologie".to_owned(), "Religion".to_owned()]),
-                title: "Es gibt keine Schöpfung".to_owned(),
-                sub_title: None,
-                publisher: Some("Ullstein Verlag".to_owned()),
-                publish_date: Some(Utc.timestamp_opt(1283075713, 0).unwrap()),
-                id: 3,
+    if let Some(owned) = search.get_owned() {
+        builder.use_condition("owned = ?".to_owned(), vec![Value::Integer(owned.into())]);
+    }
 
-                ..Default::default()
-            }]
+    let (from, to) = search.get_publish_range();
+    if let Some(from) = from {
+        builder.use_condition(
+            "publish_date >= ?".to_owned(),
+            vec![Value::Integer(from.timestamp())],
+        );
+    }
+    if let Some(to) = to {
+        builder.use_condition(
+            "publish_date <= ?".to_owned(),
+            vec![Value::Integer(to.timestamp())],
         );
+    }
 
-        let mut partial_books =
-            db.fetch_books(SearchConfig::new("").use_take(2).use_skip_page(1).build())?;
-        assert_eq!(partial_books.total, 3);
-        assert_eq!(partial_books.skipped, 1);
-        assert_eq!(partial_books.items.len(), 1);
+    let (from, to) = search.get_acquired_range();
+    if let Some(from) = from {
+        builder.use_condition(
+            "acquired_date >= ?".to_owned(),
+            vec![Value::Integer(from.timestamp())],
+        );
+    }
+    if let Some(to) = to {
+        builder.use_condition(
+            "acquired_date <= ?".to_owned(),
+            vec![Value::Integer(to.timestamp())],
+        );
+    }
 
-        cmp_vec_books!(partial_books.items, vec![Book {
-            authors: vec!["Richard Dawkins".to_owned()],
-            cover_img: None,
-            description: Some("Richard Dawkins provozierendes Buch beseitigt jeden Zweifel an Darwins Theorie.".to_owned()),
-            isbn: "9783550087653".to_owned(),
-            lang: "DE".to_owned(),
-            tags: Some(vec!["Wissenschaft".to_owned(), "Biologie".to_owned(), "Religion".to_owned()]),
-            title: "Es gibt keine Schöpfung".to_owned(),
-            sub_title: None,
-            publisher: Some("Ullstein Verlag".to_owned()),
-            publish_date: Some(Utc.timestamp_opt(1283075713, 0).unwrap()),
-            id: 3,
+    Ok(builder)
+}
 
-            ..Default::default()
-        }]);
+/// Returns the condition matching all books which satisfy the given query. Terms within a group
+/// are combined with `OR`, groups and excluded terms with `AND`.
+fn search_query_clause(query: &SearchQuery, columns: &[SearchColumn]) -> (String, Vec<String>) {
+    if query.is_empty() {
+        return (String::new(), vec![]);
+    }
 
-        Ok(())
+    let mut conditions: Vec<String> = Vec::new();
+    let mut params: Vec<String> = Vec::new();
+
+    for group in &query.groups {
+        let alternatives = group
+            .iter()
+            .map(|t| {
+                let (clause, p) = search_term_clause(t, columns);
+                params.extend(p);
+                clause
+            })
+            .collect::<Vec<String>>();
+        conditions.push(format!("({})", alternatives.join(" OR ")));
     }
 
-    #[test]
-    fn delete_book_successfully() -> Result {
-        let mut db = SqliteStore::new("db_file")?;
+    for term in &query.excluded {
+        let (clause, p) = search_term_clause(term, columns);
+        conditions.push(format!("NOT {}", clause));
+        params.extend(p);
+    }
 
+    (conditions.join(" AND "), params)
+}
+
+/// Returns the condition matching all books which contain the given term in one of the columns.
+fn search_term_clause(term: &str, columns: &[SearchColumn]) -> (String, Vec<String>) {
+    if let Some(expr) = fts_match_expr(term, columns) {
+        return (
+            "id IN (SELECT rowid FROM books_fts WHERE books_fts MATCH ?)".to_owned(),
+            vec![expr],
+        );
+    }
+
+    let conditions = columns
+        .iter()
+        .map(|c| format!(r"{} LIKE ? ESCAPE '\'", search_column(c)))
+        .collect::<Vec<String>>()
+        .join(" OR ");
+    (
+        format!(
+            r#"id IN (
+            SELECT DISTINCT B.id
+            FROM books as B
+                LEFT JOIN authors AS A ON A.book_id = B.id
+                LEFT JOIN tags AS T ON T.book_id = B.id
+            WHERE {}
+        )"#,
+            conditions
+        ),
+        vec![format!("%{}%", escape_like(term)); columns.len()],
+    )
+}
+
+/// Returns the `MATCH` expression used to rank the books found by the given query. Only groups
+/// whose terms are all matched by the full-text index are considered, `None` if there is none.
+fn fts_rank_expr(query: &SearchQuery, columns: &[SearchColumn]) -> Option<String> {
+    let groups = query
+        .groups
+        .iter()
+        .filter_map(|g| {
+            g.iter()
+                .map(|t| fts_match_expr(t, columns))
+                .collect::<Option<Vec<String>>>()
+        })
+        .map(|g| format!("({})", g.join(" OR ")))
+        .collect::<Vec<String>>();
+
+    if groups.is_empty() {
+        return None;
+    }
+    Some(groups.join(" AND "))
+}
+
+/// Returns the `MATCH` expression of the full-text index, which searches the given text as
+/// phrase within the given columns. `None` if the text is too short for the trigram tokenizer.
+fn fts_match_expr(text: &str, columns: &[SearchColumn]) -> Option<String> {
+    if text.chars().count() < FTS_MIN_CHARS {
+        return None;
+    }
+
+    let columns = columns
+        .iter()
+        .map(fts_column)
+        .collect::<Vec<&str>>()
+        .join(" ");
+    Some(format!(
+        "{{{}}} : \"{}\"",
+        columns,
+        text.replace('"', "\"\"")
+    ))
+}
+
+/// Returns the column of the full-text index for the given search column.
+fn fts_column(column: &SearchColumn) -> &'static str {
+    match column {
+        SearchColumn::Title => "title",
+        SearchColumn::SubTitle => "sub_title",
+        SearchColumn::Publisher => "publisher",
+        SearchColumn::Isbn => "isbn",
+        SearchColumn::Description => "description",
+        SearchColumn::Authors => "authors",
+        SearchColumn::Tags => "tags",
+        SearchColumn::Notes => "notes",
+    }
+}
+
+/// Returns the column of the text search subquery for the given search column.
+fn search_column(column: &SearchColumn) -> &'static str {
+    match column {
+        SearchColumn::Title => "B.title",
+        SearchColumn::SubTitle => "B.sub_title",
+        SearchColumn::Publisher => "B.publisher",
+        SearchColumn::Isbn => "B.isbn",
+        SearchColumn::Description => "B.description",
+        SearchColumn::Authors => "A.name",
+        SearchColumn::Tags => "T.tag",
+        SearchColumn::Notes => "B.notes",
+    }
+}
+
+/// Ranks the values of given column starting with the search text first, if the config
+/// asks for prefix matches. Values which only contain the text follow them.
+fn use_prefix_rank(builder: &mut QueryBuilder, column: &str) {
+    let text = builder.config.get_text();
+    if builder.config.get_prefix() && !text.is_empty() {
+        let param = Value::Text(format!("{}%", escape_like(text)));
+        builder.use_rank(format!(r"({} NOT LIKE ? ESCAPE '\')", column), vec![param]);
+    }
+}
+
+/// Escapes the wildcards `%` and `_` of a search text, so it is matched literally by a
+/// pattern with `ESCAPE '\'`.
+fn escape_like(text: &str) -> String {
+    text.replace('\\', r"\\")
+        .replace('%', r"\%")
+        .replace('_', r"\_")
+}
+
+/// Renames the value `from` of the given column of the `authors` or `tags` table to `to`
+/// in all books. If a book already has `to`, only one entry is kept. Returns the number of
+/// affected books.
+fn rename_book_names(
+    conn: &mut Connection,
+    table: &str,
+    column: &str,
+    from: &str,
+    to: &str,
+) -> Result<u64> {
+    let to = to.trim();
+    if to.is_empty() {
+        return Err(BookError::InvalidBook {
+            field: column.to_owned(),
+            reason: String::from("empty value is not valid"),
+        });
+    }
+    if from == to {
+        return Ok(0);
+    }
+
+    let tx = write_transaction(conn)?;
+    let affected = tx.execute(
+        &format!(
+            "UPDATE books SET updated = unixepoch() WHERE id IN (SELECT book_id FROM {} WHERE {} = ?)",
+            table, column
+        ),
+        [from],
+    )?;
+    tx.execute(
+        &format!("UPDATE {} SET {} = ?1 WHERE {} = ?2", table, column, column),
+        [to, from],
+    )?;
+    // Books which had both names now have the new one twice
+    tx.execute(
+        &format!(
+            "DELETE FROM {0} WHERE {1} = ?1 AND rowid NOT IN (SELECT MIN(rowid) FROM {0} WHERE {1} = ?1 GROUP BY book_id)",
+            table, column
+        ),
+        [to],
+    )?;
+    tx.commit()?;
+
+    Ok(affected as u64)
+}
+
+/// Translates a violation of the unique ISBN index into [BookError::DuplicateIsbn], other
+/// errors are converted as usual.
+fn map_duplicate_isbn(err: rusqlite::Error, isbn: &str) -> BookError {
+    match &err {
+        rusqlite::Error::SqliteFailure(e, Some(msg))
+            if e.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE
+                && msg.contains("books.isbn") =>
+        {
+            BookError::DuplicateIsbn(isbn.to_owned())
+        }
+        _ => err.into(),
+    }
+}
+
+/// Inserts the book with its authors and tags, sets its id and timestamps.
+fn insert_book(conn: &Connection, book: &mut Book, collation: NameCollation) -> Result<()> {
+    validate_book(book)?;
+    validate_rating(book.rating)?;
+    validate_page_count(book.page_count)?;
+    validate_series_index(book.series_index)?;
+    book.isbn = normalize_isbn(&book.isbn)?;
+
+    let mut books_stmt = conn.prepare(r#"INSERT INTO books (cover_img, description, isbn, lang, title, sub_title, publisher, publish_date, reading_status, started_at, finished_at, rating, owned, page_count, format, notes, series, series_index, acquired_date, created, updated)
+    VALUES (:img, :desc, :isbn, :lang , :title, :subt, :pub, :pubd, :status, :started, :finished, :rating, :owned, :pages, :format, :notes, :series, :sidx, :acquired, unixepoch(), unixepoch())"#)?;
+
+    let book_id = books_stmt
+        .insert(named_params! {
+            ":img": book.cover_img,
+            ":desc": book.description,
+            ":isbn": book.isbn,
+            ":lang": book.lang,
+            ":title": book.title,
+            ":subt": book.sub_title,
+            ":pub": book.publisher,
+            ":pubd": book.publish_date.as_ref().map(|d| d.timestamp()),
+            ":status": book.reading_status.as_str(),
+            ":started": book.started_at.as_ref().map(|d| d.timestamp()),
+            ":finished": book.finished_at.as_ref().map(|d| d.timestamp()),
+            ":rating": book.rating,
+            ":owned": book.owned,
+            ":pages": book.page_count,
+            ":format": book.format,
+            ":notes": book.notes,
+            ":series": book.series,
+            ":sidx": book.series_index,
+            ":acquired": book.acquired_date.as_ref().map(|d| d.timestamp())
+        })
+        .map_err(|e| map_duplicate_isbn(e, &book.isbn))?;
+    drop(books_stmt);
+
+    if book_id <= 0 {
+        return Err(BookError::Generic(format!(
+            "return row id is invalid: {}",
+            book_id
+        )));
+    }
+    book.id = book_id;
+
+    {
+        let mut authors_stmt =
+            conn.prepare("INSERT INTO authors (book_id, name) VALUES (?1, ?2)")?;
+        for author in &book.authors {
+            authors_stmt.execute(params![book_id, author])?;
+        }
+
+        {
+            if let Some(tags) = &book.tags {
+                let mut tags_stmt =
+                    conn.prepare("INSERT INTO tags (book_id, tag) VALUES (?1, ?2)")?;
+                for tag in tags {
+                    tags_stmt.execute(params![book_id, tag])?;
+                }
+            }
+        }
+    }
+
+    let dates: (i64, i64) = conn.query_row(
+        "SELECT created, updated FROM books WHERE id = ?1",
+        [&book_id],
+        |row| Ok((row.get::<usize, i64>(0)?, row.get::<usize, i64>(1)?)),
+    )?;
+
+    book.created = convert_timestamp(dates.0)?;
+    book.updated = convert_timestamp(dates.1)?;
+
+    book.authors.sort_by(|a, b| collation.compare(a, b));
+    if let Some(tags) = book.tags.as_mut() {
+        tags.sort_by(|a, b| collation.compare(a, b));
+    }
+
+    Ok(())
+}
+
+/// Writes all fields of the given book, authors and tags are replaced. Modified fields
+/// are tracked against the stored book.
+fn write_book_update(conn: &Connection, book: &mut Book, collation: NameCollation) -> Result<()> {
+    let query = r#"UPDATE books SET cover_img = :img, description = :desc, isbn = :isbn, lang = :lang, 
+        title = :title, sub_title = :sub, publisher = :pub, 'publish_date' = :pdate, reading_status = :status,
+        started_at = :started, finished_at = :finished, rating = :rating, owned = :owned,
+        page_count = :pages, format = :format, notes = :notes,
+        series = :series, series_index = :sidx, acquired_date = :acquired, updated = unixepoch(),
+        field_updated = :fupd
+        WHERE id = :id AND updated = :expected"#;
+
+    validate_rating(book.rating)?;
+    validate_page_count(book.page_count)?;
+    validate_series_index(book.series_index)?;
+    let stored = load_book(conn, book.id)?;
+    book.field_updated = track_field_updates(&stored, book, Utc::now());
+    let field_updated = book
+        .field_updated
+        .as_ref()
+        .map(serde_json::to_string)
+        .transpose()
+        .map_err(|e| BookError::Generic(format!("Failed to serialize field_updated: {}", e)))?;
+
+    let updated = conn
+        .execute(
+            query,
+            named_params! {
+                ":img": book.cover_img,
+                ":desc": book.description,
+                ":isbn": book.isbn,
+                ":lang": book.lang,
+                ":title": book.title,
+                ":sub": book.sub_title,
+                ":pub": book.publisher,
+                ":pdate": book.publish_date.as_ref().map(|d| d.timestamp()),
+                ":status": book.reading_status.as_str(),
+                ":started": book.started_at.as_ref().map(|d| d.timestamp()),
+                ":finished": book.finished_at.as_ref().map(|d| d.timestamp()),
+                ":rating": book.rating,
+                ":owned": book.owned,
+                ":pages": book.page_count,
+                ":format": book.format,
+                ":notes": book.notes,
+                ":series": book.series,
+                ":sidx": book.series_index,
+                ":acquired": book.acquired_date.as_ref().map(|d| d.timestamp()),
+                ":fupd": field_updated,
+                ":id": book.id,
+                ":expected": book.updated.timestamp()
+            },
+        )
+        .map_err(|e| map_duplicate_isbn(e, &book.isbn))?;
+    // The book exists, so someone else updated it in the meantime
+    if updated == 0 {
+        return Err(BookError::Conflict);
+    }
+
+    update_book_tags(conn, book, collation)?;
+    update_book_authors(conn, book, collation)?;
+
+    Ok(())
+}
+
+fn update_book_authors(conn: &Connection, book: &mut Book, collation: NameCollation) -> Result<()> {
+    if book.authors.is_empty() {
+        return Err(BookError::EmptyAuthors);
+    }
+
+    conn.execute("DELETE FROM authors WHERE book_id = ?1", [&book.id])?;
+    book.authors.sort_by(|a, b| collation.compare(a, b));
+
+    let mut stmt = conn.prepare("INSERT INTO authors (book_id, name) VALUES (:id, :name)")?;
+    for a in &book.authors {
+        stmt.execute(named_params! {":id": &book.id, ":name": a})?;
+    }
+
+    Ok(())
+}
+
+fn update_book_tags(conn: &Connection, book: &mut Book, collation: NameCollation) -> Result<()> {
+    conn.execute("DELETE FROM tags WHERE book_id = ?1", [&book.id])?;
+
+    match book.tags.as_mut() {
+        None => return Ok(()),
+        Some(t) if t.is_empty() => {
+            book.tags = None;
+            return Ok(());
+        }
+        Some(_) => (),
+    }
+
+    book.tags
+        .as_mut()
+        .unwrap()
+        .sort_by(|a, b| collation.compare(a, b));
+    book.tags.as_mut().unwrap().dedup();
+
+    let mut stmt = conn.prepare("INSERT INTO tags (book_id, tag) VALUES (:id, :tag)")?;
+    for t in book.tags.as_ref().unwrap() {
+        stmt.execute(named_params! { ":id": &book.id, ":tag": t })?;
+    }
+
+    Ok(())
+}
+
+fn load_authors_of_book(conn: &Connection, id: &i64) -> Result<Vec<String>, rusqlite::Error> {
+    let query = format!(
+        "SELECT name FROM authors WHERE book_id = ?1 ORDER BY name COLLATE {} ASC",
+        NAME_COLLATION
+    );
+
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map([id], |row| row.get::<usize, String>(0))?;
+
+    let mut authors: Vec<String> = Vec::new();
+    for tag in rows {
+        authors.push(tag?);
+    }
+
+    Ok(authors)
+}
+
+fn load_tags_of_book(conn: &Connection, id: &i64) -> Result<Vec<String>, rusqlite::Error> {
+    let query = format!(
+        "SELECT tag FROM tags WHERE book_id = ?1 ORDER BY tag COLLATE {} ASC",
+        NAME_COLLATION
+    );
+
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map([id], |row| row.get::<usize, String>(0))?;
+
+    let mut tags: Vec<String> = Vec::new();
+    for tag in rows {
+        tags.push(tag?);
+    }
+
+    Ok(tags)
+}
+
+/// Loads the values of `column` in `table` for all given book ids with one query per
+/// [MAX_BATCHED_IDS] ids, grouped by book id and ordered like [load_authors_of_book].
+fn load_names_of_books(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    ids: &[i64],
+) -> Result<HashMap<i64, Vec<String>>, rusqlite::Error> {
+    let mut names: HashMap<i64, Vec<String>> = HashMap::new();
+    for chunk in ids.chunks(MAX_BATCHED_IDS) {
+        let query = format!(
+            "SELECT book_id, {0} FROM {1} WHERE book_id IN ({2}) ORDER BY {0} COLLATE {3} ASC",
+            column,
+            table,
+            placeholders(chunk.len()),
+            NAME_COLLATION
+        );
+
+        let mut stmt = conn.prepare(&query)?;
+        let mut rows = stmt.query(params_from_iter(chunk.iter()))?;
+        while let Some(row) = rows.next()? {
+            names.entry(row.get(0)?).or_default().push(row.get(1)?);
+        }
+    }
+
+    Ok(names)
+}
+
+/// Fills in authors and tags of books mapped by [map_book_columns], batched over all
+/// books instead of two queries per book.
+fn load_authors_and_tags(conn: &Connection, books: &mut [Book]) -> Result<(), rusqlite::Error> {
+    let ids: Vec<i64> = books.iter().map(|b| b.id).collect();
+    let mut authors = load_names_of_books(conn, "authors", "name", &ids)?;
+    let mut tags = load_names_of_books(conn, "tags", "tag", &ids)?;
+
+    for book in books.iter_mut() {
+        book.authors = authors.remove(&book.id).unwrap_or_default();
+        book.tags = tags.remove(&book.id);
+    }
+
+    Ok(())
+}
+
+impl From<rusqlite::Error> for BookError {
+    fn from(value: rusqlite::Error) -> Self {
+        // Todo: If necessary transform [rusqlite::Error] errors into database agnostic errors.
+        match value {
+            rusqlite::Error::QueryReturnedNoRows => BookError::NotFound,
+            // The busy timeout has already expired
+            rusqlite::Error::SqliteFailure(e, _)
+                if matches!(e.code, ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked) =>
+            {
+                BookError::Busy
+            }
+            _ => BookError::DBError(value.into()),
+        }
+    }
+}
+
+impl From<rusqlite_migration::Error> for BookError {
+    fn from(value: rusqlite_migration::Error) -> Self {
+        // Todo: If necessary transform [rusqlite_migration::Error] errors into database agnostic errors.
+        BookError::DBError(value.into())
+    }
+}
+
+/// Maps a sqlite row of [SELECT_BOOKS_QUERY] to a Book, authors and tags are
+/// loaded with the given connection. Invalid values result in an error.
+fn map_book_row(conn: &Connection, row: &rusqlite::Row) -> rusqlite::Result<Book> {
+    let mut book = map_book_columns(row)?;
+    book.authors = load_authors_of_book(conn, &book.id)?;
+    book.tags = load_tags_of_book(conn, &book.id).map(|v| match v.len() {
+        0 => None,
+        _ => Some(v),
+    })?;
+
+    Ok(book)
+}
+
+/// Maps the columns of a sqlite row of [SELECT_BOOKS_QUERY] to a Book without authors
+/// and tags, use [load_authors_and_tags] to fill them in for a list of books.
+fn map_book_columns(row: &rusqlite::Row) -> rusqlite::Result<Book> {
+    Ok(Book {
+        authors: Vec::new(),
+        cover_img: row.get("cover_img")?,
+        description: row.get("description")?,
+        isbn: row.get("isbn")?,
+        lang: row.get("lang")?,
+        tags: None,
+        title: row.get("title")?,
+        sub_title: row.get("sub_title")?,
+        publisher: row.get("publisher")?,
+        publish_date: row
+            .get::<&str, i64>("publish_date")
+            .ok()
+            .map(|ts| timestamp_from_row(row, "publish_date", ts))
+            .transpose()?,
+        reading_status: row
+            .get::<&str, Option<String>>("reading_status")?
+            .map(|s| ReadingStatus::from_str_or_default(&s))
+            .unwrap_or_default(),
+        started_at: row
+            .get::<&str, Option<i64>>("started_at")?
+            .map(|ts| timestamp_from_row(row, "started_at", ts))
+            .transpose()?,
+        finished_at: row
+            .get::<&str, Option<i64>>("finished_at")?
+            .map(|ts| timestamp_from_row(row, "finished_at", ts))
+            .transpose()?,
+        rating: row.get("rating")?,
+        owned: row.get("owned")?,
+        page_count: row.get("page_count")?,
+        format: row.get("format")?,
+        notes: row.get("notes")?,
+        series: row.get("series")?,
+        series_index: row.get("series_index")?,
+        acquired_date: row
+            .get::<&str, Option<i64>>("acquired_date")?
+            .map(|ts| timestamp_from_row(row, "acquired_date", ts))
+            .transpose()?,
+        id: row.get("id")?,
+        created: timestamp_from_row(row, "created", row.get("created")?)?,
+        updated: timestamp_from_row(row, "updated", row.get("updated")?)?,
+        field_updated: row
+            .get::<&str, Option<String>>("field_updated")?
+            .and_then(|json| serde_json::from_str(&json).ok()),
+    })
+}
+
+/// Converts the timestamp of the given column, an invalid timestamp results
+/// in a conversion error instead of a panic.
+fn timestamp_from_row(
+    row: &rusqlite::Row,
+    column: &str,
+    timestamp: i64,
+) -> rusqlite::Result<DateTime<Utc>> {
+    convert_timestamp(timestamp).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(
+            row.as_ref().column_index(column).unwrap_or_default(),
+            Type::Integer,
+            Box::new(e),
+        )
+    })
+}
+
+fn convert_timestamp(timestamp: i64) -> Result<DateTime<Utc>, BookError> {
+    match Utc.timestamp_opt(timestamp, 0) {
+        chrono::LocalResult::Single(dt) => Ok(dt),
+        _ => Err(BookError::Generic(
+            "Invalid timestamp conversion".to_owned(),
+        )),
+    }
+}
+
+fn validate_book(book: &Book) -> Result<(), BookError> {
+    if book.title == "" || book.lang == "" || book.isbn == "" || book.authors.is_empty() {
+        return Err(BookError::InvalidBook {
+            field: String::from("title isbn lang authors"),
+            reason: String::from("empty value is not valid"),
+        });
+    }
+    Ok(())
+}
+
+/// Checks that a rating is within 1 to 5 stars.
+fn validate_rating(rating: Option<u8>) -> Result<(), BookError> {
+    match rating {
+        Some(r) if !(1..=5).contains(&r) => Err(BookError::InvalidBook {
+            field: "rating".to_owned(),
+            reason: format!("{} is not between 1 and 5", r),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that a page count is positive.
+fn validate_page_count(page_count: Option<u32>) -> Result<(), BookError> {
+    match page_count {
+        Some(0) => Err(BookError::InvalidBook {
+            field: "page_count".to_owned(),
+            reason: "must be greater than 0".to_owned(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that a series index is a finite, non-negative number.
+fn validate_series_index(index: Option<f32>) -> Result<(), BookError> {
+    match index {
+        Some(i) if !i.is_finite() || i < 0.0 => Err(BookError::InvalidBook {
+            field: "series_index".to_owned(),
+            reason: format!("{} is not a valid position", i),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Sort columns which can be used for keyset pagination, they never contain NULL values.
+const KEYSET_COLUMNS: &[&str] = &["id", "title", "isbn", "lang", "created", "updated"];
+
+/// Columns the books can be sorted by. Sort descriptors come from the frontend and are
+/// interpolated into the query, therefore any other column is rejected.
+const BOOK_SORT_COLUMNS: &[&str] = &[
+    "id",
+    "title",
+    "sub_title",
+    "isbn",
+    "publisher",
+    "publish_date",
+    "lang",
+    "created",
+    "updated",
+    "first_author",
+    "reading_status",
+    "started_at",
+    "finished_at",
+    "rating",
+    "owned",
+    "page_count",
+    "format",
+    "series",
+    "series_index",
+    "acquired_date",
+];
+
+/// Columns the tags can be sorted by, see [BOOK_SORT_COLUMNS].
+const TAG_SORT_COLUMNS: &[&str] = &["tag"];
+
+/// Columns the authors can be sorted by, see [BOOK_SORT_COLUMNS].
+const AUTHOR_SORT_COLUMNS: &[&str] = &["name"];
+
+struct QueryBuilder<'a> {
+    query: &'a str,
+    config: &'a SearchConfig<ConfigInitialized>,
+    filter: Vec<String>,
+    params: Vec<Value>,
+    id_column: Option<&'a str>,
+    rank: Option<(String, Vec<Value>)>,
+}
+
+impl<'a> QueryBuilder<'a> {
+    /// Creates a builder for the given query, fails if the config sorts by a column which
+    /// is not in `sort_columns`.
+    fn new(
+        query: &'a str,
+        sort_columns: &[&str],
+        config: &'a SearchConfig<ConfigInitialized>,
+    ) -> Result<Self> {
+        let sort = config.get_sort_desc().map(|s| &s[..]).unwrap_or(&[]);
+        if let Some(d) = sort.iter().find(|d| !sort_columns.contains(&d.0.as_str())) {
+            return Err(BookError::Generic(format!("Invalid sort column: {}", d.0)));
+        }
+
+        Ok(Self {
+            query,
+            config,
+            filter: Vec::new(),
+            params: Vec::new(),
+            id_column: None,
+            rank: None,
+        })
+    }
+
+    /// Use given function to construct the where clause, only called if the search text is not empty.
+    /// Uses first value of tuple as condition of the where clause ` WHERE ([first value of tuple])` and
+    /// second argument is used as parameter in the given order.
+    ///
+    /// Example:
+    /// ```rust
+    /// builder.use_where_clause(|txt| ("name LIKE ? AND typ = ? ".to_string(), vec![txt.to_owned(), "employee".to_owned()]));
+    /// ```
+    fn use_where_clause<F>(&mut self, transform: F) -> Result<()>
+    where
+        F: FnOnce(&str) -> (String, Vec<String>),
+    {
+        let text = self.config.get_text();
+        if !text.is_empty() {
+            let (clause, params) = transform(text);
+            if clause.is_empty() {
+                return Ok(());
+            }
+
+            self.filter.push(clause);
+            self.params.extend(params.into_iter().map(Value::Text));
+        }
+
+        Ok(())
+    }
+
+    /// Adds a condition to the where clause, regardless of the search text.
+    /// All conditions are combined with `AND`.
+    fn use_condition(&mut self, clause: String, params: Vec<Value>) {
+        self.filter.push(clause);
+        self.params.extend(params);
+    }
+
+    /// Uses given column as unique key of the queried rows. The key is appended as tiebreaker to
+    /// the sort order and enables keyset pagination with a [Cursor].
+    fn use_keyset(&mut self, id_column: &'a str) {
+        self.id_column = Some(id_column);
+    }
+
+    /// Uses given expression to order the rows by relevance, lower values first. Only applied by
+    /// [QueryBuilder::fetch] if neither a sort order nor a cursor is given, the key column remains
+    /// the tiebreaker. Ranked results can't be continued with a cursor.
+    fn use_rank(&mut self, expr: String, params: Vec<Value>) {
+        self.rank = Some((expr, params));
+    }
+
+    /// Returns the rank expression and its parameters if the rows are ordered by relevance.
+    fn ranked(&self) -> Option<&(String, Vec<Value>)> {
+        let sorted = self.config.get_sort_desc().is_some_and(|s| !s.is_empty());
+        match self.config.get_cursor() {
+            None if !sorted => self.rank.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Returns the sort column (`None` for the key column itself) and order used for keyset
+    /// pagination. Keyset pagination requires a key column and at most one sort descriptor
+    /// on a column without NULL values.
+    fn keyset(&self) -> Option<(Option<&'a str>, &'a SortOrder)> {
+        let id = self.id_column?;
+        match self.config.get_sort_desc().map(|s| &s[..]) {
+            None | Some([]) => Some((None, &SortOrder::Asc)),
+            Some([d]) if d.0 == id => Some((None, &d.1)),
+            Some([d]) if KEYSET_COLUMNS.contains(&d.0.as_str()) => Some((Some(d.0.as_str()), &d.1)),
+            _ => None,
+        }
+    }
+
+    /// Builds the `ORDER BY` clause, the key column is appended as tiebreaker if set.
+    /// If `reverse` is set, all sort orders are inverted.
+    fn order_by(&self, reverse: bool) -> String {
+        let mut cols: Vec<String> = Vec::new();
+        let sort = self.config.get_sort_desc().map(|s| &s[..]).unwrap_or(&[]);
+
+        for d in sort {
+            // Books without authors sort last, regardless of the direction. Authors of
+            // different books are compared like the authors of one book.
+            if d.0 == "first_author" {
+                cols.push(format!(
+                    "first_author IS NULL {}",
+                    sql_order_rev(&SortOrder::Asc, reverse)
+                ));
+                cols.push(format!(
+                    "first_author COLLATE {} {}",
+                    NAME_COLLATION,
+                    sql_order_rev(&d.1, reverse)
+                ));
+                continue;
+            }
+            cols.push(format!("{} {}", d.0, sql_order_rev(&d.1, reverse)));
+        }
+
+        if let Some(id) = self.id_column {
+            if !sort.iter().any(|d| d.0 == id) {
+                let order = sort.first().map(|d| &d.1).unwrap_or(&SortOrder::Asc);
+                cols.push(format!("{} {}", id, sql_order_rev(order, reverse)));
+            }
+        }
+
+        if cols.is_empty() {
+            return String::new();
+        }
+        format!("ORDER BY {}", cols.join(", "))
+    }
+
+    /// Counts all rows matching the where clause, pagination is not applied.
+    fn count(&self, conn: &Connection) -> Result<u64> {
+        self.count_where(conn, &self.filter, &self.params)
+    }
+
+    /// Counts all rows matching the given conditions.
+    fn count_where(&self, conn: &Connection, filter: &[String], params: &[Value]) -> Result<u64> {
+        let mut query = self.query.to_owned();
+        push_where_clause(&mut query, filter);
+
+        Ok(conn.query_row(
+            format!("SELECT COUNT(*) FROM ({});", query).as_ref(),
+            params_from_iter(params.iter()),
+            |row| row.get::<usize, u64>(0),
+        )?)
+    }
+
+    /// Fetch queries the database with given connection and fills passed result struct.
+    fn fetch<T, F>(&self, conn: &Connection, result: &mut StoreResult<T>, mut map: F) -> Result<()>
+    where
+        F: FnMut(&rusqlite::Row) -> rusqlite::Result<T>,
+    {
+        let count = self.count(conn)?;
+
+        let rank = self.ranked();
+        let keyset = if rank.is_some() { None } else { self.keyset() };
+        let mut filter = self.filter.clone();
+        let mut params = self.params.clone();
+        let mut skipped = 0u64;
+        let mut limit = String::new();
+        // Number of rows from the start of the page to the end of the result
+        let mut remaining = count;
+
+        if let Some(cursor) = self.config.get_cursor() {
+            let (clause, cursor_params) = self.keyset_condition(cursor, false)?;
+            filter.push(clause);
+            params.extend(cursor_params);
+            remaining = self.count_where(conn, &filter, &params)?;
+
+            if let Some(l) = self.config.get_take() {
+                limit = format!("LIMIT {}", l);
+            }
+        } else if let Some(l) = self.config.get_take() {
+            match self.config.get_skip_page() {
+                Some(s) if *s > 0 => {
+                    limit = format!("LIMIT {} OFFSET {}", l, l * s);
+                    skipped = *s;
+                    remaining = count.saturating_sub(l * s);
+                }
+                _ => limit = format!("LIMIT {}", l),
+            }
+        }
+
+        let order = match rank {
+            // Parameters of the order follow the ones of the where clause
+            Some((expr, rank_params)) => {
+                params.extend(rank_params.iter().cloned());
+                match self.id_column {
+                    Some(id) => format!("ORDER BY {} ASC, {} ASC", expr, id),
+                    None => format!("ORDER BY {} ASC", expr),
+                }
+            }
+            None => self.order_by(false),
+        };
+
+        let mut query = self.query.to_owned();
+        push_where_clause(&mut query, &filter);
+        query.push(' ');
+        query.push_str(&order);
+        query.push(' ');
+        query.push_str(&limit);
+
+        let mut stmt = conn.prepare(&query)?;
+        let mut last: Option<Cursor> = None;
+        let mut rows = 0u64;
+        {
+            let res = stmt.query_map(params_from_iter(params.iter()), |row| {
+                if let (Some((col, _)), Some(id)) = (keyset, self.id_column) {
+                    last = Some(Cursor {
+                        key: match col {
+                            Some(c) => to_cursor_key(row.get::<&str, Value>(c)?),
+                            None => None,
+                        },
+                        id: row.get::<&str, i64>(id)?,
+                    });
+                }
+                map(row)
+            })?;
+
+            for item in res {
+                rows += 1;
+                match item {
+                    Ok(i) => result.items.push(i),
+                    // A single corrupt row must not hide all other rows
+                    Err(e @ rusqlite::Error::FromSqlConversionFailure(..)) => {
+                        warn!("skipping row with invalid value: {}", e)
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+
+        result.total = count;
+        result.skipped = skipped;
+        // Skipped corrupt rows are part of the page as well
+        result.has_more = rows < remaining;
+        result.next_cursor = match self.config.get_take() {
+            // The last row may be a skipped one, the next page starts after it anyway
+            Some(l) if *l > 0 && rows >= *l => last,
+            _ => None,
+        };
+
+        Ok(())
+    }
+
+    /// Returns the condition which matches all rows after the cursor, or before it if
+    /// `reverse` is set, under the current sort order.
+    fn keyset_condition(&self, cursor: &Cursor, reverse: bool) -> Result<(String, Vec<Value>)> {
+        let (col, order) = self.keyset().ok_or_else(|| {
+            BookError::Generic(format!(
+                "Cursor pagination requires sorting by at most one of: {}",
+                KEYSET_COLUMNS.join(", ")
+            ))
+        })?;
+        let id = self.id_column.unwrap_or("id");
+        let op = match sql_order_rev(order, reverse) {
+            "ASC" => ">",
+            _ => "<",
+        };
+
+        let mut params: Vec<Value> = Vec::new();
+        let clause = match (col, &cursor.key) {
+            (Some(col), Some(key)) => {
+                params.push(key.clone().into());
+                format!("({}, {}) {} (?, ?)", col, id, op)
+            }
+            (None, _) => format!("{} {} ?", id, op),
+            (Some(_), None) => {
+                return Err(BookError::Generic(
+                    "Cursor is missing the value of the sort column".to_owned(),
+                ))
+            }
+        };
+        params.push(Value::Integer(cursor.id));
+
+        Ok((clause, params))
+    }
+
+    /// Returns the cursor pointing to the row with the given key.
+    fn cursor_of(&self, conn: &Connection, id: i64) -> Result<Cursor> {
+        let (col, _) = self.keyset().ok_or_else(|| {
+            BookError::Generic(format!(
+                "Neighbors require sorting by at most one of: {}",
+                KEYSET_COLUMNS.join(", ")
+            ))
+        })?;
+        let id_column = self.id_column.unwrap_or("id");
+
+        // Also queried if sorted by key only, to fail on an unknown key.
+        let key = conn.query_row(
+            &format!(
+                "SELECT {} FROM ({}) WHERE {} = ?",
+                col.unwrap_or(id_column),
+                self.query,
+                id_column
+            ),
+            [id],
+            |row| row.get::<usize, Value>(0),
+        )?;
+
+        Ok(Cursor {
+            key: col.and_then(|_| to_cursor_key(key)),
+            id,
+        })
+    }
+
+    /// Fetches up to `take` rows which follow the cursor under the current sort order and
+    /// filters, or precede it if `reverse` is set. Rows are ordered nearest first.
+    fn fetch_neighbors<T, F>(
+        &self,
+        conn: &Connection,
+        cursor: &Cursor,
+        reverse: bool,
+        take: u64,
+        map: F,
+    ) -> Result<Vec<T>>
+    where
+        F: FnMut(&rusqlite::Row) -> rusqlite::Result<T>,
+    {
+        let mut filter = self.filter.clone();
+        let mut params = self.params.clone();
+        let (clause, cursor_params) = self.keyset_condition(cursor, reverse)?;
+        filter.push(clause);
+        params.extend(cursor_params);
+
+        let mut query = self.query.to_owned();
+        push_where_clause(&mut query, &filter);
+        query.push_str(&format!(" {} LIMIT {}", self.order_by(reverse), take));
+
+        let mut stmt = conn.prepare(&query)?;
+        let rows = stmt.query_map(params_from_iter(params.iter()), map)?;
+
+        let mut items = Vec::new();
+        for item in rows {
+            items.push(item?);
+        }
+
+        Ok(items)
+    }
+}
+
+/// Appends the given conditions as `WHERE` clause to the query.
+fn push_where_clause(query: &mut String, conditions: &[String]) {
+    if conditions.is_empty() {
+        return;
+    }
+
+    query.push_str(" WHERE ");
+    query.push_str(
+        &conditions
+            .iter()
+            .map(|c| format!("({})", c))
+            .collect::<Vec<String>>()
+            .join(" AND "),
+    );
+}
+
+/// Returns a comma separated list of `count` SQL parameter placeholders.
+fn placeholders(count: usize) -> String {
+    vec!["?"; count].join(", ")
+}
+
+/// Returns the SQL sort order, inverted if `reverse` is set.
+fn sql_order_rev(order: &SortOrder, reverse: bool) -> &'static str {
+    match (order, reverse) {
+        (SortOrder::Asc, false) | (SortOrder::Desc, true) => "ASC",
+        (SortOrder::Desc, false) | (SortOrder::Asc, true) => "DESC",
+    }
+}
+
+fn to_cursor_key(value: Value) -> Option<CursorKey> {
+    match value {
+        Value::Integer(i) => Some(CursorKey::Integer(i)),
+        Value::Text(s) => Some(CursorKey::Text(s)),
+        _ => None,
+    }
+}
+
+impl From<CursorKey> for Value {
+    fn from(value: CursorKey) -> Self {
+        match value {
+            CursorKey::Integer(i) => Value::Integer(i),
+            CursorKey::Text(s) => Value::Text(s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SqliteStore;
+    use crate::books::models::SearchConfig;
+    use crate::books::models::{
+        Book, BookDB, BookError, DbTuning, Direction, Filters, JournalMode, NameCollation,
+        ReadingStatus, SearchColumn, SortOrder, Synchronous,
+    };
+    use crate::sort_desc;
+    use chrono::prelude::*;
+    use chrono::Utc;
+    use rusqlite::types::Value;
+    use rusqlite::Connection;
+    use std::error::Error;
+    use std::fs;
+    use std::path::PathBuf;
+
+    type Result<T = (), E = Box<dyn Error>> = std::result::Result<T, E>;
+
+    /// Returns a valid ISBN-13, which is distinct for every `n`.
+    fn test_isbn(n: u32) -> String {
+        let digits = format!("978{:09}", n);
+        let sum: u32 = digits
+            .chars()
+            .enumerate()
+            .map(|(i, c)| c.to_digit(10).unwrap() * if i.is_multiple_of(2) { 1 } else { 3 })
+            .sum();
+        format!("{}{}", digits, (10 - sum % 10) % 10)
+    }
+
+    /// Creates a database file with the bookshelf schema and a single book.
+    fn create_db_file(name: &str) -> Result<PathBuf> {
+        let path = std::env::temp_dir().join(format!("{}-{}.db", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let conn = Connection::open(&path)?;
+        for script in super::SCHEMA_SCRIPTS {
+            conn.execute_batch(script)?;
+        }
+        conn.execute_batch(
+            r#"INSERT INTO books (isbn, lang, title, created, updated)
+            VALUES ('9780857056429', 'EN', 'The Girl Who Takes an Eye for an Eye', unixepoch(), unixepoch());
+            INSERT INTO authors (name, book_id) VALUES ('David Lagercrantz', 1);"#,
+        )?;
+
+        Ok(path)
+    }
+
+    macro_rules! cmp_book {
+        (@Vec $a:expr, $b:expr, $comment:literal) => {{
+            let mut aa: Vec<String> = $a.clone();
+            let mut bb: Vec<String> = $b.clone();
+            aa.sort();
+            bb.sort();
+            assert_eq!(aa, bb);
+        }};
+        ($a:expr, $b:expr) => {
+            assert_eq!($a.id, $b.id, "Book id mismatch");
+            assert_eq!($a.cover_img, $b.cover_img, "Cover image mismatch");
+            assert_eq!($a.description, $b.description, "Description mismatch");
+            assert_eq!($a.isbn, $b.isbn, "ISBN mismatch");
+            assert_eq!($a.lang, $b.lang, "Language mismatch");
+            assert_eq!($a.title, $b.title, "Title mismatch");
+            assert_eq!($a.sub_title, $b.sub_title, "Sub title mismatch");
+            assert_eq!($a.publisher, $b.publisher, "Publisher mismatch");
+            assert_eq!($a.publish_date, $b.publish_date, "Publisher date mismatch");
+
+            cmp_book!(@Vec $a.authors, $b.authors, "Authors mismatched");
+
+            assert_eq!($a.tags.is_some(), $b.tags.is_some(), "Tags mismatch");
+            if $a.tags.is_some() {
+                cmp_book!(@Vec $a.tags.as_ref().unwrap(), $b.tags.as_ref().unwrap(), "Tags mismatched");
+            }
+
+        };
+    }
+
+    macro_rules! cmp_vec_books {
+        ($testee:expr, $expected:expr) => {
+            assert!($testee.len() == $expected.len(), "Books count mismatch");
+
+            $expected.sort_by(|a, b| a.id.cmp(&b.id));
+            $testee.sort_by(|a, b| a.id.cmp(&b.id));
+
+            for (pos, item) in $testee.iter().enumerate() {
+                cmp_book!(item, $expected[pos]);
+            }
+        };
+    }
+
+    #[test]
+    fn fetch_books() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+
+        let mut books = db.fetch_books(SearchConfig::new("").build())?;
+        assert_eq!(books.total, 3);
+        assert_eq!(books.skipped, 0);
+        assert_eq!(books.items.len(), 3);
+
+        cmp_vec_books!(
+            books.items,
+            vec![Book {
+                authors: vec!["David Lagercrantz".to_owned()],
+                cover_img: None,
+                description: Some("Lisbeth Salander is an unstoppable force!".to_owned()),
+                isbn: "9780857056429".to_owned(),
+                lang: "EN".to_owned(),
+                tags: Some(vec!["Thriller".to_owned(), "Suspense".to_owned()]),
+                title: "The Girl Who Takes an Eye for an Eye".to_owned(),
+                sub_title: None,
+                publisher: Some("McLehose Press".to_owned()),
+                publish_date: Some(Utc.timestamp_opt(1483523713, 0).unwrap()),
+                id: 1,
+
+                ..Default::default()
+            }, Book {
+                authors: vec!["Jochen Schiller".to_owned()],
+                cover_img: None,
+                description: Some("Explains mobile communications in details.".to_owned()),
+                isbn: "9780321123817".to_owned(),
+                lang: "EN".to_owned(),
+                tags: Some(vec!["Data Transmission Systems".to_owned(), "Wireless".to_owned(), "Communications".to_owned()]),
+                title: "Mobile Communications".to_owned(),
+                sub_title: Some("Second Edition".to_owned()),
+                publisher: Some("Addison Wesley".to_owned()),
+                publish_date: Some(Utc.timestamp_opt(1062150913, 0).unwrap()),
+                id: 2,
+
+                ..Default::default()
+            }, Book {
+                authors: vec!["Richard Dawkins".to_owned()],
+                cover_img: None,
+                description: Some("Richard Dawkins provozierendes Buch beseitigt jeden Zweifel an Darwins Theorie.".to_owned()),
+                isbn: "9783550087653".to_owned(),
+                lang: "DE".to_owned(),
+                tags: Some(vec!["Wissenschaft".to_owned(), "Biologie".to_owned(), "Religion".to_owned()]),
+                title: "Es gibt keine Schöpfung".to_owned(),
+                sub_title: None,
+                publisher: Some("Ullstein Verlag".to_owned()),
+                publish_date: Some(Utc.timestamp_opt(1283075713, 0).unwrap()),
+                id: 3,
+
+                ..Default::default()
+            }]
+        );
+
+        let mut partial_books =
+            db.fetch_books(SearchConfig::new("").use_take(2).use_skip_page(1).build())?;
+        assert_eq!(partial_books.total, 3);
+        assert_eq!(partial_books.skipped, 1);
+        assert_eq!(partial_books.items.len(), 1);
+
+        cmp_vec_books!(partial_books.items, vec![Book {
+            authors: vec!["Richard Dawkins".to_owned()],
+            cover_img: None,
+            description: Some("Richard Dawkins provozierendes Buch beseitigt jeden Zweifel an Darwins Theorie.".to_owned()),
+            isbn: "9783550087653".to_owned(),
+            lang: "DE".to_owned(),
+            tags: Some(vec!["Wissenschaft".to_owned(), "Biologie".to_owned(), "Religion".to_owned()]),
+            title: "Es gibt keine Schöpfung".to_owned(),
+            sub_title: None,
+            publisher: Some("Ullstein Verlag".to_owned()),
+            publish_date: Some(Utc.timestamp_opt(1283075713, 0).unwrap()),
+            id: 3,
+
+            ..Default::default()
+        }]);
+
+        Ok(())
+    }
+
+    thread_local! {
+        /// Number of statements run on connections traced by [count_statement].
+        static STATEMENTS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+
+    fn count_statement(_: &str) {
+        STATEMENTS.with(|s| s.set(s.get() + 1));
+    }
+
+    #[test]
+    fn fetch_books_loads_authors_and_tags_in_batches() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        for n in 1..=20 {
+            db.add_book(&mut Book {
+                authors: vec![format!("Author {}", n), "Co Author".to_owned()],
+                isbn: test_isbn(n),
+                lang: "EN".to_owned(),
+                title: format!("Book {}", n),
+                tags: Some(vec![format!("Tag {}", n)]),
+                ..Default::default()
+            })?;
+        }
+
+        db.conn.trace(Some(count_statement));
+        STATEMENTS.with(|s| s.set(0));
+        let books = db.fetch_books(SearchConfig::new("").build())?;
+        db.conn.trace(None);
+
+        // Count, books, authors and tags, independent of the number of books
+        let statements = STATEMENTS.with(|s| s.get());
+        assert!(statements <= 4, "{} statements for one fetch", statements);
+
+        assert_eq!(books.items.len(), 23);
+        for book in &books.items {
+            let stored = db.get_book(book.id)?;
+            assert_eq!(book.authors, stored.authors);
+            assert_eq!(book.tags, stored.tags);
+        }
+        let book = books.items.iter().find(|b| b.id == 1).unwrap();
+        assert_eq!(book.authors, vec!["David Lagercrantz"]);
+        let book = books.items.iter().find(|b| b.id == 3).unwrap();
+        assert_eq!(
+            book.tags,
+            Some(vec![
+                "Biologie".to_owned(),
+                "Religion".to_owned(),
+                "Wissenschaft".to_owned()
+            ])
+        );
+        let book = books.items.iter().find(|b| b.title == "Book 7").unwrap();
+        assert_eq!(book.authors, vec!["Author 7", "Co Author"]);
+        assert_eq!(book.tags, Some(vec!["Tag 7".to_owned()]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn get_book_by_isbn() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+
+        let book = db.get_book_by_isbn("9780857056429")?;
+        assert_eq!(book.id, 1);
+        assert_eq!(book.authors, vec!["David Lagercrantz"]);
+
+        // Hyphenated and ISBN-10 forms are normalized
+        assert_eq!(db.get_book_by_isbn("978-0-85705-642-9")?.id, 1);
+        assert_eq!(db.get_book_by_isbn("0857056425")?.id, 1);
+
+        assert!(matches!(
+            db.get_book_by_isbn("9783161484100"),
+            Err(BookError::NotFound)
+        ));
+        assert!(matches!(db.get_book_by_isbn(""), Err(BookError::NotFound)));
+
+        db.trash_book(1)?;
+        assert!(matches!(
+            db.get_book_by_isbn("9780857056429"),
+            Err(BookError::NotFound)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn delete_book_successfully() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+
+        db.delete_book_by_id(1)?;
+        assert!(db.get_book(1).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn add_book_successfully() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        let mut new_book = Book {
+            authors: vec![String::from("Schiller"), "Goethe".to_owned()],
+            cover_img: None,
+            description: Some("Most loved and famous book ever!".to_owned()),
+            isbn: String::from("9783161484100"),
+            lang: String::from("DE"),
+            tags: Some(vec!["Classic".to_owned(), "Poem".to_owned()]),
+            title: String::from("The Famous One"),
+            sub_title: None,
+            publisher: Some("Plato Verlag".to_owned()),
+            publish_date: Some(Utc.with_ymd_and_hms(1743, 1, 12, 13, 14, 44).unwrap()),
+            id: 123465798, // Should never be set or inserted
+            created: Utc::now()
+                .checked_sub_signed(chrono::Duration::seconds(1000000))
+                .unwrap(),
+            updated: Utc::now()
+                .checked_sub_signed(chrono::Duration::seconds(1000000))
+                .unwrap(),
+            reading_status: ReadingStatus::Reading,
+            started_at: Some(Utc.with_ymd_and_hms(2023, 3, 1, 20, 0, 0).unwrap()),
+            finished_at: None,
+            rating: Some(4),
+            owned: false,
+            field_updated: None,
+            ..Default::default()
+        };
+
+        db.add_book(&mut new_book)?;
+        let saved_book = db.get_book(new_book.id)?;
+
+        assert_eq!(new_book.title, saved_book.title);
+        assert_eq!(new_book.sub_title, saved_book.sub_title);
+        assert_eq!(new_book.isbn, saved_book.isbn);
+        assert_eq!(new_book.cover_img, saved_book.cover_img);
+        assert_eq!(new_book.description, saved_book.description);
+        assert_eq!(new_book.lang, saved_book.lang);
+        assert_eq!(new_book.publisher, saved_book.publisher);
+        assert_eq!(new_book.publish_date, saved_book.publish_date);
+        assert_eq!(new_book.reading_status, saved_book.reading_status);
+        assert_eq!(new_book.owned, saved_book.owned);
+        assert_eq!(new_book.started_at, saved_book.started_at);
+        assert_eq!(new_book.rating, saved_book.rating);
+
+        assert_eq!(new_book.created, saved_book.created);
+        assert_eq!(new_book.updated, saved_book.updated);
+
+        assert_eq!(new_book.authors, saved_book.authors);
+        assert_eq!(new_book.tags, saved_book.tags);
+
+        Ok(())
+    }
+
+    #[test]
+    fn open_read_only_database() -> Result {
+        let path = create_db_file("bookshelf-read-only")?;
+        let mut perms = fs::metadata(&path)?.permissions();
+        perms.set_readonly(true);
+        fs::set_permissions(&path, perms)?;
+
+        // Read-only files are detected and opened without WAL
+        let mut db = SqliteStore::new(path.to_str().unwrap(), &DbTuning::default())?;
+        let books = db.fetch_books(SearchConfig::new("").build())?;
+        assert_eq!(books.total, 1);
+        assert_eq!(books.items[0].authors, vec!["David Lagercrantz".to_owned()]);
+
+        let mode: String = db
+            .conn
+            .pragma_query_value(None, "journal_mode", |row| row.get(0))?;
+        assert_ne!(mode, "wal");
+
+        drop(db);
+        let _ = fs::remove_file(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn concurrent_writers_wait_for_lock() -> Result {
+        let path = std::env::temp_dir().join(format!("bookshelf-busy-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        // Debug builds open stores in memory, so the file connections are set up directly
+        let open = |tuning: &DbTuning| -> Result<SqliteStore> {
+            let mut conn = Connection::open(&path)?;
+            super::init_sqlite_connection(&mut conn, false, tuning)?;
+            Ok(SqliteStore::from_connection(conn)?)
+        };
+        let book = |n: u32| Book {
+            authors: vec!["Jochen Schiller".to_owned()],
+            isbn: test_isbn(n),
+            lang: "EN".to_owned(),
+            title: format!("Mobile Communications {}", n),
+            ..Default::default()
+        };
+
+        let first = open(&DbTuning::default())?;
+        let mut second = open(&DbTuning::default())?;
+
+        // The first writer holds the write lock for a moment, the second one waits for it
+        first.conn.execute_batch("BEGIN IMMEDIATE")?;
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(200));
+            first.conn.execute_batch("COMMIT")
+        });
+        second.add_book(&mut book(1))?;
+        writer.join().expect("writer thread panicked")?;
+
+        // Without busy timeout the lock fails immediately
+        let mut third = open(&DbTuning {
+            busy_timeout: None,
+            ..Default::default()
+        })?;
+        second.conn.execute_batch("BEGIN IMMEDIATE")?;
+        assert!(matches!(third.add_book(&mut book(2)), Err(BookError::Busy)));
+        second.conn.execute_batch("ROLLBACK")?;
+        third.add_book(&mut book(2))?;
+
+        let books = third.fetch_books(SearchConfig::new("").build())?;
+        assert_eq!(books.total, 2);
+
+        drop((second, third));
+        let _ = fs::remove_file(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn journal_mode_delete_leaves_no_wal_file() -> Result {
+        // Debug builds open stores in memory, so the file connection is set up directly
+        let open = |name: &str, tuning: &DbTuning| -> Result<(PathBuf, SqliteStore)> {
+            let path = std::env::temp_dir().join(format!("{}-{}.db", name, std::process::id()));
+            let _ = fs::remove_file(&path);
+            let mut conn = Connection::open(&path)?;
+            super::init_sqlite_connection(&mut conn, true, tuning)?;
+            Ok((path, SqliteStore::from_connection(conn)?))
+        };
+        let wal_exists = |path: &PathBuf| PathBuf::from(format!("{}-wal", path.display())).exists();
+
+        let tuning = DbTuning {
+            journal_mode: JournalMode::Delete,
+            synchronous: Synchronous::Full,
+            busy_timeout: Some(1000),
+        };
+        let (path, mut db) = open("bookshelf-journal-delete", &tuning)?;
+        let mut book = db.get_book(1)?;
+        book.title = "Renamed".to_owned();
+        db.update_book(&mut book)?;
+        assert!(!wal_exists(&path));
+
+        let mode: String = db
+            .conn
+            .pragma_query_value(None, "journal_mode", |row| row.get(0))?;
+        assert_eq!(mode, "delete");
+        let synchronous: i64 = db
+            .conn
+            .pragma_query_value(None, "synchronous", |row| row.get(0))?;
+        assert_eq!(synchronous, 2);
+        drop(db);
+        let _ = fs::remove_file(&path);
+
+        // The default keeps using the WAL
+        let (path, mut db) = open("bookshelf-journal-wal", &DbTuning::default())?;
+        let mut book = db.get_book(1)?;
+        book.title = "Renamed".to_owned();
+        db.update_book(&mut book)?;
+        assert!(wal_exists(&path));
+        drop(db);
+        let _ = fs::remove_file(&path);
+
+        Ok(())
+    }
+
+    #[test]
+    fn seed_only_on_request() -> Result {
+        assert!(!super::seed_enabled(None));
+        assert!(!super::seed_enabled(Some("0")));
+        assert_eq!(super::seed_enabled(Some("1")), cfg!(debug_assertions));
+
+        let mut db = SqliteStore::in_memory(super::seed_enabled(None))?;
+        assert_eq!(db.fetch_books(SearchConfig::new("").build())?.total, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_foreign_database() -> Result {
+        let path =
+            std::env::temp_dir().join(format!("bookshelf-foreign-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let conn = Connection::open(&path)?;
+        conn.execute_batch("CREATE TABLE notes (id INTEGER PRIMARY KEY, text TEXT);")?;
+        drop(conn);
+
+        let err = SqliteStore::new(path.to_str().unwrap(), &DbTuning::default()).unwrap_err();
+        assert!(matches!(err, BookError::IncompatibleDatabase(_)));
+        // The file is left untouched
+        let conn = Connection::open(&path)?;
+        let version: u32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        assert_eq!(version, 0);
+        drop(conn);
+
+        fs::write(&path, b"no database at all")?;
+        assert!(matches!(
+            SqliteStore::new(path.to_str().unwrap(), &DbTuning::default()),
+            Err(BookError::IncompatibleDatabase(_))
+        ));
+        let _ = fs::remove_file(&path);
+
+        let path = create_db_file("bookshelf-compatible")?;
+        assert!(SqliteStore::new(path.to_str().unwrap(), &DbTuning::default()).is_ok());
+
+        let _ = fs::remove_file(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn open_database_explicitly_read_only() -> Result {
+        let path = create_db_file("bookshelf-explicit-read-only")?;
+
+        let mut db = SqliteStore::new_read_only(path.to_str().unwrap())?;
+        assert_eq!(
+            db.get_book(1)?.title,
+            "The Girl Who Takes an Eye for an Eye"
+        );
+        assert!(db.delete_book_by_id(1).is_err());
+
+        drop(db);
+        let _ = fs::remove_file(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn sort_by_first_author() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+
+        let books = db.fetch_books(
+            SearchConfig::new("")
+                .use_sort(sort_desc!("first_author", SortOrder::Asc))
+                .build(),
+        )?;
+        let authors: Vec<&str> = books
+            .items
+            .iter()
+            .filter_map(|b| b.first_author())
+            .collect();
+        assert_eq!(
+            authors,
+            vec!["David Lagercrantz", "Jochen Schiller", "Richard Dawkins"]
+        );
+
+        let books = db.fetch_books(
+            SearchConfig::new("")
+                .use_sort(sort_desc!(
+                    "first_author",
+                    SortOrder::Desc,
+                    "title",
+                    SortOrder::Asc
+                ))
+                .build(),
+        )?;
+        let ids: Vec<i64> = books.items.iter().map(|b| b.id).collect();
+        assert_eq!(ids, vec![3, 2, 1]);
+
+        // Books without authors sort last
+        db.conn
+            .execute("DELETE FROM authors WHERE book_id = 1", [])?;
+        let books = db.fetch_books(
+            SearchConfig::new("")
+                .use_sort(sort_desc!("first_author", SortOrder::Asc))
+                .build(),
+        )?;
+        let ids: Vec<i64> = books.items.iter().map(|b| b.id).collect();
+        assert_eq!(ids, vec![2, 3, 1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn first_author_follows_name_collation() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        db.set_name_collation(NameCollation::Unicode)?;
+        let book = db.get_book(1)?;
+        db.update_book(&mut Book {
+            authors: vec!["Zweig".to_owned(), "ärger".to_owned()],
+            ..book
+        })?;
+
+        // Binary order would pick "Zweig", which sorts before "ä"
+        let book = db.get_book(1)?;
+        assert_eq!(book.first_author(), Some("ärger"));
+        let books = db.fetch_books(
+            SearchConfig::new("")
+                .use_sort(sort_desc!("first_author", SortOrder::Asc))
+                .build(),
+        )?;
+        let ids: Vec<i64> = books.items.iter().map(|b| b.id).collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn unseeded_store_is_empty() -> Result {
+        let mut db = SqliteStore::in_memory(false)?;
+
+        let books = db.fetch_books(SearchConfig::new("").build())?;
+        assert_eq!(books.total, 0);
+        assert!(books.items.is_empty());
+        assert_eq!(db.get_tags(SearchConfig::new("").build())?.total, 0);
+        assert_eq!(db.get_authors(SearchConfig::new("").build())?.total, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn fetch_books_with_cursor() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+
+        for order in ["asc", "desc"] {
+            let all: Vec<i64> = db
+                .fetch_books(
+                    SearchConfig::new("")
+                        .use_sort(sort_desc!("title", SortOrder::from(order)))
+                        .build(),
+                )?
+                .items
+                .iter()
+                .map(|b| b.id)
+                .collect();
+
+            let mut paged: Vec<i64> = Vec::new();
+            let mut cursor = None;
+            loop {
+                let mut config = SearchConfig::new("")
+                    .use_sort(sort_desc!("title", SortOrder::from(order)))
+                    .use_take(2);
+                if let Some(c) = cursor {
+                    config = config.use_cursor(c);
+                }
+
+                let page = db.fetch_books(config.build())?;
+                assert_eq!(page.total, 3);
+                paged.extend(page.items.iter().map(|b| b.id));
+
+                cursor = page.next_cursor;
+                if cursor.is_none() {
+                    break;
+                }
+            }
+
+            assert_eq!(paged, all);
+        }
+
+        let first = db.fetch_books(SearchConfig::new("").use_take(1).build())?;
+        let cursor = first.next_cursor.expect("First page should have a cursor");
+        assert!(db
+            .fetch_books(
+                SearchConfig::new("")
+                    .use_sort(sort_desc!("first_author", SortOrder::Asc))
+                    .use_cursor(cursor)
+                    .build()
+            )
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn skip_pages() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+
+        let books = db.fetch_books(SearchConfig::new("").use_take(1).use_skip_page(2).build())?;
+        assert_eq!(books.total, 3);
+        assert_eq!(books.skipped, 2);
+        assert_eq!(books.items.len(), 1);
+        assert_eq!(books.items[0].id, 3);
+
+        let books = db.fetch_books(SearchConfig::new("").use_take(1).use_skip_page(3).build())?;
+        assert!(books.items.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn has_more_pages() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+
+        let books = db.fetch_books(SearchConfig::new("").use_take(2).build())?;
+        assert!(books.has_more);
+        assert_eq!(books.page_count(2), 2);
+        let books = db.fetch_books(SearchConfig::new("").use_take(2).use_skip_page(1).build())?;
+        assert!(!books.has_more);
+        assert!(!db.fetch_books(SearchConfig::new("").build())?.has_more);
+
+        let first = db.fetch_books(SearchConfig::new("").use_take(2).build())?;
+        let cursor = first.next_cursor.expect("First page should have a cursor");
+        let books = db.fetch_books(SearchConfig::new("").use_take(1).use_cursor(cursor).build())?;
+        assert_eq!(books.items.len(), 1);
+        assert!(!books.has_more);
+
+        Ok(())
+    }
+
+    #[test]
+    fn search_books_by_text() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+
+        let books = db.fetch_books(SearchConfig::new("Salander").build())?;
+        assert_eq!(books.total, 1);
+        assert_eq!(books.items[0].authors, vec!["David Lagercrantz".to_owned()]);
+
+        let books = db.fetch_books(SearchConfig::new("i").use_take(1).build())?;
+        assert_eq!(books.total, 3);
+        assert_eq!(books.items.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn search_books_by_query_syntax() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        let ids = |db: &mut SqliteStore, text: &str| -> Result<Vec<i64>> {
+            let mut ids: Vec<i64> = db
+                .fetch_books(SearchConfig::new(text).build())?
+                .items
+                .iter()
+                .map(|b| b.id)
+                .collect();
+            ids.sort();
+            Ok(ids)
+        };
+
+        assert_eq!(ids(&mut db, "mobile schiller")?, vec![2]);
+        assert_eq!(ids(&mut db, "communications mobile")?, vec![2]);
+        assert!(ids(&mut db, "\"communications mobile\"")?.is_empty());
+        assert_eq!(ids(&mut db, "\"mobile communications\"")?, vec![2]);
+        assert_eq!(ids(&mut db, "Salander OR Dawkins")?, vec![1, 3]);
+        assert_eq!(ids(&mut db, "-thriller")?, vec![2, 3]);
+        assert_eq!(ids(&mut db, "i -thriller -Wissenschaft")?, vec![2]);
+        // Unbalanced quotes search the text as a whole
+        assert_eq!(ids(&mut db, "\"mobile communications")?, vec![2]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn normalize_isbn_on_add_and_update() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        let mut book = Book {
+            authors: vec!["Goethe".to_owned()],
+            isbn: "3-16-148410-X".to_owned(),
+            lang: "DE".to_owned(),
+            title: "Faust".to_owned(),
+            ..Default::default()
+        };
+        db.add_book(&mut book)?;
+        assert_eq!(book.isbn, "9783161484100");
+        assert_eq!(db.get_book(book.id)?.isbn, "9783161484100");
+
+        book.isbn = "978-3-16-148410-1".to_owned();
+        assert!(matches!(
+            db.update_book(&mut book),
+            Err(BookError::InvalidBook { .. })
+        ));
+        assert_eq!(db.get_book(book.id)?.isbn, "9783161484100");
+
+        Ok(())
+    }
+
+    #[test]
+    fn normalize_stored_isbns_on_migration() -> Result {
+        let mut conn = Connection::open_in_memory()?;
+        rusqlite_migration::Migrations::new(
+            super::SCHEMA_SCRIPTS[..super::UNIQUE_ISBN_VERSION]
+                .iter()
+                .map(|s| rusqlite_migration::M::up(s))
+                .collect(),
+        )
+        .to_latest(&mut conn)?;
+        conn.execute_batch(
+            r#"INSERT INTO books (isbn, lang, title, created, updated) VALUES
+                ('3-16-148410-X', 'DE', 'Faust', unixepoch(), unixepoch()),
+                ('978-3-16-148410-1', 'DE', 'Faust II', unixepoch(), unixepoch());
+            INSERT INTO authors (name, book_id) VALUES ('Goethe', 1), ('Goethe', 2);"#,
+        )?;
+
+        super::migrate_schema(&mut conn)?;
+        let mut db = SqliteStore::from_connection(conn)?;
+        assert_eq!(db.get_book_by_isbn("9783161484100")?.id, 1);
+
+        // The invalid ISBN is kept and doesn't prevent other changes
+        let mut book = db.get_book(2)?;
+        assert_eq!(book.isbn, "978-3-16-148410-1");
+        book.title = "Faust. Der Tragödie zweiter Teil".to_owned();
+        db.update_book(&mut book)?;
+        assert_eq!(db.get_book(2)?.title, "Faust. Der Tragödie zweiter Teil");
+
+        book.isbn = "978-3-16-148410-2".to_owned();
+        assert!(matches!(
+            db.update_book(&mut book),
+            Err(BookError::InvalidBook { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn filter_by_fields() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+
+        let books = db.fetch_books(SearchConfig::new("").use_lang("DE").build())?;
+        assert_eq!(books.total, 1);
+        assert_eq!(books.items[0].id, 3);
+
+        let config = SearchConfig::new("")
+            .use_lang("EN")
+            .use_tag("Thriller")
+            .build();
+        assert_eq!(db.count_books(config)?, 1);
+
+        let from = Utc.with_ymd_and_hms(2000, 1, 1, 0, 0, 0).unwrap();
+        let to = Utc.with_ymd_and_hms(2012, 12, 31, 23, 59, 59).unwrap();
+        let books = db.fetch_books(
+            SearchConfig::new("i")
+                .use_publish_between(from, to)
+                .use_take(1)
+                .build(),
+        )?;
+        assert_eq!(books.total, 2);
+        assert_eq!(books.items.len(), 1);
+        assert_eq!(books.items[0].id, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn filter_by_reading_status() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        let mut book = db.get_book(2)?;
+        book.reading_status = ReadingStatus::Reading;
+        db.update_book(&mut book)?;
+        assert_eq!(db.get_book(2)?.reading_status, ReadingStatus::Reading);
+
+        let books = db.fetch_books(
+            SearchConfig::new("")
+                .use_reading_status(ReadingStatus::Reading)
+                .build(),
+        )?;
+        assert_eq!(books.total, 1);
+        assert_eq!(books.items[0].id, 2);
+
+        let books = db.fetch_books(
+            SearchConfig::new("")
+                .use_reading_status(ReadingStatus::Unread)
+                .use_sort(sort_desc!("reading_status", SortOrder::Asc))
+                .build(),
+        )?;
+        assert_eq!(books.total, 2);
+
+        // finished is accepted as name of the read status
+        let status: ReadingStatus = serde_json::from_str("\"finished\"")?;
+        assert_eq!(status, ReadingStatus::Read);
+
+        Ok(())
+    }
+
+    #[test]
+    fn noop_update_keeps_updated() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        db.conn
+            .execute("UPDATE books SET updated = 1000 WHERE id = 1", [])?;
+        let stored = db.get_book(1)?;
+
+        let mut book = stored.clone();
+        book.tags.as_mut().unwrap().reverse();
+        db.update_book(&mut book)?;
+        assert_eq!(book, stored);
+        assert_eq!(db.get_book(1)?.updated.timestamp(), 1000);
+
+        book.title = "Millennium".to_owned();
+        db.update_book(&mut book)?;
+        assert_ne!(book.updated, stored.updated);
+        assert_eq!(book, db.get_book(1)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_stale_update() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        db.conn
+            .execute("UPDATE books SET updated = 1000 WHERE id = 1", [])?;
+        let mut first = db.get_book(1)?;
+        let mut second = db.get_book(1)?;
+
+        first.title = "Millennium".to_owned();
+        db.update_book(&mut first)?;
+        second.title = "Millennium 5".to_owned();
+        assert!(matches!(
+            db.update_book(&mut second),
+            Err(BookError::Conflict)
+        ));
+        assert_eq!(db.get_book(1)?.title, "Millennium");
+
+        // A reloaded book can be updated again
+        let mut second = db.get_book(1)?;
+        second.title = "Millennium 5".to_owned();
+        db.update_book(&mut second)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn rate_books() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        let mut book = db.get_book(1)?;
+        assert_eq!(book.rating, None);
+
+        book.rating = Some(5);
+        db.update_book(&mut book)?;
+        assert_eq!(db.get_book(1)?.rating, Some(5));
+
+        for rating in [0, 6] {
+            book.rating = Some(rating);
+            assert!(matches!(
+                db.update_book(&mut book),
+                Err(BookError::InvalidBook { .. })
+            ));
+            assert!(db
+                .add_book(&mut Book {
+                    id: 0,
+                    ..book.clone()
+                })
+                .is_err());
+        }
+        assert_eq!(db.get_book(1)?.rating, Some(5));
+
+        let books = db.fetch_books(
+            SearchConfig::new("")
+                .use_sort(sort_desc!("rating", SortOrder::Desc))
+                .build(),
+        )?;
+        assert_eq!(books.items[0].id, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn migrate_database_without_rating() -> Result {
+        let migrate = |conn: &mut Connection, scripts: &[&'static str]| {
+            rusqlite_migration::Migrations::new(
+                scripts
+                    .iter()
+                    .map(|s| rusqlite_migration::M::up(s))
+                    .collect(),
+            )
+            .to_latest(conn)
+        };
+        let version = super::SCHEMA_SCRIPTS
+            .iter()
+            .position(|s| s.contains("ADD COLUMN rating"))
+            .unwrap();
+
+        let mut conn = Connection::open_in_memory()?;
+        migrate(&mut conn, &super::SCHEMA_SCRIPTS[..version])?;
+        conn.execute_batch(
+            r#"INSERT INTO books (isbn, lang, title, created, updated)
+            VALUES ('9780857056429', 'EN', 'The Girl Who Takes an Eye for an Eye', unixepoch(), unixepoch());
+            INSERT INTO authors (name, book_id) VALUES ('David Lagercrantz', 1);"#,
+        )?;
+
+        migrate(&mut conn, super::SCHEMA_SCRIPTS)?;
+        let mut db = SqliteStore::from_connection(conn)?;
+        let mut book = db.get_book(1)?;
+        assert_eq!(book.rating, None);
+
+        book.rating = Some(3);
+        db.update_book(&mut book)?;
+        assert_eq!(db.get_book(1)?.rating, Some(3));
+
+        Ok(())
+    }
+
+    #[test]
+    fn acquired_date_round_trip() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        let acquired = Utc.with_ymd_and_hms(2019, 12, 24, 18, 30, 0).unwrap();
+        let mut book = db.get_book(3)?;
+        assert_eq!(book.acquired_date, None);
+
+        book.acquired_date = Some(acquired);
+        db.update_book(&mut book)?;
+        let stored = db.get_book(3)?;
+        assert_eq!(stored.acquired_date, Some(acquired));
+        assert_ne!(stored.acquired_date, Some(stored.created));
+        assert!(stored.created > acquired);
+
+        let books = db.fetch_books(
+            SearchConfig::new("")
+                .use_acquired_between(
+                    Utc.with_ymd_and_hms(2019, 1, 1, 0, 0, 0).unwrap(),
+                    Utc.with_ymd_and_hms(2019, 12, 31, 23, 59, 59).unwrap(),
+                )
+                .use_sort(sort_desc!("acquired_date", SortOrder::Desc))
+                .build(),
+        )?;
+        assert_eq!(books.total, 1);
+        assert_eq!(books.items[0].id, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn page_count_and_format_round_trip() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        let mut book = Book {
+            authors: vec!["Richard Dawkins".to_owned()],
+            isbn: "9780199291151".to_owned(),
+            lang: "EN".to_owned(),
+            title: "The Selfish Gene".to_owned(),
+            page_count: Some(544),
+            format: Some("Paperback".to_owned()),
+            ..Default::default()
+        };
+        db.add_book(&mut book)?;
+        let stored = db.get_book(book.id)?;
+        assert_eq!(stored.page_count, Some(544));
+        assert_eq!(stored.format.as_deref(), Some("Paperback"));
+
+        let books = db.fetch_books(
+            SearchConfig::new("")
+                .use_filters(Filters {
+                    format: Some("Paperback".to_owned()),
+                    ..Default::default()
+                })
+                .use_sort(sort_desc!("format", SortOrder::Asc))
+                .build(),
+        )?;
+        assert_eq!(books.total, 1);
+        assert_eq!(books.items[0].id, book.id);
+
+        book.page_count = None;
+        book.format = None;
+        db.update_book(&mut book)?;
+        let stored = db.get_book(book.id)?;
+        assert_eq!(stored.page_count, None);
+        assert_eq!(stored.format, None);
+
+        book.page_count = Some(0);
+        assert!(matches!(
+            db.update_book(&mut book),
+            Err(BookError::InvalidBook { .. })
+        ));
+        assert_eq!(db.get_book(book.id)?.page_count, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn books_of_series_ordered_by_index() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        for (i, (title, index)) in [
+            ("The Girl Who Lived Twice", 6.0),
+            ("The Girl with the Dragon Tattoo", 1.0),
+            ("The Girl in the Spider's Web", 4.0),
+            ("Salander Short Story", 1.5),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            db.add_book(&mut Book {
+                authors: vec!["Stieg Larsson".to_owned()],
+                isbn: test_isbn(i as u32 + 1),
+                lang: "EN".to_owned(),
+                title: title.to_owned(),
+                series: Some("Millennium".to_owned()),
+                series_index: Some(index),
+                ..Default::default()
+            })?;
+        }
+
+        let books = db.fetch_books(
+            SearchConfig::new("")
+                .use_series("Millennium")
+                .use_sort(sort_desc!(
+                    "series",
+                    SortOrder::Asc,
+                    "series_index",
+                    SortOrder::Asc
+                ))
+                .build(),
+        )?;
+        assert_eq!(books.total, 4);
+        assert_eq!(
+            books
+                .items
+                .iter()
+                .map(|b| b.series_index)
+                .collect::<Vec<_>>(),
+            vec![Some(1.0), Some(1.5), Some(4.0), Some(6.0)]
+        );
+        assert_eq!(books.items[0].title, "The Girl with the Dragon Tattoo");
+
+        let mut book = db.get_book(books.items[0].id)?;
+        book.series_index = Some(-1.0);
+        assert!(matches!(
+            db.update_book(&mut book),
+            Err(BookError::InvalidBook { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn filter_wishlist_books() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        // Books existing before the migration are owned
+        assert!(db.get_book(1)?.owned);
+
+        let mut wish = Book {
+            authors: vec!["Richard Dawkins".to_owned()],
+            isbn: "9780199291151".to_owned(),
+            lang: "EN".to_owned(),
+            title: "The Selfish Gene".to_owned(),
+            owned: false,
+            ..Default::default()
+        };
+        db.add_book(&mut wish)?;
+        assert!(!db.get_book(wish.id)?.owned);
+
+        let books = db.fetch_books(SearchConfig::new("").use_owned(true).build())?;
+        assert_eq!(books.total, 3);
+        assert!(books.items.iter().all(|b| b.id != wish.id));
+
+        let books = db.fetch_books(SearchConfig::new("").use_owned(false).build())?;
+        assert_eq!(books.total, 1);
+        assert_eq!(books.items[0].id, wish.id);
+
+        // Buying the book moves it from the wishlist into the library
+        wish.owned = true;
+        db.update_book(&mut wish)?;
+        let books = db.fetch_books(SearchConfig::new("").use_owned(true).build())?;
+        assert_eq!(books.total, 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_duplicate_isbn() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        let book = Book {
+            authors: vec!["Goethe".to_owned()],
+            isbn: "9783161484100".to_owned(),
+            lang: "DE".to_owned(),
+            title: "Faust".to_owned(),
+            ..Default::default()
+        };
+
+        let mut first = book.clone();
+        db.add_book(&mut first)?;
+        let res = db.add_book(&mut Book {
+            isbn: "3-16-148410-X".to_owned(),
+            title: "Faust II".to_owned(),
+            ..book.clone()
+        });
+        assert!(matches!(res, Err(BookError::DuplicateIsbn(isbn)) if isbn == "9783161484100"));
+        assert_eq!(db.get_book(first.id)?, first);
+        assert_eq!(db.count_books(SearchConfig::new("").build())?, 4);
+
+        let mut other = db.get_book(2)?;
+        other.isbn = first.isbn.clone();
+        assert!(matches!(
+            db.update_book(&mut other),
+            Err(BookError::DuplicateIsbn(_))
+        ));
+
+        // Books in the trash don't block their ISBN, but can't be restored while it is taken
+        db.trash_book(first.id)?;
+        let mut second = book.clone();
+        db.add_book(&mut second)?;
+        assert!(matches!(
+            db.restore_book(first.id),
+            Err(BookError::DuplicateIsbn(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn migrate_duplicate_isbns() -> Result {
+        let mut conn = Connection::open_in_memory()?;
+        rusqlite_migration::Migrations::new(
+            super::SCHEMA_SCRIPTS[..super::UNIQUE_ISBN_VERSION]
+                .iter()
+                .map(|s| rusqlite_migration::M::up(s))
+                .collect(),
+        )
+        .to_latest(&mut conn)?;
+        conn.execute_batch(
+            r#"INSERT INTO books (isbn, lang, title, created, updated) VALUES
+                ('9780857056429', 'EN', 'First', unixepoch(), unixepoch()),
+                ('978-0-85705-642-9', 'EN', 'Second', unixepoch(), unixepoch()),
+                ('', 'EN', 'Without ISBN', unixepoch(), unixepoch()),
+                ('', 'EN', 'Without ISBN', unixepoch(), unixepoch());
+            INSERT INTO authors (name, book_id) VALUES ('A', 1), ('B', 2), ('C', 3), ('D', 4);"#,
+        )?;
+
+        super::migrate_schema(&mut conn)?;
+        let mut db = SqliteStore::from_connection(conn)?;
+
+        // The later duplicate was moved to the trash, even though its ISBN was hyphenated
+        let trash = db.list_trash()?;
+        assert_eq!(trash.len(), 1);
+        assert_eq!(trash[0].title, "Second");
+        assert_eq!(db.count_books(SearchConfig::new("").build())?, 3);
+
+        // It is kept in the trash until it is reviewed
+        assert_eq!(db.purge_trash(0)?, 0);
         db.delete_book_by_id(1)?;
+        db.restore_book(2)?;
+        db.trash_book(2)?;
+        assert_eq!(db.purge_trash(0)?, 1);
+        assert!(db.list_trash()?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn add_books_in_one_transaction() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        let book = Book {
+            authors: vec!["Goethe".to_owned()],
+            isbn: "9783161484100".to_owned(),
+            lang: "DE".to_owned(),
+            title: "Faust".to_owned(),
+            ..Default::default()
+        };
+
+        let mut books = vec![
+            book.clone(),
+            Book {
+                authors: vec![],
+                ..book.clone()
+            },
+        ];
+        assert!(db.add_books(&mut books).is_err());
+        assert_eq!(db.count_books(SearchConfig::new("").build())?, 3);
+
+        // Duplicate ISBNs within the batch are rejected as well
+        let mut books = vec![book.clone(), book.clone()];
+        assert!(matches!(
+            db.add_books(&mut books),
+            Err(BookError::DuplicateIsbn(_))
+        ));
+        assert_eq!(db.count_books(SearchConfig::new("").build())?, 3);
+
+        let mut books = vec![
+            book.clone(),
+            Book {
+                isbn: test_isbn(1),
+                ..book
+            },
+        ];
+        db.add_books(&mut books)?;
+        assert_eq!(db.count_books(SearchConfig::new("").build())?, 5);
+        assert_eq!(db.get_book(books[1].id)?, books[1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn delete_several_books() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        assert_eq!(db.delete_books_by_ids(&[])?, 0);
+        assert_eq!(db.delete_books_by_ids(&[1, 3, 42])?, 2);
+        assert_eq!(db.fetch_books(SearchConfig::new("").build())?.total, 1);
         assert!(db.get_book(1).is_err());
 
+        assert_eq!(db.trash_books(&[2, 2, 42])?, 1);
+        assert_eq!(db.trash_books(&[2])?, 0);
+        assert_eq!(db.fetch_books(SearchConfig::new("").build())?.total, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn add_many_books() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        let before = db.count_books(SearchConfig::new("").build())?;
+
+        let mut books: Vec<Book> = (0..50)
+            .map(|i| Book {
+                authors: vec![format!("Author {}", i)],
+                isbn: test_isbn(i),
+                lang: "EN".to_owned(),
+                title: format!("Book {}", i),
+                ..Default::default()
+            })
+            .collect();
+        db.add_books(&mut books)?;
+
+        let ids: std::collections::HashSet<i64> = books.iter().map(|b| b.id).collect();
+        assert_eq!(ids.len(), 50);
+        assert!(books.iter().all(|b| b.id > 0 && b.created.timestamp() > 0));
+        assert_eq!(db.count_books(SearchConfig::new("").build())?, before + 50);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rank_search_results() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        let mut book = Book {
+            authors: vec!["Lisbeth Salander".to_owned()],
+            description: Some("Salander, Salander and again Salander.".to_owned()),
+            isbn: "9783161484100".to_owned(),
+            lang: "EN".to_owned(),
+            title: "Salander".to_owned(),
+            ..Default::default()
+        };
+        db.add_book(&mut book)?;
+
+        let books = db.fetch_books(SearchConfig::new("salander").build())?;
+        assert_eq!(books.total, 2);
+        assert_eq!(books.items[0].id, book.id);
+        assert_eq!(books.items[1].authors, vec!["David Lagercrantz".to_owned()]);
+        assert!(books.next_cursor.is_none());
+
+        // An explicit sort order replaces the ranking
+        let books = db.fetch_books(
+            SearchConfig::new("salander")
+                .use_sort(sort_desc!("id", SortOrder::Asc))
+                .build(),
+        )?;
+        assert_eq!(books.items[0].id, 1);
+
+        // The index follows updates of books and their authors
+        book.authors = vec!["Mikael Blomkvist".to_owned()];
+        book.title = "Millennium".to_owned();
+        book.description = None;
+        db.update_book(&mut book)?;
+        let books = db.fetch_books(SearchConfig::new("salander").build())?;
+        assert_eq!(books.total, 1);
+        assert_eq!(db.count_books(SearchConfig::new("Blomkvist").build())?, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn wal_autocheckpoint() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+
+        db.set_wal_autocheckpoint(100)?;
+        assert_eq!(db.get_wal_autocheckpoint()?, 100);
+
+        assert!(db.set_wal_autocheckpoint(-1).is_err());
+        assert_eq!(db.get_wal_autocheckpoint()?, 100);
+
+        Ok(())
+    }
+
+    #[test]
+    fn filter_by_any_tag() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+
+        let books = db.fetch_books(
+            SearchConfig::new("")
+                .use_tags_any(vec!["Thriller".to_owned(), "Wireless".to_owned()])
+                .build(),
+        )?;
+        assert_eq!(books.total, 2);
+        let mut ids: Vec<i64> = books.items.iter().map(|b| b.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2]);
+
+        let books = db.fetch_books(
+            SearchConfig::new("Mobile")
+                .use_tags_any(vec!["Thriller".to_owned(), "Wireless".to_owned()])
+                .build(),
+        )?;
+        assert_eq!(books.total, 1);
+        assert_eq!(books.items[0].id, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn filter_by_all_tags() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        let mut book = db.get_book(2)?;
+        book.tags = Some(vec!["Wissenschaft".to_owned()]);
+        db.update_book(&mut book)?;
+
+        let books = db.fetch_books(
+            SearchConfig::new("")
+                .use_tags_all(vec!["Wissenschaft".to_owned(), "Biologie".to_owned()])
+                .build(),
+        )?;
+        assert_eq!(books.total, 1);
+        assert_eq!(books.items[0].id, 3);
+
+        let books = db.fetch_books(
+            SearchConfig::new("")
+                .use_tags_all(vec!["Wissenschaft".to_owned()])
+                .build(),
+        )?;
+        assert_eq!(books.total, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn count_matching_books() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+
+        assert_eq!(db.count_books(SearchConfig::new("").build())?, 3);
+        assert_eq!(
+            db.count_books(SearchConfig::new("i").use_take(1).build())?,
+            3
+        );
+        assert_eq!(db.count_books(SearchConfig::new("Salander").build())?, 1);
+        assert_eq!(
+            db.count_books(SearchConfig::new("no such book").build())?,
+            0
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn cover_references() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        assert!(db.get_cover_references()?.is_empty());
+
+        let mut book = db.get_book(1)?;
+        book.cover_img = Some("covers/girl.jpg".to_owned());
+        db.update_book(&mut book)?;
+
+        assert_eq!(
+            db.get_cover_references()?,
+            vec!["covers/girl.jpg".to_owned()]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn filter_by_author() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+
+        let books = db.fetch_books(SearchConfig::new("").use_author("Richard Dawkins").build())?;
+        assert_eq!(books.total, 1);
+        assert_eq!(books.items[0].id, 3);
+
+        let books = db.fetch_books(
+            SearchConfig::new("Darwin")
+                .use_author("Richard Dawkins")
+                .build(),
+        )?;
+        assert_eq!(books.total, 1);
+
+        let books = db.fetch_books(
+            SearchConfig::new("Mobile")
+                .use_author("Richard Dawkins")
+                .build(),
+        )?;
+        assert_eq!(books.total, 0);
+
+        // Exact match only
+        let books = db.fetch_books(SearchConfig::new("").use_author("Dawkins").build())?;
+        assert_eq!(books.total, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn vacuum_large_database() -> Result {
+        let mut db = SqliteStore::in_memory(false)?;
+
+        for i in 0..100 {
+            db.add_book(&mut Book {
+                authors: vec!["Filler".to_owned()],
+                isbn: test_isbn(i as u32),
+                lang: "EN".to_owned(),
+                title: format!("Filler {}", i),
+                description: Some("x".repeat(8192)),
+                ..Default::default()
+            })?;
+        }
+        db.conn.execute("DELETE FROM books", [])?;
+
+        let free_pages = |db: &SqliteStore| -> rusqlite::Result<u64> {
+            db.conn
+                .pragma_query_value(None, "freelist_count", |row| row.get(0))
+        };
+        assert!(free_pages(&db)? > 0);
+
+        // Below the threshold nothing happens
+        assert!(!db.vacuum_if_needed(u64::MAX)?);
+        assert!(free_pages(&db)? > 0);
+
+        assert!(db.vacuum_if_needed(0)?);
+        assert_eq!(free_pages(&db)?, 0);
+
+        // Nothing left to reclaim
+        assert!(!db.vacuum_if_needed(0)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_unknown_sort_column() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        let sorted_by = |col: &str| {
+            SearchConfig::new("")
+                .use_sort(sort_desc!(col, SortOrder::Asc))
+                .build()
+        };
+
+        let res = db.fetch_books(sorted_by("title; DROP TABLE books"));
+        assert!(matches!(res, Err(BookError::Generic(_))));
+        assert!(db.get_tags(sorted_by("tag) --")).is_err());
+        assert_eq!(db.fetch_books(sorted_by("publisher"))?.total, 3);
+
+        // Each query only accepts its own columns
+        assert!(db.fetch_books(sorted_by("tag")).is_err());
+        assert!(db.get_tags(sorted_by("title")).is_err());
+        assert!(db.get_authors(sorted_by("tag")).is_err());
+        assert_eq!(db.get_tags(sorted_by("tag"))?.total, 8);
+        assert_eq!(db.get_authors(sorted_by("name"))?.total, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn neighbors_by_title() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        let by_title = || {
+            SearchConfig::new("")
+                .use_sort(sort_desc!("title", SortOrder::Asc))
+                .build()
+        };
+        let ids = |books: Vec<Book>| books.iter().map(|b| b.id).collect::<Vec<i64>>();
+
+        // Sorted by title: 3, 2, 1
+        let next = db.get_neighbors(3, by_title(), Direction::Forward, 2)?;
+        assert_eq!(ids(next), vec![2, 1]);
+
+        let next = db.get_neighbors(2, by_title(), Direction::Forward, 2)?;
+        assert_eq!(ids(next), vec![1]);
+
+        let prev = db.get_neighbors(1, by_title(), Direction::Backward, 2)?;
+        assert_eq!(ids(prev), vec![2, 3]);
+
+        assert!(db
+            .get_neighbors(42, by_title(), Direction::Forward, 2)
+            .is_err());
+
         Ok(())
     }
 
     #[test]
-    fn add_book_successfully() -> Result {
-        let mut db = SqliteStore::new("db_file")?;
-        let mut new_book = Book {
-            authors: vec![String::from("Schiller"), "Goethe".to_owned()],
-            cover_img: None,
-            description: Some("Most loved and famous book ever!".to_owned()),
-            isbn: String::from("123456789"),
-            lang: String::from("DE"),
-            tags: Some(vec!["Classic".to_owned(), "Poem".to_owned()]),
-            title: String::from("The Famous One"),
-            sub_title: None,
-            publisher: Some("Plato Verlag".to_owned()),
+    fn search_in_notes() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        let mut book = db.get_book(2)?;
+        book.notes = Some("Signed copy, my Lieblingsbuch on GSM".to_owned());
+        db.update_book(&mut book)?;
+        assert_eq!(db.get_book(2)?.notes, book.notes);
+
+        let books = db.fetch_books(SearchConfig::new("lieblingsbuch").build())?;
+        assert_eq!(books.total, 1);
+        assert_eq!(books.items[0].id, 2);
+
+        // Too short for the full-text index
+        let books = db.fetch_books(
+            SearchConfig::new("GS")
+                .use_search_columns(vec![SearchColumn::Notes])
+                .build(),
+        )?;
+        assert_eq!(books.total, 1);
+
+        book.notes = None;
+        db.update_book(&mut book)?;
+        let books = db.fetch_books(SearchConfig::new("lieblingsbuch").build())?;
+        assert_eq!(books.total, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn search_without_description() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+
+        // Only part of the description of the first book
+        let books = db.fetch_books(SearchConfig::new("unstoppable").build())?;
+        assert_eq!(books.total, 1);
+
+        let columns: Vec<SearchColumn> = SearchColumn::ALL
+            .into_iter()
+            .filter(|c| *c != SearchColumn::Description)
+            .collect();
+        let books = db.fetch_books(
+            SearchConfig::new("unstoppable")
+                .use_search_columns(columns.clone())
+                .build(),
+        )?;
+        assert_eq!(books.total, 0);
+        assert!(books.items.is_empty());
+
+        let books = db.fetch_books(
+            SearchConfig::new("Salander")
+                .use_search_columns(vec![SearchColumn::Description])
+                .build(),
+        )?;
+        assert_eq!(books.total, 1);
+
+        let books = db.fetch_books(
+            SearchConfig::new("Lagercrantz")
+                .use_search_columns(columns)
+                .build(),
+        )?;
+        assert_eq!(books.total, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn repair_invalid_timestamps() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        db.conn
+            .execute("UPDATE books SET created = 0 WHERE id = 1", [])?;
+        db.conn
+            .execute("UPDATE books SET updated = ? WHERE id = 2", [i64::MAX])?;
+
+        // An invalid timestamp is an error, not a panic
+        assert!(db.get_book(2).is_err());
+        assert_eq!(
+            db.fetch_books(SearchConfig::new("").build())?.items.len(),
+            2
+        );
+
+        assert_eq!(db.repair_timestamps()?, 2);
+        assert_eq!(db.repair_timestamps()?, 0);
+
+        let books = db.fetch_books(SearchConfig::new("").build())?;
+        assert_eq!(books.total, 3);
+        assert!(db.get_book(1)?.created.timestamp() > 0);
+        assert!(db.get_book(2)?.updated.timestamp() < super::MAX_VALID_TIMESTAMP);
+
+        Ok(())
+    }
+
+    #[test]
+    fn skip_rows_with_invalid_timestamps() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        db.conn
+            .execute("UPDATE books SET publish_date = ? WHERE id = 3", [i64::MIN])?;
+
+        assert!(db.get_book(3).is_err());
+        assert!(db.get_book(1).is_ok());
+
+        let books = db.fetch_books(SearchConfig::new("").build())?;
+        let mut ids: Vec<i64> = books.items.iter().map(|b| b.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn page_past_invalid_rows() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        db.conn
+            .execute("UPDATE books SET publish_date = ? WHERE id = 2", [i64::MIN])?;
+
+        let first = db.fetch_books(SearchConfig::new("").use_take(1).build())?;
+        assert_eq!(first.items[0].id, 1);
+        let cursor = first.next_cursor.expect("First page should have a cursor");
+
+        // The page only holds the corrupt row, but paging continues after it
+        let second =
+            db.fetch_books(SearchConfig::new("").use_take(1).use_cursor(cursor).build())?;
+        assert!(second.items.is_empty());
+        assert!(second.has_more);
+        let cursor = second
+            .next_cursor
+            .expect("Second page should have a cursor");
+
+        let third = db.fetch_books(SearchConfig::new("").use_take(1).use_cursor(cursor).build())?;
+        assert_eq!(
+            third.items.iter().map(|b| b.id).collect::<Vec<_>>(),
+            vec![3]
+        );
+        assert!(!third.has_more);
+
+        Ok(())
+    }
+
+    #[test]
+    fn count_books_per_status() -> Result {
+        let mut db = SqliteStore::in_memory(false)?;
+        assert_eq!(
+            db.status_counts()?,
+            vec![
+                (ReadingStatus::Unread, 0),
+                (ReadingStatus::Reading, 0),
+                (ReadingStatus::Read, 0)
+            ]
+        );
+
+        let statuses = [
+            ReadingStatus::Read,
+            ReadingStatus::Read,
+            ReadingStatus::Unread,
+            ReadingStatus::Read,
+        ];
+        for (i, status) in statuses.into_iter().enumerate() {
+            db.add_book(&mut Book {
+                authors: vec!["Terry Pratchett".to_owned()],
+                isbn: test_isbn(i as u32),
+                lang: "EN".to_owned(),
+                title: format!("Discworld {}", i),
+                reading_status: status,
+                ..Default::default()
+            })?;
+        }
+
+        assert_eq!(
+            db.status_counts()?,
+            vec![
+                (ReadingStatus::Unread, 1),
+                (ReadingStatus::Reading, 0),
+                (ReadingStatus::Read, 3)
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn search_with_filter_bundle() -> Result {
+        let filters = Filters {
+            tags_all: Some(vec!["Biologie".to_owned(), "Religion".to_owned()]),
+            author: Some("Richard Dawkins".to_owned()),
+            // Empty filters are dropped by build
+            tags_any: Some(vec![]),
+            ..Default::default()
+        };
+        let config = SearchConfig::new("")
+            .use_author("Jochen Schiller")
+            .use_filters(filters.clone())
+            .build();
+        assert_eq!(config.get_author(), Some("Richard Dawkins"));
+        assert_eq!(config.get_tags_any(), None);
+
+        let builder = super::books_query_builder(&config)?;
+        assert_eq!(
+            builder.filter,
+            vec![
+                "deleted_at IS NULL",
+                "EXISTS (SELECT 1 FROM tags WHERE book_id = books.id AND tag = ?)",
+                "EXISTS (SELECT 1 FROM tags WHERE book_id = books.id AND tag = ?)",
+                "EXISTS (SELECT 1 FROM authors WHERE book_id = books.id AND name = ?)",
+            ]
+        );
+        assert_eq!(
+            builder.params,
+            vec![
+                Value::Text("Biologie".to_owned()),
+                Value::Text("Religion".to_owned()),
+                Value::Text("Richard Dawkins".to_owned()),
+            ]
+        );
+
+        let mut db = SqliteStore::new_in_memory()?;
+        let books = db.fetch_books(SearchConfig::new("").use_filters(filters).build())?;
+        assert_eq!(books.total, 1);
+        assert_eq!(books.items[0].id, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn order_names_by_collation() -> Result {
+        let mut db = SqliteStore::in_memory(false)?;
+        let mut book = Book {
+            authors: vec!["Zweig".to_owned(), "Ärger".to_owned(), "Adler".to_owned()],
+            isbn: "9783161484100".to_owned(),
+            lang: "DE".to_owned(),
+            title: "Sammelband".to_owned(),
+            tags: Some(vec![
+                "Zeit".to_owned(),
+                "Übung".to_owned(),
+                "Natur".to_owned(),
+            ]),
+            ..Default::default()
+        };
+        db.add_book(&mut book)?;
+
+        let stored = db.get_book(book.id)?;
+        assert_eq!(stored.authors, vec!["Adler", "Zweig", "Ärger"]);
+        assert_eq!(book.authors, stored.authors);
+        assert!(db.check_name_order()?.is_empty());
+
+        db.set_name_collation(NameCollation::Unicode)?;
+        let stored = db.get_book(book.id)?;
+        assert_eq!(stored.authors, vec!["Adler", "Ärger", "Zweig"]);
+        assert_eq!(
+            stored.tags,
+            Some(vec![
+                "Natur".to_owned(),
+                "Übung".to_owned(),
+                "Zeit".to_owned()
+            ])
+        );
+        assert!(db.check_name_order()?.is_empty());
+
+        let mut updated = stored.clone();
+        updated.authors.reverse();
+        db.update_book(&mut updated)?;
+        assert_eq!(updated.authors, stored.authors);
+
+        Ok(())
+    }
+
+    #[test]
+    fn log_reading_and_finished_in_year() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        let started = Utc.with_ymd_and_hms(2022, 12, 20, 18, 0, 0).unwrap();
+        let finished = Utc.with_ymd_and_hms(2023, 1, 6, 21, 30, 0).unwrap();
+
+        let book = db.log_reading(1, Some(started), None)?;
+        assert_eq!(book.reading_status, ReadingStatus::Reading);
+
+        let book = db.log_reading(1, Some(started), Some(finished))?;
+        assert_eq!(book.reading_status, ReadingStatus::Read);
+
+        let stored = db.get_book(1)?;
+        assert_eq!(stored.started_at, Some(started));
+        assert_eq!(stored.finished_at, Some(finished));
+        assert_eq!(stored.reading_status, ReadingStatus::Read);
+
+        db.log_reading(
+            3,
+            None,
+            Some(Utc.with_ymd_and_hms(2023, 12, 31, 23, 59, 59).unwrap()),
+        )?;
+        db.log_reading(
+            2,
+            None,
+            Some(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()),
+        )?;
+
+        let ids: Vec<i64> = db.finished_in_year(2023)?.iter().map(|b| b.id).collect();
+        assert_eq!(ids, vec![1, 3]);
+        assert!(db.finished_in_year(2022)?.is_empty());
+        assert_eq!(db.finished_in_year(2024)?.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn group_title_duplicates() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        let mut book = Book {
+            authors: vec!["Jochen Schiller".to_owned()],
+            isbn: "0000000000".to_owned(),
+            lang: "EN".to_owned(),
+            title: " MOBILE communications".to_owned(),
+            ..Default::default()
+        };
+        db.add_book(&mut book)?;
+
+        let duplicates = db.title_duplicates()?;
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].0, "mobile communications");
+        let ids: Vec<i64> = duplicates[0].1.iter().map(|b| b.id).collect();
+        assert_eq!(ids, vec![2, book.id]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn library_statistics() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+
+        let stats = db.statistics()?;
+        assert_eq!(stats.total_books, 3);
+        assert_eq!(stats.books_by_lang.len(), 2);
+        assert_eq!(stats.books_by_lang["EN"], 2);
+        assert_eq!(stats.books_by_lang["DE"], 1);
+        assert_eq!(stats.books_by_decade, vec![(2000, 1), (2010, 2)]);
+        assert_eq!(stats.top_authors.len(), 3);
+        assert!(stats.top_tags.contains(&("Thriller".to_owned(), 1)));
+
+        db.trash_book(3)?;
+        let stats = db.statistics()?;
+        assert_eq!(stats.total_books, 2);
+        assert_eq!(stats.books_by_lang.get("DE"), None);
+        assert!(!stats.top_tags.contains(&("Religion".to_owned(), 1)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn find_probable_duplicates() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        db.conn.execute_batch(
+            r#"INSERT INTO books (isbn, lang, title, created, updated) VALUES
+                ('', 'DE', 'Der Gotteswahn', unixepoch(), unixepoch()),
+                ('', 'DE', ' der GOTTESWAHN ', unixepoch(), unixepoch()),
+                ('', 'DE', 'Der Gotteswahn', unixepoch(), unixepoch()),
+                ('978-0-85705-642-9', 'EN', 'Verschwörung', unixepoch(), unixepoch());
+            INSERT INTO authors (name, book_id) VALUES
+                ('Richard Dawkins', 4), ('richard dawkins', 5), ('Sebastian Vogel', 6),
+                ('David Lagercrantz', 7);"#,
+        )?;
+
+        let ids = |groups: Vec<Vec<Book>>| -> Vec<Vec<i64>> {
+            groups
+                .iter()
+                .map(|g| g.iter().map(|b| b.id).collect())
+                .collect()
+        };
+        assert_eq!(ids(db.find_duplicates()?), vec![vec![1, 7], vec![4, 5]]);
+
+        // Books in the trash are ignored
+        db.trash_book(7)?;
+        assert_eq!(ids(db.find_duplicates()?), vec![vec![4, 5]]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn trash_and_restore_book() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        let all = || SearchConfig::new("").build();
+
+        db.trash_book(1)?;
+        let books = db.fetch_books(all())?;
+        assert_eq!(books.total, 2);
+        assert!(books.items.iter().all(|b| b.id != 1));
+        assert!(!db.get_tags(all())?.items.contains(&"Thriller".to_owned()));
+        assert!(db.trash_book(1).is_err());
+
+        db.restore_book(1)?;
+        assert_eq!(db.fetch_books(all())?.total, 3);
+        assert!(db.get_tags(all())?.items.contains(&"Thriller".to_owned()));
+
+        // Permanently deleted books can't be restored
+        db.delete_book_by_id(2)?;
+        assert_eq!(db.fetch_books(all())?.total, 2);
+        assert!(db.restore_book(2).is_err());
+        assert!(db.get_book(2).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn list_and_purge_trash() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        assert!(db.list_trash()?.is_empty());
+
+        db.trash_book(1)?;
+        db.trash_book(3)?;
+        // Book 3 was deleted long ago
+        db.conn.execute(
+            "UPDATE books SET deleted_at = unixepoch() - 40 * 86400 WHERE id = 3",
+            [],
+        )?;
+
+        let trash = db.list_trash()?;
+        assert_eq!(trash.iter().map(|b| b.id).collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(trash[0].authors, vec!["David Lagercrantz"]);
+        assert_eq!(db.fetch_books(SearchConfig::new("").build())?.total, 1);
+
+        assert_eq!(db.purge_trash(30)?, 1);
+        assert!(db.get_book(3).is_err());
+        assert_eq!(db.list_trash()?.len(), 1);
+
+        assert_eq!(db.purge_trash(0)?, 1);
+        assert!(db.list_trash()?.is_empty());
+        assert_eq!(db.fetch_books(SearchConfig::new("").build())?.total, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn publish_year_range_of_dated_books() -> Result {
+        let mut db = SqliteStore::in_memory(false)?;
+        assert_eq!(db.publish_year_range()?, None);
+
+        let mut db = SqliteStore::new_in_memory()?;
+        assert_eq!(db.publish_year_range()?, Some((2003, 2017)));
+
+        let mut book = Book {
+            authors: vec!["Goethe".to_owned()],
+            isbn: "9783161484100".to_owned(),
+            lang: "DE".to_owned(),
+            title: "The Famous One".to_owned(),
             publish_date: Some(Utc.with_ymd_and_hms(1743, 1, 12, 13, 14, 44).unwrap()),
-            id: 123465798, // Should never be set or inserted
-            created: Utc::now()
-                .checked_sub_signed(chrono::Duration::seconds(1000000))
-                .unwrap(),
-            updated: Utc::now()
-                .checked_sub_signed(chrono::Duration::seconds(1000000))
-                .unwrap(),
+            ..Default::default()
         };
+        db.add_book(&mut book)?;
+        db.add_book(&mut Book {
+            isbn: test_isbn(1),
+            title: "Undated".to_owned(),
+            publish_date: None,
+            ..book.clone()
+        })?;
+        assert_eq!(db.publish_year_range()?, Some((1743, 2017)));
 
-        db.add_book(&mut new_book)?;
-        let saved_book = db.get_book(new_book.id)?;
+        Ok(())
+    }
 
-        assert_eq!(new_book.title, saved_book.title);
-        assert_eq!(new_book.sub_title, saved_book.sub_title);
-        assert_eq!(new_book.isbn, saved_book.isbn);
-        assert_eq!(new_book.cover_img, saved_book.cover_img);
-        assert_eq!(new_book.description, saved_book.description);
-        assert_eq!(new_book.lang, saved_book.lang);
-        assert_eq!(new_book.publisher, saved_book.publisher);
-        assert_eq!(new_book.publish_date, saved_book.publish_date);
+    #[test]
+    fn compact_database() -> Result {
+        let path = create_db_file("bookshelf-compact")?;
+        let conn = Connection::open(&path)?;
+        conn.pragma_update(None, "journal_mode", "wal")?;
+        let mut db = SqliteStore::from_connection(conn)?;
 
-        assert_eq!(new_book.created, saved_book.created);
-        assert_eq!(new_book.updated, saved_book.updated);
+        let mut book = Book {
+            authors: vec!["Goethe".to_owned()],
+            description: Some("Faust ".repeat(10000)),
+            isbn: "9783161484100".to_owned(),
+            lang: "DE".to_owned(),
+            title: "Faust".to_owned(),
+            ..Default::default()
+        };
+        db.add_book(&mut book)?;
+        db.delete_book_by_id(book.id)?;
 
-        assert_eq!(new_book.authors, saved_book.authors);
-        assert_eq!(new_book.tags, saved_book.tags);
+        db.compact()?;
+        let wal = PathBuf::from(format!("{}-wal", path.to_string_lossy()));
+        assert_eq!(fs::metadata(&wal)?.len(), 0);
+        assert_eq!(db.count_books(SearchConfig::new("").build())?, 1);
+        assert_eq!(db.get_book(1)?.authors, vec!["David Lagercrantz"]);
+
+        drop(db);
+        let _ = fs::remove_file(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn backup_to_file() -> Result {
+        let db = SqliteStore::new_in_memory()?;
+        let dest = std::env::temp_dir().join(format!("bookshelf-backup-{}.db", std::process::id()));
+        let _ = fs::remove_file(&dest);
+
+        db.backup_to(&dest)?;
+        drop(db);
+
+        let mut copy = SqliteStore::from_connection(Connection::open(&dest)?)?;
+        assert_eq!(copy.count_books(SearchConfig::new("").build())?, 3);
+        assert_eq!(
+            copy.fetch_books(SearchConfig::new("Salander").build())?
+                .total,
+            1
+        );
+        drop(copy);
+
+        let _ = fs::remove_file(&dest);
+        Ok(())
+    }
+
+    #[test]
+    fn clear_all_books() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        db.trash_book(3)?;
+
+        db.clear_all()?;
+
+        let books = db.fetch_books(SearchConfig::new("").build())?;
+        assert_eq!(books.total, 0);
+        assert!(books.items.is_empty());
+        for table in ["books", "authors", "tags", "books_fts"] {
+            let count: i64 =
+                db.conn
+                    .query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+                        row.get(0)
+                    })?;
+            assert_eq!(count, 0, "table {} is not empty", table);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn detect_unhealthy_connection() -> Result {
+        let db = SqliteStore::new_in_memory()?;
+        assert!(db.is_healthy());
+
+        // A transaction left open must not be handed to the next caller
+        db.conn.execute_batch("BEGIN")?;
+        assert!(!db.is_healthy());
+
+        db.conn.execute_batch("ROLLBACK")?;
+        assert!(db.is_healthy());
+        Ok(())
+    }
+
+    #[test]
+    fn checkpoint_wal_on_drop() -> Result {
+        let path = create_db_file("bookshelf-checkpoint-on-drop")?;
+        let wal = PathBuf::from(format!("{}-wal", path.to_string_lossy()));
+        let db_size = || fs::metadata(&path).map(|m| m.len());
+
+        let conn = Connection::open(&path)?;
+        conn.pragma_update(None, "journal_mode", "wal")?;
+        let mut db = SqliteStore::from_connection(conn)?;
+
+        // Keeps the database open, so closing the store doesn't remove the WAL
+        let other = Connection::open(&path)?;
+        other.query_row("SELECT COUNT(*) FROM books", [], |row| {
+            row.get::<usize, i64>(0)
+        })?;
+        let size = db_size()?;
+
+        db.add_book(&mut Book {
+            authors: vec!["Goethe".to_owned()],
+            description: Some("Faust ".repeat(10000)),
+            isbn: "9783161484100".to_owned(),
+            lang: "DE".to_owned(),
+            title: "Faust".to_owned(),
+            ..Default::default()
+        })?;
+        assert!(fs::metadata(&wal)?.len() > 0);
+        assert_eq!(db_size()?, size);
+
+        // The pages of the WAL are copied into the database, the WAL itself is reused
+        drop(db);
+        assert!(db_size()? > size);
+
+        drop(other);
+        let _ = fs::remove_file(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn tags_with_prefix_first() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        let book = db.get_book(1)?;
+        db.update_book(&mut Book {
+            tags: Some(vec!["Kiwi".to_owned(), "Thriller".to_owned()]),
+            ..book
+        })?;
+
+        let tags = db.get_tags(SearchConfig::new("Wi").use_prefix(true).build())?;
+        assert_eq!(tags.total, 3);
+        let mut first = tags.items[..2].to_vec();
+        first.sort();
+        assert_eq!(first, vec!["Wireless", "Wissenschaft"]);
+        assert_eq!(tags.items[2], "Kiwi");
+
+        let authors = db.get_authors(SearchConfig::new("Da").use_prefix(true).build())?;
+        assert_eq!(authors.items, vec!["David Lagercrantz", "Richard Dawkins"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn search_names_with_wildcards() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        let book = db.get_book(1)?;
+        db.update_book(&mut Book {
+            authors: vec!["A_B".to_owned(), "AxB".to_owned()],
+            tags: Some(vec!["100%".to_owned(), "1000".to_owned()]),
+            ..book
+        })?;
+
+        let tags = db.get_tags(SearchConfig::new("0%").build())?;
+        assert_eq!(tags.items, vec!["100%"]);
+        let tags = db.get_tags(SearchConfig::new("%").use_prefix(true).build())?;
+        assert_eq!(tags.items, vec!["100%"]);
+
+        let authors = db.get_authors(SearchConfig::new("A_").use_prefix(true).build())?;
+        assert_eq!(authors.items, vec!["A_B"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rename_and_delete_tags() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        let book = db.get_book(2)?;
+        db.update_book(&mut Book {
+            tags: Some(vec!["Suspense".to_owned()]),
+            ..book
+        })?;
+
+        // Book 1 already has both tags
+        assert_eq!(db.rename_tag("Suspense", "Thriller")?, 2);
+        assert_eq!(db.get_book(1)?.tags, Some(vec!["Thriller".to_owned()]));
+        assert_eq!(db.get_book(2)?.tags, Some(vec!["Thriller".to_owned()]));
+        let rows: i64 = db.conn.query_row(
+            "SELECT COUNT(*) FROM tags WHERE tag = 'Thriller'",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(rows, 2);
+        assert_eq!(db.rename_tag("Suspense", "Thriller")?, 0);
+        assert!(db.rename_tag("Thriller", " ").is_err());
+
+        assert_eq!(db.delete_tag("Thriller")?, 2);
+        assert_eq!(db.get_book(1)?.tags, None);
+        assert_eq!(db.delete_tag("Thriller")?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rename_author_without_duplicates() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        let book = db.get_book(1)?;
+        db.update_book(&mut Book {
+            authors: vec!["David Lagercrantz".to_owned(), "D. Lagercrantz".to_owned()],
+            ..book
+        })?;
+
+        assert_eq!(db.rename_author("D. Lagercrantz", "David Lagercrantz")?, 1);
+        assert_eq!(db.get_book(1)?.authors, vec!["David Lagercrantz"]);
+        assert_eq!(db.rename_author("Jochen Schiller", " J. Schiller ")?, 1);
+        assert_eq!(db.get_book(2)?.authors, vec!["J. Schiller"]);
+        assert_eq!(db.rename_author("Unknown", "Nobody")?, 0);
+        assert!(db.rename_author("J. Schiller", "").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn load_tags_for_multiple_books() -> Result {
+        let mut db = SqliteStore::new_in_memory()?;
+        let book = db.get_book(2)?;
+        db.update_book(&mut Book { tags: None, ..book })?;
+
+        let tags = db.tags_for_books(&[1, 2, 3, 42])?;
+        assert_eq!(tags.len(), 3);
+        assert_eq!(tags[&1], vec!["Suspense", "Thriller"]);
+        assert!(tags[&2].is_empty());
+        assert_eq!(tags[&3], vec!["Biologie", "Religion", "Wissenschaft"]);
+        assert!(db.tags_for_books(&[])?.is_empty());
 
         Ok(())
     }
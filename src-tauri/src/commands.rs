@@ -3,17 +3,31 @@
 // Use of this source code is governed by an BSD-style
 // license that can be found in the LICENSE file.
 
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
-use log::{debug, error};
+use chrono::{DateTime, Datelike, Utc};
+use log::{debug, error, warn};
 use serde::Serialize;
 use tauri::{api::dialog::blocking::FileDialogBuilder, State};
 use tauri::{AppHandle, Manager};
 
-use crate::books::models::{self, Book, BookError, SearchConfig, StoreResult};
+use crate::books::export::{export_to_writer, ExportFormat, ImportInfo, ImportReport};
+use crate::books::merge::MergeSummary;
+use crate::books::metadata::{MetadataProvider, OpenLibraryProvider};
+use crate::books::models::{
+    self, Book, BookError, DbTuning, Direction, LibraryStats, NameCollation, ReadingStatus,
+    SearchConfig, StoreResult,
+};
+use crate::books::operation::CancellationToken;
+use crate::books::sample;
 use crate::books::{self, BookManager, BookManagerEvent, BookPool, BOOK_MANAGER_EVENTS};
+use crate::pool::PoolMode;
 use crate::rec_pois;
-use crate::settings::{SettingsError, UserSettings};
+use crate::settings::{SettingsChanged, SettingsError, UserSettings, SETTINGS_EVENTS};
 
 macro_rules! from_err_api {
     ($code:literal) => {
@@ -54,7 +68,13 @@ from_err_api!(BookError,
     BookError::NotFound => from_err_api!(41),
     BookError::DBError(e) => from_err_api!(e.to_string(),42),
     BookError::EmptyAuthors => from_err_api!(43),
-    BookError::InvalidBook{ field: _, reason: _} => from_err_api!(44)
+    e @ BookError::InvalidBook{ .. } => from_err_api!(e.to_string(), 44),
+    e @ BookError::DuplicateIsbn(_) => from_err_api!(e.to_string(), 45),
+    e @ BookError::IncompatibleDatabase(_) => from_err_api!(e.to_string(), 46),
+    e @ BookError::Conflict => from_err_api!(e.to_string(), 47),
+    e @ BookError::MetadataLookup(_) => from_err_api!(e.to_string(), 48),
+    // Retriable, like books::Error::PoolTimeout
+    e @ BookError::Busy => from_err_api!(e.to_string(), 49)
 );
 
 from_err_api!(books::Error,
@@ -62,13 +82,19 @@ from_err_api!(books::Error,
     books::Error::PoolNotFound => from_err_api!(21),
     books::Error::CurrentPoolNotSet => from_err_api!(22),
     books::Error::BookError(e) =>  e.into(),
-    books::Error::ConversionFailed => from_err_api!(23)
+    books::Error::ConversionFailed => from_err_api!(23),
+    books::Error::IoError(e) => from_err_api!(e.to_string(), 24),
+    e @ books::Error::PoolTimeout => from_err_api!(e.to_string(), 25)
 );
 
 from_err_api!(tauri::Error,
     e => from_err_api!(format!("{:?}",e), 11)
 );
 
+from_err_api!(io::Error,
+    e => from_err_api!(e.to_string(), 12)
+);
+
 from_err_api!(SettingsError ,
     e => from_err_api!(format!("{:?}",e), 30)
 );
@@ -76,10 +102,12 @@ from_err_api!(SettingsError ,
 #[derive(Debug)]
 pub enum CommandError {
     UserAborted,
+    NotConfirmed,
 }
 
 from_err_api!(CommandError,
-    CommandError::UserAborted => from_err_api!(1)
+    CommandError::UserAborted => from_err_api!(1),
+    CommandError::NotConfirmed => from_err_api!(2)
 );
 
 type Result<T = (), E = ApiError> = std::result::Result<T, E>;
@@ -134,20 +162,77 @@ impl UserSettingsAPI {
         settings.theme = theme.as_ref().to_owned()
     }
 
+    pub fn get_auto_vacuum_threshold_mb(&self) -> Option<u64> {
+        let settings = rec_pois!(self.0);
+        settings.auto_vacuum_on_close_threshold_mb
+    }
+
+    pub fn set_auto_vacuum_threshold_mb(&self, threshold_mb: Option<u64>) {
+        let mut settings = rec_pois!(self.0);
+        settings.auto_vacuum_on_close_threshold_mb = threshold_mb
+    }
+
+    pub fn get_use_trash(&self) -> bool {
+        let settings = rec_pois!(self.0);
+        settings.use_trash
+    }
+
+    pub fn set_use_trash(&self, use_trash: bool) {
+        let mut settings = rec_pois!(self.0);
+        settings.use_trash = use_trash
+    }
+
+    pub fn get_pool_size(&self) -> usize {
+        let settings = rec_pois!(self.0);
+        settings.pool_size
+    }
+
+    pub fn set_pool_size(&self, pool_size: usize) {
+        let mut settings = rec_pois!(self.0);
+        settings.pool_size = pool_size.max(1)
+    }
+
+    pub fn get_db_tuning(&self) -> DbTuning {
+        let settings = rec_pois!(self.0);
+        settings.db_tuning
+    }
+
+    pub fn set_db_tuning(&self, tuning: DbTuning) {
+        let mut settings = rec_pois!(self.0);
+        settings.db_tuning = tuning
+    }
+
+    pub fn get_name_collation(&self) -> NameCollation {
+        let settings = rec_pois!(self.0);
+        settings.name_collation
+    }
+
+    pub fn set_name_collation(&self, collation: NameCollation) {
+        let mut settings = rec_pois!(self.0);
+        settings.name_collation = collation
+    }
+
     pub fn add_history<T>(&self, path: T)
     where
         T: AsRef<str>,
     {
-        if path.as_ref().is_empty() {
-            return;
-        }
+        let mut settings = rec_pois!(self.0);
+        settings.add_history(path.as_ref())
+    }
 
+    pub fn add_recent_search(&self, text: &str) {
         let mut settings = rec_pois!(self.0);
-        let p = path.as_ref().to_string();
-        if !settings.book_history.contains(&p) {
-            settings.book_history.push(p)
-        }
-        settings.book_history.sort();
+        settings.add_recent_search(text)
+    }
+
+    pub fn get_recent_searches(&self) -> Vec<String> {
+        let settings = rec_pois!(self.0);
+        settings.recent_searches.clone()
+    }
+
+    pub fn clear_recent_searches(&self) {
+        let mut settings = rec_pois!(self.0);
+        settings.recent_searches.clear()
     }
 
     pub fn remove_history<T>(&self, path: T)
@@ -164,6 +249,22 @@ impl UserSettingsAPI {
         s.book_history.clone()
     }
 
+    /// Removes all paths of missing files from the history and returns the remaining
+    /// paths. Relative paths are resolved against the working directory, so an entry
+    /// is kept only once, no matter how its file was referenced.
+    pub fn prune_history(&self) -> Vec<String> {
+        let mut settings = rec_pois!(self.0);
+        let mut files: Vec<PathBuf> = Vec::new();
+        settings.book_history.retain(|p| match fs::canonicalize(p) {
+            Ok(file) if file.is_file() && !files.contains(&file) => {
+                files.push(file);
+                true
+            }
+            _ => false,
+        });
+        settings.book_history.clone()
+    }
+
     pub fn save_settings(&self) -> Result<(), SettingsError> {
         let s = rec_pois!(self.0);
         match s.save_to_user_dir() {
@@ -185,6 +286,13 @@ pub async fn get_history(settings: State<'_, UserSettingsAPI>) -> Result<Vec<Str
     Ok(settings.get_history())
 }
 
+/// Removes databases which don't exist anymore from the history and returns it.
+#[tauri::command]
+pub async fn prune_history(settings: State<'_, UserSettingsAPI>) -> Result<Vec<String>> {
+    debug!("calling prune_history command");
+    Ok(settings.prune_history())
+}
+
 #[tauri::command]
 pub async fn remove_history(path: String, settings: State<'_, UserSettingsAPI>) -> Result {
     debug!("calling remove_history command");
@@ -193,10 +301,17 @@ pub async fn remove_history(path: String, settings: State<'_, UserSettingsAPI>)
 }
 
 #[tauri::command]
-pub async fn set_lang(lang: String, settings: State<'_, UserSettingsAPI>) -> Result {
+pub async fn set_lang(
+    lang: String,
+    settings: State<'_, UserSettingsAPI>,
+    app: AppHandle,
+) -> Result {
     debug!("calling set_lang command");
-    settings.set_current_lang(lang);
+    settings.set_current_lang(&lang);
     settings.save_settings()?;
+
+    app.emit_all(SETTINGS_EVENTS, SettingsChanged::Lang(lang))?;
+
     Ok(())
 }
 
@@ -207,10 +322,17 @@ pub async fn current_lang(settings: State<'_, UserSettingsAPI>) -> Result<String
 }
 
 #[tauri::command]
-pub async fn set_theme(theme: String, settings: State<'_, UserSettingsAPI>) -> Result {
+pub async fn set_theme(
+    theme: String,
+    settings: State<'_, UserSettingsAPI>,
+    app: AppHandle,
+) -> Result {
     debug!("calling set_theme command");
-    settings.set_theme(theme);
+    settings.set_theme(&theme);
     settings.save_settings()?;
+
+    app.emit_all(SETTINGS_EVENTS, SettingsChanged::Theme(theme))?;
+
     Ok(())
 }
 
@@ -221,10 +343,17 @@ pub async fn current_theme(settings: State<'_, UserSettingsAPI>) -> Result<Strin
 }
 
 #[tauri::command]
-pub async fn set_menu_expanded(expanded: bool, settings: State<'_, UserSettingsAPI>) -> Result {
+pub async fn set_menu_expanded(
+    expanded: bool,
+    settings: State<'_, UserSettingsAPI>,
+    app: AppHandle,
+) -> Result {
     debug!("calling set_menu_expanded command");
     settings.set_menu_expanded(expanded);
     settings.save_settings()?;
+
+    app.emit_all(SETTINGS_EVENTS, SettingsChanged::MenuExpanded(expanded))?;
+
     Ok(())
 }
 
@@ -234,6 +363,120 @@ pub async fn get_menu_expanded(settings: State<'_, UserSettingsAPI>) -> Result<b
     Ok(settings.get_menu_expanded())
 }
 
+#[tauri::command]
+pub async fn set_auto_vacuum_threshold(
+    threshold_mb: Option<u64>,
+    settings: State<'_, UserSettingsAPI>,
+) -> Result {
+    debug!(
+        "calling set_auto_vacuum_threshold command with param: {:?}",
+        threshold_mb
+    );
+    settings.set_auto_vacuum_threshold_mb(threshold_mb);
+    settings.save_settings()?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_auto_vacuum_threshold(
+    settings: State<'_, UserSettingsAPI>,
+) -> Result<Option<u64>> {
+    debug!("calling get_auto_vacuum_threshold command");
+    Ok(settings.get_auto_vacuum_threshold_mb())
+}
+
+#[tauri::command]
+pub async fn set_use_trash(use_trash: bool, settings: State<'_, UserSettingsAPI>) -> Result {
+    debug!("calling set_use_trash command with param: {}", use_trash);
+    settings.set_use_trash(use_trash);
+    settings.save_settings()?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_use_trash(settings: State<'_, UserSettingsAPI>) -> Result<bool> {
+    debug!("calling get_use_trash command");
+    Ok(settings.get_use_trash())
+}
+
+/// Sets the number of idle connections kept per database, applies to databases
+/// opened afterwards.
+#[tauri::command]
+pub async fn set_pool_size(pool_size: usize, settings: State<'_, UserSettingsAPI>) -> Result {
+    debug!("calling set_pool_size command with param: {}", pool_size);
+    settings.set_pool_size(pool_size);
+    settings.save_settings()?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_pool_size(settings: State<'_, UserSettingsAPI>) -> Result<usize> {
+    debug!("calling get_pool_size command");
+    Ok(settings.get_pool_size())
+}
+
+/// Sets the journal mode, synchronous level and busy timeout of connections, applies
+/// to databases opened afterwards.
+#[tauri::command]
+pub async fn set_db_tuning(tuning: DbTuning, settings: State<'_, UserSettingsAPI>) -> Result {
+    debug!("calling set_db_tuning command with param: {:?}", tuning);
+    settings.set_db_tuning(tuning);
+    settings.save_settings()?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_db_tuning(settings: State<'_, UserSettingsAPI>) -> Result<DbTuning> {
+    debug!("calling get_db_tuning command");
+    Ok(settings.get_db_tuning())
+}
+
+/// Records a submitted search text, which is offered as suggestion afterwards.
+#[tauri::command]
+pub async fn record_search(text: String, settings: State<'_, UserSettingsAPI>) -> Result {
+    debug!("calling record_search command with text: {}", text);
+    settings.add_recent_search(&text);
+    settings.save_settings()?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_recent_searches(settings: State<'_, UserSettingsAPI>) -> Result<Vec<String>> {
+    debug!("calling get_recent_searches command");
+    Ok(settings.get_recent_searches())
+}
+
+#[tauri::command]
+pub async fn clear_recent_searches(settings: State<'_, UserSettingsAPI>) -> Result {
+    debug!("calling clear_recent_searches command");
+    settings.clear_recent_searches();
+    settings.save_settings()?;
+    Ok(())
+}
+
+/// Sets the ordering of authors and tags and applies it to all open databases.
+#[tauri::command]
+pub async fn set_name_collation(
+    collation: NameCollation,
+    manager: State<'_, BookManagerState>,
+    settings: State<'_, UserSettingsAPI>,
+) -> Result {
+    debug!(
+        "calling set_name_collation command with param: {:?}",
+        collation
+    );
+    rec_pois!(manager.0).set_name_collation(collation)?;
+    settings.set_name_collation(collation);
+    settings.save_settings()?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_name_collation(settings: State<'_, UserSettingsAPI>) -> Result<NameCollation> {
+    debug!("calling get_name_collation command");
+    Ok(settings.get_name_collation())
+}
+
 /*******************************************************
  *
  * Book API
@@ -258,6 +501,54 @@ pub async fn set_current_db(
     Ok(())
 }
 
+/// Result of a database switch.
+#[derive(Debug, Serialize)]
+pub struct CurrentDBSwitch {
+    previous: Option<String>,
+    current: String,
+}
+
+/// Switches the current database and returns the previous and new one. If the target
+/// database was closed in the meantime, an error is returned and nothing changes.
+#[tauri::command]
+pub async fn try_set_current_db(
+    db: String,
+    manager: State<'_, BookManagerState>,
+    app: AppHandle,
+) -> Result<CurrentDBSwitch> {
+    debug!("calling try_set_current_db command with param: {}", db);
+    let mut m = rec_pois!(manager.0);
+    let previous = m.swap_current_pool(&db)?;
+
+    app.emit_all(
+        BOOK_MANAGER_EVENTS,
+        BookManagerEvent::CurrentDBChanged(db.clone()),
+    )?;
+
+    Ok(CurrentDBSwitch {
+        previous,
+        current: db,
+    })
+}
+
+/// Returns the names of all open databases, sorted by name.
+#[tauri::command]
+pub async fn list_open_databases(manager: State<'_, BookManagerState>) -> Result<Vec<String>> {
+    debug!("calling list_open_databases command");
+    let m = rec_pois!(manager.0);
+    let mut pools: Vec<String> = m.get_pools().into_iter().map(String::from).collect();
+    pools.sort();
+    Ok(pools)
+}
+
+/// Returns the name of the current database, `None` if no database is selected.
+#[tauri::command]
+pub async fn current_database(manager: State<'_, BookManagerState>) -> Result<Option<String>> {
+    debug!("calling current_database command");
+    let m = rec_pois!(manager.0);
+    Ok(m.current_pool_name().ok())
+}
+
 #[tauri::command]
 pub async fn fetch_book(
     search: SearchConfig<models::ConfigInitialized>,
@@ -269,6 +560,226 @@ pub async fn fetch_book(
     Ok(result)
 }
 
+/// Returns the tags of the current database matching the search text, e.g. for type-ahead.
+/// With `prefix` set, tags starting with the text are listed first.
+#[tauri::command]
+pub async fn get_tags(
+    search: SearchConfig<models::ConfigInitialized>,
+    manager: State<'_, BookManagerState>,
+) -> Result<StoreResult<String>> {
+    debug!("calling get_tags command with params: {:?}", search);
+    let m = rec_pois!(manager.0);
+    Ok(m.get_current_pool()?.get_tags(search)?)
+}
+
+/// Returns the authors of the current database matching the search text, e.g. for type-ahead.
+/// With `prefix` set, authors starting with the text are listed first.
+#[tauri::command]
+pub async fn get_authors(
+    search: SearchConfig<models::ConfigInitialized>,
+    manager: State<'_, BookManagerState>,
+) -> Result<StoreResult<String>> {
+    debug!("calling get_authors command with params: {:?}", search);
+    let m = rec_pois!(manager.0);
+    Ok(m.get_current_pool()?.get_authors(search)?)
+}
+
+/// Returns the next `count` books after (or before) the book with given id, which
+/// lets the slideshow prefetch a window of covers.
+#[tauri::command]
+pub async fn get_neighbors(
+    id: i64,
+    search: SearchConfig<models::ConfigInitialized>,
+    direction: Direction,
+    count: u64,
+    manager: State<'_, BookManagerState>,
+) -> Result<Vec<Book>> {
+    debug!(
+        "calling get_neighbors command with id: {}, direction: {:?}, count: {}, search: {:?}",
+        id, direction, count, search
+    );
+    let m = rec_pois!(manager.0);
+    let books = m
+        .get_current_pool()?
+        .get_neighbors(id, search, direction, count)?;
+    Ok(books)
+}
+
+/// Returns figures of the current database for the statistics dashboard.
+#[tauri::command]
+pub async fn library_statistics(manager: State<'_, BookManagerState>) -> Result<LibraryStats> {
+    debug!("calling library_statistics command");
+    let m = rec_pois!(manager.0);
+    Ok(m.get_current_pool()?.statistics()?)
+}
+
+/// Returns the number of books per reading status for the progress dashboard.
+#[tauri::command]
+pub async fn status_counts(
+    manager: State<'_, BookManagerState>,
+) -> Result<Vec<(ReadingStatus, u64)>> {
+    debug!("calling status_counts command");
+    let m = rec_pois!(manager.0);
+    Ok(m.get_current_pool()?.status_counts()?)
+}
+
+/// Logs the dates the given book was started and finished reading, a finish
+/// date marks the book as read. Returns the updated book.
+#[tauri::command]
+pub async fn log_reading(
+    id: i64,
+    started: Option<DateTime<Utc>>,
+    finished: Option<DateTime<Utc>>,
+    manager: State<'_, BookManagerState>,
+) -> Result<Book> {
+    debug!(
+        "calling log_reading command with id: {}, started: {:?}, finished: {:?}",
+        id, started, finished
+    );
+    let m = rec_pois!(manager.0);
+    Ok(m.get_current_pool()?.log_reading(id, started, finished)?)
+}
+
+/// Returns all books finished within the given year, defaults to the current year.
+#[tauri::command]
+pub async fn finished_books(
+    year: Option<i32>,
+    manager: State<'_, BookManagerState>,
+) -> Result<Vec<Book>> {
+    debug!("calling finished_books command with year: {:?}", year);
+    let year = year.unwrap_or_else(|| Utc::now().year());
+    let m = rec_pois!(manager.0);
+    Ok(m.get_current_pool()?.finished_in_year(year)?)
+}
+
+/// Merges entries of the same book into the one with `keep_id` and deletes the
+/// others. Returns the merged book.
+#[tauri::command]
+pub async fn merge_books(
+    keep_id: i64,
+    merge_ids: Vec<i64>,
+    manager: State<'_, BookManagerState>,
+) -> Result<Book> {
+    debug!(
+        "calling merge_books command with keep_id: {}, merge_ids: {:?}",
+        keep_id, merge_ids
+    );
+    let m = rec_pois!(manager.0);
+    Ok(m.get_current_pool()?.merge_books(keep_id, &merge_ids)?)
+}
+
+/// Returns the tags of all given books, so the bulk tag editor can show common and mixed tags.
+#[tauri::command]
+pub async fn tags_for_books(
+    ids: Vec<i64>,
+    manager: State<'_, BookManagerState>,
+) -> Result<HashMap<i64, Vec<String>>> {
+    debug!("calling tags_for_books command with ids: {:?}", ids);
+    let m = rec_pois!(manager.0);
+    Ok(m.get_current_pool()?.tags_for_books(&ids)?)
+}
+
+/// Renames a tag in all books of the current database, e.g. to fix a typo. Returns
+/// the number of affected books.
+#[tauri::command]
+pub async fn rename_tag(
+    from: String,
+    to: String,
+    manager: State<'_, BookManagerState>,
+) -> Result<u64> {
+    debug!("calling rename_tag command with from: {}, to: {}", from, to);
+    let m = rec_pois!(manager.0);
+    Ok(m.get_current_pool()?.rename_tag(&from, &to)?)
+}
+
+/// Removes a tag from all books of the current database. Returns the number of
+/// affected books.
+#[tauri::command]
+pub async fn delete_tag(tag: String, manager: State<'_, BookManagerState>) -> Result<u64> {
+    debug!("calling delete_tag command with tag: {}", tag);
+    let m = rec_pois!(manager.0);
+    Ok(m.get_current_pool()?.delete_tag(&tag)?)
+}
+
+/// Renames an author in all books of the current database, e.g. to merge different
+/// spellings. Returns the number of affected books.
+#[tauri::command]
+pub async fn rename_author(
+    from: String,
+    to: String,
+    manager: State<'_, BookManagerState>,
+) -> Result<u64> {
+    debug!(
+        "calling rename_author command with from: {}, to: {}",
+        from, to
+    );
+    let m = rec_pois!(manager.0);
+    Ok(m.get_current_pool()?.rename_author(&from, &to)?)
+}
+
+/// Returns the earliest and latest publish year of the current database, which
+/// bounds the year filter. Returns `None` if no book has a publish date.
+#[tauri::command]
+pub async fn publish_year_range(
+    manager: State<'_, BookManagerState>,
+) -> Result<Option<(i32, i32)>> {
+    debug!("calling publish_year_range command");
+    let m = rec_pois!(manager.0);
+    Ok(m.get_current_pool()?.publish_year_range()?)
+}
+
+/// Returns groups of probable duplicates with the same ISBN, or the same title and
+/// first author if they have no ISBN. Nothing is changed.
+#[tauri::command]
+pub async fn find_duplicates(manager: State<'_, BookManagerState>) -> Result<Vec<Vec<Book>>> {
+    debug!("calling find_duplicates command");
+    let m = rec_pois!(manager.0);
+    Ok(m.get_current_pool()?.find_duplicates()?)
+}
+
+/// Returns groups of books with the same title, which are potential duplicates
+/// even without a matching ISBN.
+#[tauri::command]
+pub async fn list_title_duplicates(
+    manager: State<'_, BookManagerState>,
+) -> Result<Vec<(String, Vec<Book>)>> {
+    debug!("calling list_title_duplicates command");
+    let m = rec_pois!(manager.0);
+    Ok(m.get_current_pool()?.title_duplicates()?)
+}
+
+/// Returns the ids of books in the current database whose authors or tags are not
+/// ordered according to the configured collation.
+#[tauri::command]
+pub async fn check_name_order(manager: State<'_, BookManagerState>) -> Result<Vec<i64>> {
+    debug!("calling check_name_order command");
+    let m = rec_pois!(manager.0);
+    Ok(m.get_current_pool()?.check_name_order()?)
+}
+
+/// Returns only the number of books matching the search text, which is cheap
+/// enough to be called while the user is typing.
+#[tauri::command]
+pub async fn search_preview(text: String, manager: State<'_, BookManagerState>) -> Result<u64> {
+    debug!("calling search_preview command with text: {}", text);
+    let m = rec_pois!(manager.0);
+    let count = m
+        .get_current_pool()?
+        .count_books(SearchConfig::new(&text).build())?;
+    Ok(count)
+}
+
+/// Returns the number of books in the current database, books in the trash are not counted.
+#[tauri::command]
+pub async fn count_books(manager: State<'_, BookManagerState>) -> Result<u64> {
+    debug!("calling count_books command");
+    let m = rec_pois!(manager.0);
+    let count = m
+        .get_current_pool()?
+        .count_books(SearchConfig::new("").build())?;
+    Ok(count)
+}
+
 #[tauri::command]
 pub async fn update_book(mut book: Book, manager: State<'_, BookManagerState>) -> Result<Book> {
     debug!("calling update_book command with book: {:?}", book);
@@ -277,14 +788,71 @@ pub async fn update_book(mut book: Book, manager: State<'_, BookManagerState>) -
     Ok(book)
 }
 
+/// Moves the book to the trash or deletes it permanently, depending on the user settings.
 #[tauri::command]
-pub async fn delete_book(id: i64, manager: State<'_, BookManagerState>) -> Result {
+pub async fn delete_book(
+    id: i64,
+    manager: State<'_, BookManagerState>,
+    settings: State<'_, UserSettingsAPI>,
+) -> Result {
     debug!("calling delete_book command with id: {:?}", id);
     let m = rec_pois!(manager.0);
-    m.get_current_pool()?.delete_book_by_id(id)?;
+    if settings.get_use_trash() {
+        m.get_current_pool()?.trash_book(id)?;
+    } else {
+        m.get_current_pool()?.delete_book_by_id(id)?;
+    }
     Ok(())
 }
 
+/// Moves the books to the trash or deletes them permanently, depending on the user
+/// settings. Returns the number of affected books.
+#[tauri::command]
+pub async fn delete_books(
+    ids: Vec<i64>,
+    manager: State<'_, BookManagerState>,
+    settings: State<'_, UserSettingsAPI>,
+) -> Result<u64> {
+    debug!("calling delete_books command with ids: {:?}", ids);
+    let m = rec_pois!(manager.0);
+    if settings.get_use_trash() {
+        Ok(m.get_current_pool()?.trash_books(&ids)?)
+    } else {
+        Ok(m.get_current_pool()?.delete_books_by_ids(&ids)?)
+    }
+}
+
+#[tauri::command]
+pub async fn restore_book(id: i64, manager: State<'_, BookManagerState>) -> Result {
+    debug!("calling restore_book command with id: {:?}", id);
+    let m = rec_pois!(manager.0);
+    m.get_current_pool()?.restore_book(id)?;
+    Ok(())
+}
+
+/// Returns the books in the trash of the current database.
+#[tauri::command]
+pub async fn list_trash(manager: State<'_, BookManagerState>) -> Result<Vec<Book>> {
+    debug!("calling list_trash command");
+    let m = rec_pois!(manager.0);
+    Ok(m.get_current_pool()?.list_trash()?)
+}
+
+/// Permanently deletes books which are in the trash for at least the given number
+/// of days, zero empties the trash. Returns the number of deleted books.
+#[tauri::command]
+pub async fn purge_trash(
+    older_than_days: u32,
+    manager: State<'_, BookManagerState>,
+) -> Result<u64> {
+    debug!(
+        "calling purge_trash command with older_than_days: {}",
+        older_than_days
+    );
+    let m = rec_pois!(manager.0);
+    Ok(m.get_current_pool()?.purge_trash(older_than_days)?)
+}
+
 #[tauri::command]
 pub async fn add_book(mut book: Book, manager: State<'_, BookManagerState>) -> Result<i64> {
     debug!("calling add_book command with book: {:?}", book);
@@ -293,6 +861,28 @@ pub async fn add_book(mut book: Book, manager: State<'_, BookManagerState>) -> R
     Ok(book.id)
 }
 
+/// Adds all books within one transaction and returns their ids in the given order.
+/// If a single book fails, none is added.
+#[tauri::command]
+pub async fn add_books(
+    mut books: Vec<Book>,
+    manager: State<'_, BookManagerState>,
+) -> Result<Vec<i64>> {
+    debug!("calling add_books command with {} books", books.len());
+    let m = rec_pois!(manager.0);
+    m.get_current_pool()?.add_books(&mut books)?;
+    Ok(books.iter().map(|b| b.id).collect())
+}
+
+/// Adds the sample books to the current database, which must be empty.
+#[tauri::command]
+pub async fn load_sample_data(manager: State<'_, BookManagerState>) -> Result<Vec<i64>> {
+    debug!("calling load_sample_data command");
+    let m = rec_pois!(manager.0);
+    let ids = sample::load_sample_data(&mut *m.get_current_pool()?)?;
+    Ok(ids)
+}
+
 #[tauri::command]
 pub async fn get_book(id: i64, manager: State<'_, BookManagerState>) -> Result<Book> {
     debug!("calling get_book command with id: {}", id);
@@ -300,12 +890,310 @@ pub async fn get_book(id: i64, manager: State<'_, BookManagerState>) -> Result<B
     Ok(m.get_current_pool()?.get_book(id)?)
 }
 
+/// Returns the book with the given ISBN, e.g. of a scanned barcode.
+#[tauri::command]
+pub async fn get_book_by_isbn(isbn: String, manager: State<'_, BookManagerState>) -> Result<Book> {
+    debug!("calling get_book_by_isbn command with isbn: {}", isbn);
+    let m = rec_pois!(manager.0);
+    Ok(m.get_current_pool()?.get_book_by_isbn(&isbn)?)
+}
+
+#[tauri::command]
+pub async fn get_book_hash(id: i64, manager: State<'_, BookManagerState>) -> Result<String> {
+    debug!("calling get_book_hash command with id: {}", id);
+    let m = rec_pois!(manager.0);
+    Ok(m.get_current_pool()?.get_book(id)?.content_hash())
+}
+
+#[tauri::command]
+pub async fn get_wal_autocheckpoint(manager: State<'_, BookManagerState>) -> Result<i64> {
+    debug!("calling get_wal_autocheckpoint command");
+    let m = rec_pois!(manager.0);
+    Ok(m.get_current_pool()?.get_wal_autocheckpoint()?)
+}
+
+#[tauri::command]
+pub async fn set_wal_autocheckpoint(pages: i64, manager: State<'_, BookManagerState>) -> Result {
+    debug!(
+        "calling set_wal_autocheckpoint command with pages: {}",
+        pages
+    );
+    let m = rec_pois!(manager.0);
+    m.get_current_book_pool()?.set_wal_autocheckpoint(pages)?;
+    Ok(())
+}
+
+/// Exports all books of the current database as JSON into the given file,
+/// `.gz` is appended to the path if compressed. Returns the written path.
+#[tauri::command]
+pub async fn export_json(
+    mut path: String,
+    compress: bool,
+    manager: State<'_, BookManagerState>,
+) -> Result<String> {
+    debug!("calling export_json command with path: {}", path);
+    if compress && !path.ends_with(".gz") {
+        path.push_str(".gz");
+    }
+    let format = match compress {
+        true => ExportFormat::JsonGzip,
+        false => ExportFormat::Json,
+    };
+
+    let m = rec_pois!(manager.0);
+    export_to_writer(
+        &mut *m.get_current_pool()?,
+        BufWriter::new(File::create(&path)?),
+        format,
+        &SearchConfig::new("").build(),
+    )?;
+    Ok(path)
+}
+
+/// Exports all books of the current database as CSV into the given file, one column
+/// per book field. Returns the number of exported books.
+#[tauri::command]
+pub async fn export_csv(path: String, manager: State<'_, BookManagerState>) -> Result<u64> {
+    debug!("calling export_csv command with path: {}", path);
+    let m = rec_pois!(manager.0);
+    let count = export_to_writer(
+        &mut *m.get_current_pool()?,
+        BufWriter::new(File::create(&path)?),
+        ExportFormat::Csv,
+        &SearchConfig::new("").build(),
+    )?;
+    Ok(count)
+}
+
+/// Shrinks the file of the current database to the size of its content, e.g. after
+/// deleting many books.
+#[tauri::command]
+pub async fn compact_database(manager: State<'_, BookManagerState>) -> Result {
+    debug!("calling compact_database command");
+    let m = rec_pois!(manager.0);
+    m.get_current_pool()?.compact()?;
+    Ok(())
+}
+
+/// Deletes all books of the current database, the file stays open and in the history.
+/// Nothing is deleted unless `confirm` is set.
+#[tauri::command]
+pub async fn clear_library(confirm: bool, manager: State<'_, BookManagerState>) -> Result {
+    debug!("calling clear_library command with confirm: {}", confirm);
+    if !confirm {
+        return Err(CommandError::NotConfirmed.into());
+    }
+    let m = rec_pois!(manager.0);
+    m.get_current_pool()?.clear_all()?;
+    Ok(())
+}
+
+/// Returns the book with the given id as pretty printed JSON, e.g. to share it.
+#[tauri::command]
+pub async fn export_book_json(id: i64, manager: State<'_, BookManagerState>) -> Result<String> {
+    debug!("calling export_book_json command with id: {}", id);
+    let m = rec_pois!(manager.0);
+    let book = m.get_current_pool()?.get_book(id)?;
+    Ok(books::export::book_to_json(&book)?)
+}
+
+/// The active metadata provider, OpenLibrary by default.
+pub struct MetadataProviderState(pub Box<dyn MetadataProvider + Send + Sync>);
+
+impl Default for MetadataProviderState {
+    fn default() -> Self {
+        Self(Box::new(OpenLibraryProvider::new()))
+    }
+}
+
+impl MetadataProviderState {
+    /// Looks up the book with the given ISBN, blocks until the provider answered.
+    pub fn lookup_isbn(&self, isbn: &str) -> Result<Book> {
+        let metadata = self.0.by_isbn(isbn).map_err(BookError::from)?;
+        Ok(metadata.into())
+    }
+}
+
+/// Looks up the book with the given ISBN by the active metadata provider. The returned
+/// book is not stored, it only prefills the fields known by the provider.
+#[tauri::command]
+pub async fn lookup_isbn(isbn: String, app: AppHandle) -> Result<Book> {
+    debug!("calling lookup_isbn command with isbn: {}", isbn);
+    // Providers block until they answered, keep them off the async runtime.
+    tauri::async_runtime::spawn_blocking(move || {
+        app.state::<MetadataProviderState>().lookup_isbn(&isbn)
+    })
+    .await?
+}
+
+/// Writes a consistent copy of the current database to the given file, which
+/// includes all changes still kept in the WAL.
+#[tauri::command]
+pub async fn backup_database(dest: String, manager: State<'_, BookManagerState>) -> Result {
+    debug!("calling backup_database command with dest: {}", dest);
+    let m = rec_pois!(manager.0);
+    m.get_current_pool()?.backup_to(Path::new(&dest))?;
+    Ok(())
+}
+
+/// Returns the format and number of books of an import file, so the user can be
+/// warned before a large import. No book is created.
+#[tauri::command]
+pub async fn inspect_import(file: String) -> Result<ImportInfo> {
+    debug!("calling inspect_import command with file: {}", file);
+    Ok(books::export::inspect_import(File::open(&file)?)?)
+}
+
+/// Imports all books of a JSON or CSV file into the current database. A dry run only
+/// validates the books, otherwise all books are added within one transaction. Nothing is
+/// added if any book fails, the report lists the failures per row.
+#[tauri::command]
+pub async fn import_books(
+    path: String,
+    dry_run: bool,
+    manager: State<'_, BookManagerState>,
+) -> Result<ImportReport> {
+    debug!(
+        "calling import_books command with path: {}, dry run: {}",
+        path, dry_run
+    );
+    let (mut new_books, mut report) = books::export::read_import(File::open(&path)?)?;
+    report.dry_run = dry_run;
+    if dry_run || report.failed > 0 {
+        return Ok(report);
+    }
+
+    let m = rec_pois!(manager.0);
+    m.get_current_pool()?.add_books(&mut new_books)?;
+    report.imported = new_books.len() as u64;
+    Ok(report)
+}
+
+/// Merges all books of the given open database into the current one, field by field.
+#[tauri::command]
+pub async fn merge_db(
+    source_db: String,
+    manager: State<'_, BookManagerState>,
+    operation: State<'_, OperationState>,
+) -> Result<MergeSummary> {
+    debug!("calling merge_db command with source: {}", source_db);
+    operation.0.reset();
+    let m = rec_pois!(manager.0);
+    Ok(m.merge_from(&source_db, &operation.0)?)
+}
+
+/// Sets missing or invalid creation and modification timestamps of the current
+/// database to now and returns the number of repaired timestamps.
+#[tauri::command]
+pub async fn repair_timestamps(manager: State<'_, BookManagerState>) -> Result<u64> {
+    debug!("calling repair_timestamps command");
+    let m = rec_pois!(manager.0);
+    Ok(m.get_current_pool()?.repair_timestamps()?)
+}
+
+/// Copies the cover of the given book to a file chosen by the user and returns its path.
 #[tauri::command]
-pub async fn close_db(manager: State<'_, BookManagerState>, app: AppHandle) -> Result {
+pub async fn export_cover(id: i64, manager: State<'_, BookManagerState>) -> Result<String> {
+    debug!("calling export_cover command with id: {}", id);
+    let dest = FileDialogBuilder::new()
+        .save_file()
+        .ok_or(CommandError::UserAborted)?;
+
+    let m = rec_pois!(manager.0);
+    let written = m.export_cover(id, &dest)?;
+    Ok(written.to_string_lossy().into())
+}
+
+/// Returns the cover files of a whole page of books in one call, books without
+/// a cover are not part of the result.
+#[tauri::command]
+pub async fn prefetch_covers(
+    ids: Vec<i64>,
+    manager: State<'_, BookManagerState>,
+) -> Result<HashMap<i64, String>> {
+    debug!("calling prefetch_covers command with {} ids", ids.len());
+    let m = rec_pois!(manager.0);
+    Ok(m.prefetch_covers(&ids)?
+        .into_iter()
+        .map(|(id, p)| (id, p.to_string_lossy().into()))
+        .collect())
+}
+
+/// Sets the cover of the given book to a copy of the image at `source_path`, an empty or
+/// missing path clears the cover. Returns the updated book.
+#[tauri::command]
+pub async fn set_cover(
+    id: i64,
+    source_path: Option<String>,
+    manager: State<'_, BookManagerState>,
+) -> Result<Book> {
+    debug!(
+        "calling set_cover command with id: {}, source_path: {:?}",
+        id, source_path
+    );
+    let source = source_path
+        .filter(|p| !p.trim().is_empty())
+        .map(PathBuf::from);
+    let m = rec_pois!(manager.0);
+    Ok(m.set_cover(id, source.as_deref())?)
+}
+
+/// Sets the cover of the given book to the given image, e.g. pasted from the clipboard.
+/// Returns the updated book.
+#[tauri::command]
+pub async fn set_book_cover(
+    id: i64,
+    bytes: Vec<u8>,
+    manager: State<'_, BookManagerState>,
+) -> Result<Book> {
+    debug!(
+        "calling set_book_cover command with id: {}, {} bytes",
+        id,
+        bytes.len()
+    );
+    let m = rec_pois!(manager.0);
+    Ok(m.set_cover_data(id, &bytes)?)
+}
+
+#[tauri::command]
+pub async fn list_orphaned_covers(manager: State<'_, BookManagerState>) -> Result<Vec<String>> {
+    debug!("calling list_orphaned_covers command");
+    let m = rec_pois!(manager.0);
+    Ok(m.list_orphaned_covers()?
+        .iter()
+        .map(|p| p.to_string_lossy().into())
+        .collect())
+}
+
+#[tauri::command]
+pub async fn purge_orphaned_covers(manager: State<'_, BookManagerState>) -> Result<u64> {
+    debug!("calling purge_orphaned_covers command");
+    let m = rec_pois!(manager.0);
+    Ok(m.purge_orphaned_covers()?)
+}
+
+#[tauri::command]
+pub async fn close_db(
+    manager: State<'_, BookManagerState>,
+    settings: State<'_, UserSettingsAPI>,
+    app: AppHandle,
+) -> Result {
     debug!("calling close_db command");
     let mut m = rec_pois!(manager.0);
     let current = m.current_pool_name()?;
 
+    if let Some(threshold_mb) = settings.get_auto_vacuum_threshold_mb() {
+        // A failed vacuum must not prevent closing the database
+        match m
+            .get_current_pool()?
+            .vacuum_if_needed(threshold_mb.saturating_mul(1024 * 1024))
+        {
+            Ok(true) => debug!("vacuumed database {} on close", current),
+            Ok(false) => (),
+            Err(e) => warn!("failed to vacuum database {} on close: {:?}", current, e),
+        }
+    }
+
     m.remove_pool(current.clone());
 
     let db = m.get_pools().first().unwrap_or(&"").to_string();
@@ -320,21 +1208,13 @@ pub async fn close_db(manager: State<'_, BookManagerState>, app: AppHandle) -> R
     Ok(())
 }
 
-#[tauri::command]
-pub async fn create_book_db(
-    manager: State<'_, BookManagerState>,
-    settings: State<'_, UserSettingsAPI>,
-    app: AppHandle,
-) -> Result<String> {
-    debug!("calling create_book_db command");
-
+/// Asks the user for a database file to write, the extension `.db` is enforced.
+fn save_db_file_dialog() -> Result<PathBuf> {
     let mut path = FileDialogBuilder::new()
         .add_filter("DB", &[".db"])
         .save_file()
         .ok_or(CommandError::UserAborted)?;
 
-    let pool = BookPool::new_sqlite_pool(&path)?;
-
     if let Some(e) = path.extension() {
         if e.to_ascii_lowercase() != "db" {
             path.set_extension("db");
@@ -343,13 +1223,56 @@ pub async fn create_book_db(
         path.set_extension("db");
     }
 
+    Ok(path)
+}
+
+/// Saves a standalone copy of the current database with its covers to a file chosen by
+/// the user. Returns the path of the copy.
+#[tauri::command]
+pub async fn export_database(manager: State<'_, BookManagerState>) -> Result<String> {
+    debug!("calling export_database command");
+    let path = save_db_file_dialog()?;
+    let m = rec_pois!(manager.0);
+    m.export_database(&path)?;
+    Ok(path.to_string_lossy().into_owned())
+}
+
+#[tauri::command]
+pub async fn create_book_db(
+    manager: State<'_, BookManagerState>,
+    settings: State<'_, UserSettingsAPI>,
+    app: AppHandle,
+) -> Result<String> {
+    debug!("calling create_book_db command");
+
+    let path = save_db_file_dialog()?;
+    let mut mgr = rec_pois!(manager.0);
+
+    // Activate the database if it is already open, maybe through another path
+    if let Some(key) = mgr.pool_key_by_path(&path) {
+        mgr.set_current_pool(&key)?;
+        app.emit_all(
+            BOOK_MANAGER_EVENTS,
+            BookManagerEvent::CurrentDBChanged(key.clone()),
+        )?;
+        return Ok(key);
+    }
+
+    // A new database has no load yet, connections are opened on demand
+    let pool = BookPool::new_sqlite_pool(
+        &path,
+        settings.get_pool_size(),
+        PoolMode::Lazy,
+        settings.get_db_tuning(),
+    )?;
+    pool.set_name_collation(settings.get_name_collation())?;
+
     let key: String = path
         .file_name()
         .expect("Invalid file path, should never happen.")
         .to_string_lossy()
         .into();
 
-    let mut mgr = rec_pois!(manager.0);
     mgr.add_pool(&key, pool)?;
 
     settings.add_history(path.to_str().unwrap_or_default());
@@ -361,3 +1284,179 @@ pub async fn create_book_db(
 
     Ok(key)
 }
+
+/// Opens an existing database, e.g. from the history, and makes it the current one.
+/// If the database is already open, it is only activated.
+#[tauri::command]
+pub async fn open_database(
+    path: String,
+    manager: State<'_, BookManagerState>,
+    settings: State<'_, UserSettingsAPI>,
+    app: AppHandle,
+) -> Result<String> {
+    debug!("calling open_database command with path: {}", path);
+    let path = PathBuf::from(path);
+    let mut mgr = rec_pois!(manager.0);
+
+    let key = match mgr.pool_key_by_path(&path) {
+        Some(key) => key,
+        None => {
+            let pool = BookPool::open_sqlite_pool(
+                &path,
+                settings.get_pool_size(),
+                PoolMode::Lazy,
+                settings.get_db_tuning(),
+            )?;
+            pool.set_name_collation(settings.get_name_collation())?;
+
+            let key: String = path
+                .file_name()
+                .expect("Invalid file path, should never happen.")
+                .to_string_lossy()
+                .into();
+            mgr.add_pool(&key, pool)?;
+            settings.add_history(path.to_str().unwrap_or_default());
+
+            app.emit_all(
+                BOOK_MANAGER_EVENTS,
+                BookManagerEvent::OpenDBChanged(
+                    mgr.get_pools().iter().map(|s| s.to_string()).collect(),
+                ),
+            )?;
+            key
+        }
+    };
+
+    mgr.set_current_pool(&key)?;
+    app.emit_all(
+        BOOK_MANAGER_EVENTS,
+        BookManagerEvent::CurrentDBChanged(key.clone()),
+    )?;
+
+    Ok(key)
+}
+
+/*******************************************************
+ *
+ * Operation API
+ *
+ ******************************************************/
+
+/// Token of the running bulk operation, bulk commands reset it before they start.
+#[derive(Default)]
+pub struct OperationState(pub CancellationToken);
+
+/// Cancels the running bulk operation (e.g. an import) after the current item.
+#[tauri::command]
+pub async fn cancel_operation(operation: State<'_, OperationState>) -> Result {
+    debug!("calling cancel_operation command");
+    operation.0.cancel();
+    Ok(())
+}
+
+/*******************************************************
+ *
+ * Application API
+ *
+ ******************************************************/
+
+/// Application and environment information for the about dialog and bug reports.
+#[derive(Debug, Clone, Serialize)]
+pub struct AboutInfo {
+    pub app_version: String,
+    pub schema_version: u32,
+    pub sqlite_version: String,
+    pub log_level: String,
+}
+
+impl AboutInfo {
+    pub fn collect() -> Self {
+        Self {
+            app_version: env!("CARGO_PKG_VERSION").to_owned(),
+            schema_version: books::schema_version(),
+            sqlite_version: books::sqlite_version().to_owned(),
+            log_level: log::max_level().to_string(),
+        }
+    }
+}
+
+#[tauri::command]
+pub async fn about_info() -> Result<AboutInfo> {
+    debug!("calling about_info command");
+    Ok(AboutInfo::collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::{AboutInfo, ApiError, MetadataProviderState, UserSettingsAPI};
+    use crate::books::metadata::{BookMetadata, StaticProvider};
+    use crate::books::{self, models::BookError};
+    use crate::settings::UserSettings;
+
+    #[test]
+    fn about_info() {
+        let info = AboutInfo::collect();
+        assert!(!info.app_version.is_empty());
+        assert!(info.schema_version > 0);
+        assert!(!info.sqlite_version.is_empty());
+    }
+
+    #[test]
+    fn prune_missing_history_entries() {
+        let dir = std::env::temp_dir().join(format!("bookshelf-history-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let db = dir.join("books.db");
+        std::fs::write(&db, b"").unwrap();
+        let path = |p: &std::path::Path| p.to_string_lossy().into_owned();
+
+        let settings = UserSettingsAPI(Arc::new(Mutex::new(UserSettings {
+            book_history: vec![
+                path(&dir.join("missing.db")),
+                path(&db),
+                path(&dir.join("sub/../books.db")),
+                path(&dir.join("sub")),
+            ],
+            ..Default::default()
+        })));
+        assert_eq!(settings.prune_history(), vec![path(&db)]);
+        assert_eq!(settings.get_history(), vec![path(&db)]);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn invalid_book_api_error() {
+        let invalid = || BookError::InvalidBook {
+            field: "isbn".to_owned(),
+            reason: "wrong check digit".to_owned(),
+        };
+
+        let err: ApiError = invalid().into();
+        assert_eq!(err.code, 44);
+        assert_eq!(err.error, "invalid field: isbn, reason: wrong check digit");
+
+        let err: ApiError = books::Error::from(invalid()).into();
+        assert_eq!(err.code, 44);
+    }
+
+    #[test]
+    fn lookup_isbn_with_static_provider() {
+        let state = MetadataProviderState(Box::new(StaticProvider::new([BookMetadata {
+            isbn: "9783453270855".to_owned(),
+            title: "Millennium".to_owned(),
+            authors: vec!["David Lagercrantz".to_owned()],
+            publisher: Some("Heyne Verlag".to_owned()),
+            ..Default::default()
+        }])));
+
+        let book = state.lookup_isbn("978-3-453-27085-5").unwrap();
+        assert_eq!(book.title, "Millennium");
+        assert_eq!(book.authors, vec!["David Lagercrantz"]);
+        assert_eq!(book.publisher.as_deref(), Some("Heyne Verlag"));
+
+        let err = state.lookup_isbn("9783550087653").unwrap_err();
+        assert_eq!(err.code, 41);
+    }
+}
@@ -8,7 +8,7 @@
 
 use std::{ffi::OsString, fs::File, path::PathBuf};
 
-use commands::{BookManagerState, UserSettingsAPI};
+use commands::{BookManagerState, MetadataProviderState, OperationState, UserSettingsAPI};
 use log::{info, LevelFilter};
 use simplelog::{
     ColorChoice, CombinedLogger, Config, ConfigBuilder, SharedLogger, TermLogger, TerminalMode,
@@ -44,25 +44,93 @@ fn main() {
     tauri::Builder::default()
         .manage(BookManagerState::default())
         .manage(UserSettingsAPI::default())
+        .manage(OperationState::default())
+        .manage(MetadataProviderState::default())
         .invoke_handler(tauri::generate_handler![
             greet,
             shutdown,
             commands::create_book_db,
+            commands::open_database,
             commands::current_lang,
             commands::set_lang,
             commands::remove_history,
+            commands::prune_history,
             commands::get_history,
             commands::set_current_db,
+            commands::try_set_current_db,
+            commands::list_open_databases,
+            commands::current_database,
             commands::close_db,
+            commands::export_json,
+            commands::export_csv,
+            commands::export_book_json,
+            commands::lookup_isbn,
+            commands::backup_database,
+            commands::export_database,
+            commands::compact_database,
+            commands::clear_library,
+            commands::inspect_import,
+            commands::import_books,
+            commands::merge_db,
+            commands::repair_timestamps,
+            commands::export_cover,
+            commands::prefetch_covers,
+            commands::set_cover,
+            commands::set_book_cover,
+            commands::list_orphaned_covers,
+            commands::purge_orphaned_covers,
             commands::get_book,
+            commands::get_book_by_isbn,
+            commands::get_book_hash,
             commands::add_book,
+            commands::add_books,
+            commands::load_sample_data,
             commands::delete_book,
+            commands::delete_books,
+            commands::restore_book,
+            commands::list_trash,
+            commands::purge_trash,
             commands::update_book,
             commands::fetch_book,
+            commands::get_tags,
+            commands::get_authors,
+            commands::search_preview,
+            commands::count_books,
+            commands::status_counts,
+            commands::library_statistics,
+            commands::log_reading,
+            commands::finished_books,
+            commands::tags_for_books,
+            commands::rename_tag,
+            commands::delete_tag,
+            commands::rename_author,
+            commands::publish_year_range,
+            commands::list_title_duplicates,
+            commands::find_duplicates,
+            commands::merge_books,
+            commands::get_neighbors,
+            commands::get_wal_autocheckpoint,
+            commands::set_wal_autocheckpoint,
+            commands::get_auto_vacuum_threshold,
+            commands::set_auto_vacuum_threshold,
+            commands::set_name_collation,
+            commands::get_name_collation,
+            commands::set_use_trash,
+            commands::get_use_trash,
+            commands::set_pool_size,
+            commands::get_pool_size,
+            commands::set_db_tuning,
+            commands::get_db_tuning,
+            commands::record_search,
+            commands::get_recent_searches,
+            commands::clear_recent_searches,
+            commands::check_name_order,
             commands::set_theme,
             commands::current_theme,
             commands::get_menu_expanded,
-            commands::set_menu_expanded
+            commands::set_menu_expanded,
+            commands::cancel_operation,
+            commands::about_info
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -5,65 +5,291 @@
 
 use std::{
     ops::{Deref, DerefMut},
-    sync::{Arc, Mutex},
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
 };
 
-struct InnerPool<T: Send + ?Sized>(Arc<Mutex<Vec<Box<T>>>>, usize);
+struct PoolState<T: Send + ?Sized> {
+    /// Idle items with the settings generation of the creator they were last updated to.
+    idle: Vec<(Box<T>, u64)>,
+    /// Number of items created by the pool and not dropped yet, idle or in use.
+    live: usize,
+}
+
+struct InnerPool<T: Send + ?Sized> {
+    state: Arc<(Mutex<PoolState<T>>, Condvar)>,
+    max_idle: usize,
+    /// If set, no more than this number of items exist at once.
+    max_items: Option<usize>,
+    /// Checks released items and updates items of an older settings generation.
+    creator: Arc<dyn Creator<T> + Send + Sync>,
+}
+
+enum Acquired<T: Send + ?Sized> {
+    Idle(Box<T>, u64),
+    /// The caller may create a new item, it is already counted as live.
+    Create,
+    TimedOut,
+}
 
 impl<T: Send + ?Sized> InnerPool<T> {
-    fn acquire(&self) -> Result<Box<T>, bool> {
-        let mut v = self.0.lock().unwrap();
-        v.pop().ok_or(false)
+    /// Returns an idle item or allows the caller to create a new one. A bounded pool
+    /// waits until an item is released if the maximum is reached, but no longer than
+    /// the given timeout.
+    fn acquire(&self, timeout: Option<Duration>) -> Acquired<T> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let (lock, released) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        loop {
+            if let Some((item, generation)) = state.idle.pop() {
+                return Acquired::Idle(item, generation);
+            }
+            match self.max_items {
+                Some(max) if state.live >= max => match deadline {
+                    Some(deadline) => {
+                        let remaining = deadline.saturating_duration_since(Instant::now());
+                        if remaining.is_zero() {
+                            return Acquired::TimedOut;
+                        }
+                        state = released.wait_timeout(state, remaining).unwrap().0;
+                    }
+                    None => state = released.wait(state).unwrap(),
+                },
+                _ => {
+                    state.live += 1;
+                    return Acquired::Create;
+                }
+            }
+        }
+    }
+
+    /// Gives up a slot counted as live, which didn't end up with an item.
+    fn forfeit(&self) {
+        let (lock, released) = &*self.state;
+        lock.lock().unwrap().live -= 1;
+        released.notify_one();
+    }
+
+    /// Brings an item of an older settings generation up to date. Returns the item with
+    /// its new generation, or `None` if it couldn't be updated and was dropped.
+    fn refresh(&self, mut item: Box<T>, generation: u64) -> Option<(Box<T>, u64)> {
+        // Read before updating, so a change during the update is applied next time
+        let current = self.creator.generation();
+        if generation == current || self.creator.update_item(&mut item) {
+            Some((item, current))
+        } else {
+            None
+        }
+    }
+
+    /// Returns an item to the pool. The item is kept only if it is healthy, could be
+    /// updated to the current settings and fewer than `max_idle` items are idle,
+    /// otherwise it is dropped silently. Waiting callers are woken up in all cases.
+    fn release(&self, item: Box<T>, generation: u64) {
+        // The check and the update may query the item, so run them before taking the lock
+        let item = if self.creator.is_healthy(&item) {
+            self.refresh(item, generation)
+        } else {
+            None
+        };
+        let (lock, released) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        match item {
+            Some(item) if state.idle.len() < self.max_idle => {
+                state.idle.push(item);
+                drop(state);
+            }
+            item => {
+                // Surplus items are dropped outside of the lock, as dropping may take
+                // a while (e.g. a database connection checkpoints its WAL).
+                state.live -= 1;
+                drop(state);
+                drop(item);
+            }
+        }
+        released.notify_one();
+    }
+}
+
+/// Forfeits the slot of an item which is being created, unless it is defused after the
+/// creation succeeded. Otherwise a panicking creator would permanently reduce the capacity
+/// of a bounded pool.
+struct CreateGuard<'a, T: Send + ?Sized>(&'a InnerPool<T>);
+
+impl<T: Send + ?Sized> CreateGuard<'_, T> {
+    fn defuse(self) {
+        std::mem::forget(self);
     }
+}
 
-    fn relase(&self, item: Box<T>) {
-        let mut v = self.0.lock().unwrap();
-        if v.len() < self.1 {
-            v.push(item)
+impl<T: Send + ?Sized> Drop for CreateGuard<'_, T> {
+    fn drop(&mut self) {
+        self.0.forfeit();
+    }
+}
+
+impl<T: Send + ?Sized> Clone for InnerPool<T> {
+    fn clone(&self) -> Self {
+        Self {
+            state: Arc::clone(&self.state),
+            max_idle: self.max_idle,
+            max_items: self.max_items,
+            creator: Arc::clone(&self.creator),
         }
     }
 }
 
 pub trait Creator<T: Send + ?Sized> {        
     fn create_item(&self) -> Box<T>;
+
+    /// Checks a released item before it is returned to the pool, unhealthy items
+    /// are dropped and recreated on demand.
+    fn is_healthy(&self, _item: &T) -> bool {
+        true
+    }
+
+    /// Version of the settings new items are created with, changes whenever they
+    /// are updated. Pooled items of an older generation are passed to `update_item`
+    /// before they are used again.
+    fn generation(&self) -> u64 {
+        0
+    }
+
+    /// Applies the current settings to an item of an older generation. Items which
+    /// can't be updated are dropped and recreated on demand.
+    fn update_item(&self, _item: &mut T) -> bool {
+        true
+    }
+}
+
+/// Defines when the items of a pool are created.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PoolMode {
+    /// All items are created up front, the first requests don't wait for new items.
+    Eager,
+    /// Items are created on demand, the pool only keeps released items.
+    Lazy,
 }
 
 pub struct PoolManager<T: Send + ?Sized, F: Creator<T>> {
-    creator: F,
+    creator: Arc<F>,
     pool: InnerPool<T>,
 }
 
 
-impl<T: Send + ?Sized, F: Creator<T>> PoolManager<T, F> {
+impl<T, F> PoolManager<T, F>
+where
+    T: Send + ?Sized + 'static,
+    F: Creator<T> + Send + Sync + 'static,
+{
+    #[allow(unused)]
     pub fn new(min_pool: usize, creator: F) -> PoolManager<T,F> {
-        let mut conns: Vec<Box<T>> = Vec::new();
-        for _ in 0..min_pool {
-            conns.push(creator.create_item());
+        Self::with_mode(min_pool, PoolMode::Eager, creator)
+    }
+
+    /// Creates a pool which keeps up to `max_pool` idle items, in eager mode
+    /// all of them are created immediately. If the pool is exhausted, new items
+    /// are created.
+    pub fn with_mode(max_pool: usize, mode: PoolMode, creator: F) -> PoolManager<T,F> {
+        Self::create(max_pool, None, mode, creator)
+    }
+
+    /// Creates a pool like [`PoolManager::with_mode`], but never more than `max_items`
+    /// items exist at once. If all of them are in use, `get_pool_item` waits until
+    /// one is released.
+    pub fn bounded(
+        max_pool: usize,
+        max_items: usize,
+        mode: PoolMode,
+        creator: F,
+    ) -> PoolManager<T, F> {
+        Self::create(
+            max_pool.min(max_items),
+            Some(max_items.max(1)),
+            mode,
+            creator,
+        )
+    }
+
+    fn create(
+        max_pool: usize,
+        max_items: Option<usize>,
+        mode: PoolMode,
+        creator: F,
+    ) -> PoolManager<T, F> {
+        let mut conns: Vec<(Box<T>, u64)> = Vec::new();
+        if mode == PoolMode::Eager {
+            for _ in 0..max_pool {
+                let generation = creator.generation();
+                conns.push((creator.create_item(), generation));
+            }
         }
 
+        let state = PoolState {
+            live: conns.len(),
+            idle: conns,
+        };
+
+        let creator = Arc::new(creator);
+
         Self {
             creator,
-            pool: InnerPool(Arc::new(Mutex::new(conns)), min_pool),
+            pool: InnerPool {
+                state: Arc::new((Mutex::new(state), Condvar::new())),
+                max_idle: max_pool,
+                max_items,
+                creator: Arc::clone(&creator) as Arc<dyn Creator<T> + Send + Sync>,
+            },
         }
     }
 
     pub fn get_pool_item(&self) -> PoolItem<T> {
-        match self.pool.acquire() {
-            Ok(p) => PoolItem(Some(p), InnerPool(Arc::clone(&self.pool.0), self.pool.1)),
-            Err(_) => PoolItem(
-                Some(self.creator.create_item()),
-                InnerPool(Arc::clone(&self.pool.0), self.pool.1),
-            ),
-        }
+        self.acquire_item(None)
+            .expect("Acquiring an item without timeout never fails")
+    }
+
+    /// Like `get_pool_item`, but returns `None` if a bounded pool has no item
+    /// available within the given duration.
+    pub fn get_pool_item_timeout(&self, dur: Duration) -> Option<PoolItem<T>> {
+        self.acquire_item(Some(dur))
+    }
+
+    fn acquire_item(&self, timeout: Option<Duration>) -> Option<PoolItem<T>> {
+        let (item, generation) = match self.pool.acquire(timeout) {
+            Acquired::Idle(item, generation) => match self.pool.refresh(item, generation) {
+                Some(refreshed) => refreshed,
+                // The item which couldn't be updated is replaced in its slot
+                None => self.create_item(),
+            },
+            Acquired::Create => self.create_item(),
+            Acquired::TimedOut => return None,
+        };
+        Some(PoolItem(Some(item), self.pool.clone(), generation))
+    }
+
+    /// Creates an item for a slot already counted as live.
+    fn create_item(&self) -> (Box<T>, u64) {
+        let guard = CreateGuard(&self.pool);
+        // Items are created with the settings of this generation or a newer one
+        let generation = self.creator.generation();
+        let item = self.creator.create_item();
+        guard.defuse();
+        (item, generation)
+    }
+
+    pub fn creator(&self) -> &F {
+        &self.creator
     }
 
     #[allow(unused)]
     pub fn available_items(&self) -> usize {
-        self.pool.0.lock().unwrap().len()
+        self.pool.state.0.lock().unwrap().idle.len()
     }
 }
 
-pub struct PoolItem<T: Send + ?Sized>(Option<Box<T>>, InnerPool<T>);
+/// An item checked out of the pool, it is released when dropped. The last field is the
+/// settings generation of the item.
+pub struct PoolItem<T: Send + ?Sized>(Option<Box<T>>, InnerPool<T>, u64);
 
 impl<T: Send + ?Sized> Deref for PoolItem<T> {
     type Target = T;
@@ -81,14 +307,15 @@ impl<T: Send + ?Sized> DerefMut for PoolItem<T> {
 
 impl<T: Send + ?Sized> Drop for PoolItem<T> {
     fn drop(&mut self) {
-        self.1.relase(self.0.take().unwrap())
+        self.1.release(self.0.take().unwrap(), self.2)
     }
 }
 
 #[cfg(test)] 
 mod tests {
-    use std::{thread, time::Duration, sync::Arc};
-    use super::{PoolManager, Creator};
+    use std::{thread, time::{Duration, Instant}, sync::Arc};
+    use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+    use super::{PoolManager, PoolMode, Creator};
    
 
     #[derive(Default)]
@@ -127,5 +354,226 @@ mod tests {
         });
         assert!(pool.available_items() == 5);
     }
-    
+
+    #[test]
+    fn lazy_pool_test() {
+        let pool = PoolManager::with_mode(2, PoolMode::Lazy, TestCreator::default());
+        assert_eq!(pool.available_items(), 0);
+
+        {
+            let first = pool.get_pool_item();
+            let second = pool.get_pool_item();
+            let third = pool.get_pool_item();
+            assert_eq!(*first, "Just a test");
+            assert_eq!(*second, *third);
+            assert_eq!(pool.available_items(), 0);
+        }
+
+        // Released items are kept up to the max pool size
+        assert_eq!(pool.available_items(), 2);
+    }
+
+    #[test]
+    fn single_item_pool_test() {
+        let pool = PoolManager::new(1, TestCreator::default());
+        assert_eq!(pool.available_items(), 1);
+
+        {
+            let first = pool.get_pool_item();
+            assert_eq!(pool.available_items(), 0);
+            // An exhausted pool creates a new item instead of blocking
+            let second = pool.get_pool_item();
+            assert_eq!(*first, *second);
+            assert_eq!(pool.available_items(), 0);
+        }
+
+        // Only one of the released items is kept
+        assert_eq!(pool.available_items(), 1);
+    }
+
+    struct Counted(Arc<AtomicUsize>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    struct CountingCreator(Arc<AtomicUsize>);
+
+    impl Creator<Counted> for CountingCreator {
+        fn create_item(&self) -> Box<Counted> {
+            Box::new(Counted(self.0.clone()))
+        }
+    }
+
+    #[test]
+    fn drop_surplus_items() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let pool = PoolManager::with_mode(1, PoolMode::Lazy, CountingCreator(dropped.clone()));
+
+        {
+            let _first = pool.get_pool_item();
+            let _second = pool.get_pool_item();
+        }
+
+        // Only the surplus item is closed, the other one is kept for reuse
+        assert_eq!(dropped.load(Ordering::SeqCst), 1);
+        assert_eq!(pool.available_items(), 1);
+    }
+
+    #[test]
+    fn release_beyond_capacity() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let pool = PoolManager::new(2, CountingCreator(dropped.clone()));
+
+        let items: Vec<_> = (0..4).map(|_| pool.get_pool_item()).collect();
+        assert_eq!(pool.available_items(), 0);
+        drop(items);
+
+        // The pool is filled up to its capacity, the remaining items are dropped
+        assert_eq!(pool.available_items(), 2);
+        assert_eq!(dropped.load(Ordering::SeqCst), 2);
+    }
+
+    struct FlakyCreator;
+
+    impl Creator<String> for FlakyCreator {
+        fn create_item(&self) -> Box<String> {
+            Box::new("healthy".to_owned())
+        }
+
+        fn is_healthy(&self, item: &String) -> bool {
+            item == "healthy"
+        }
+    }
+
+    #[test]
+    fn drop_unhealthy_items() {
+        let pool = PoolManager::with_mode(2, PoolMode::Lazy, FlakyCreator);
+
+        {
+            let _first = pool.get_pool_item();
+            let mut second = pool.get_pool_item();
+            second.push_str(" but broken");
+        }
+
+        // The broken item was not returned to the pool
+        assert_eq!(pool.available_items(), 1);
+        assert_eq!(*pool.get_pool_item(), "healthy");
+        assert_eq!(*pool.get_pool_item(), "healthy");
+    }
+
+    #[test]
+    fn pool_item_timeout() {
+        let pool = PoolManager::bounded(1, 1, PoolMode::Eager, TestCreator::default());
+
+        let held = pool.get_pool_item_timeout(Duration::from_millis(10));
+        assert!(held.is_some());
+
+        let started = Instant::now();
+        assert!(pool.get_pool_item_timeout(Duration::from_millis(50)).is_none());
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        drop(held);
+        assert!(pool.get_pool_item_timeout(Duration::from_millis(10)).is_some());
+    }
+
+    struct PanickingCreator(AtomicUsize);
+
+    impl Creator<String> for PanickingCreator {
+        fn create_item(&self) -> Box<String> {
+            if self.0.fetch_add(1, Ordering::SeqCst) == 0 {
+                panic!("first item can't be created");
+            }
+            Box::new("Just a test".to_owned())
+        }
+    }
+
+    #[test]
+    fn failed_creation_frees_slot() {
+        let creator = PanickingCreator(AtomicUsize::new(0));
+        let pool = PoolManager::bounded(1, 1, PoolMode::Lazy, creator);
+
+        let created = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            pool.get_pool_item();
+        }));
+        assert!(created.is_err());
+
+        // The slot of the failed item is available again
+        let item = pool.get_pool_item_timeout(Duration::from_millis(10));
+        assert_eq!(item.as_deref().map(|s| s.as_str()), Some("Just a test"));
+    }
+
+    struct VersionedCreator(AtomicU64);
+
+    impl Creator<String> for VersionedCreator {
+        fn create_item(&self) -> Box<String> {
+            Box::new(format!("version {}", self.generation()))
+        }
+
+        fn generation(&self) -> u64 {
+            self.0.load(Ordering::SeqCst)
+        }
+
+        fn update_item(&self, item: &mut String) -> bool {
+            *item = format!("version {}", self.generation());
+            true
+        }
+    }
+
+    #[test]
+    fn update_items_of_older_generations() {
+        let pool = PoolManager::with_mode(2, PoolMode::Eager, VersionedCreator(AtomicU64::new(0)));
+
+        let held = pool.get_pool_item();
+        pool.creator().0.store(1, Ordering::SeqCst);
+        assert_eq!(*held, "version 0");
+
+        // The idle item is updated when it is acquired
+        let idle = pool.get_pool_item();
+        assert_eq!(*idle, "version 1");
+        drop(idle);
+
+        // The held item is updated when it is released
+        drop(held);
+        assert_eq!(*pool.get_pool_item(), "version 1");
+        assert_eq!(*pool.get_pool_item(), "version 1");
+    }
+
+    #[test]
+    fn bounded_pool_test() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let in_use = Arc::new(AtomicUsize::new(0));
+        let max_in_use = Arc::new(AtomicUsize::new(0));
+        let pool = Arc::new(PoolManager::bounded(
+            1,
+            1,
+            PoolMode::Lazy,
+            CountingCreator(dropped.clone()),
+        ));
+
+        let handles: Vec<_> = (0..3)
+            .map(|_| {
+                let (pool, in_use, max_in_use) =
+                    (pool.clone(), in_use.clone(), max_in_use.clone());
+                thread::spawn(move || {
+                    for _ in 0..5 {
+                        let _item = pool.get_pool_item();
+                        let current = in_use.fetch_add(1, Ordering::SeqCst) + 1;
+                        max_in_use.fetch_max(current, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(10));
+                        in_use.fetch_sub(1, Ordering::SeqCst);
+                    }
+                })
+            })
+            .collect();
+
+        handles.into_iter().for_each(|h| h.join().unwrap());
+
+        // The threads waited for the single item, a surplus item would have been dropped
+        assert_eq!(max_in_use.load(Ordering::SeqCst), 1);
+        assert_eq!(dropped.load(Ordering::SeqCst), 0);
+        assert_eq!(pool.available_items(), 1);
+    }
 }
\ No newline at end of file
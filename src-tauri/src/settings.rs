@@ -13,6 +13,7 @@ use std::{
     result,
 };
 
+use crate::books::models::{DbTuning, NameCollation};
 use crate::from_err;
 
 #[cfg(not(windows))]
@@ -20,6 +21,27 @@ const SETTINGS_FILE: &str = ".config/bookshelf/bookshelf-settings.json";
 #[cfg(windows)]
 const SETTINGS_FILE: &str = r"bookshelf\bookshelf-settings.json";
 
+/// Version of the settings file format, older files are migrated when loaded.
+const SETTINGS_VERSION: u32 = 1;
+
+/// Default maximum number of databases kept in the history.
+const DEFAULT_MAX_HISTORY: usize = 20;
+
+/// Maximum number of recent searches kept in the settings.
+const MAX_RECENT_SEARCHES: usize = 10;
+
+/// Name of the event emitted to all windows when a setting was changed.
+pub const SETTINGS_EVENTS: &str = "settings-event";
+
+/// A changed setting with its new value.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "content")]
+pub enum SettingsChanged {
+    Lang(String),
+    Theme(String),
+    MenuExpanded(bool),
+}
+
 #[inline]
 fn get_user_settings_path() -> Result<PathBuf> {
     Ok(UserDirs::new()
@@ -42,35 +64,90 @@ from_err!(SettingsError, io::Error, IoError);
 
 pub type Result<T = (), E = SettingsError> = result::Result<T, E>;
 
+/// Missing fields are filled with the values of [UserSettings::default].
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
 pub struct UserSettings {
+    /// Version of the file format, files without a version are 0.
     #[serde(default)]
+    pub version: u32,
     pub lang: String,
-    #[serde(default)]
+    /// Paths of opened databases, most recent first.
     pub book_history: Vec<String>,
-    #[serde(default)]
+    /// Maximum number of databases kept in the history.
+    pub max_history: usize,
+    /// Theme of the user interface, `dark` or `light`.
     pub theme: String,
-    #[serde(default)]
+    /// Whether the side menu is expanded.
     pub menu_expanded: bool,
+    /// Databases larger than this are vacuumed on close, disabled if not set.
+    pub auto_vacuum_on_close_threshold_mb: Option<u64>,
+    /// Ordering of authors and tags within a book.
+    pub name_collation: NameCollation,
+    /// Deleted books are moved to the trash instead of being removed permanently.
+    pub use_trash: bool,
+    /// Distinct search texts, most recent first.
+    pub recent_searches: Vec<String>,
+    /// Maximum number of idle connections kept per database.
+    pub pool_size: usize,
+    /// Journal mode, synchronous level and busy timeout of database connections.
+    pub db_tuning: DbTuning,
 }
 
 impl Default for UserSettings {
     fn default() -> Self {
         Self {
+            version: SETTINGS_VERSION,
             lang: "en".to_owned(),
             book_history: Default::default(),
+            max_history: DEFAULT_MAX_HISTORY,
             theme: "dark".to_owned(),
             menu_expanded: true,
+            auto_vacuum_on_close_threshold_mb: None,
+            name_collation: NameCollation::default(),
+            use_trash: true,
+            recent_searches: Default::default(),
+            pool_size: 5,
+            db_tuning: DbTuning::default(),
         }
     }
 }
 
 impl UserSettings {
+    /// Loads the settings, files of an older version are migrated and rewritten.
     pub fn from_file<T: AsRef<Path>>(path: T) -> Result<UserSettings> {
         debug!("loading user settings from {:?}", path.as_ref().as_os_str());
-        let f = File::open(path)?;
+        let f = File::open(&path)?;
         let buf = BufReader::new(f);
-        Ok(serde_json::from_reader(buf)?)
+        let mut settings: UserSettings = serde_json::from_reader(buf)?;
+
+        if settings.version < SETTINGS_VERSION {
+            debug!(
+                "migrating user settings from version {} to {}",
+                settings.version, SETTINGS_VERSION
+            );
+            settings.migrate();
+            // The migrated settings are usable, even if they can't be written
+            if let Err(e) = settings.save_to_file(&path) {
+                warn!("failed to save migrated user settings {:?}", e);
+            }
+        }
+
+        Ok(settings)
+    }
+
+    /// Migrates the settings to [SETTINGS_VERSION], missing fields were already filled
+    /// with their defaults while loading.
+    fn migrate(&mut self) {
+        let defaults = UserSettings::default();
+        // Version 0 stored missing texts as empty strings
+        if self.lang.is_empty() {
+            self.lang = defaults.lang;
+        }
+        if self.theme.is_empty() {
+            self.theme = defaults.theme;
+        }
+        self.version = SETTINGS_VERSION;
     }
 
     pub fn from_file_or_default<T: AsRef<Path>>(path: T) -> UserSettings {
@@ -93,6 +170,31 @@ impl UserSettings {
         }
     }
 
+    /// Moves the given database path to the front of the history, the oldest entries
+    /// beyond [UserSettings::max_history] are dropped. Empty paths are ignored.
+    pub fn add_history(&mut self, path: &str) {
+        if path.is_empty() {
+            return;
+        }
+
+        self.book_history.retain(|p| p != path);
+        self.book_history.insert(0, path.to_owned());
+        self.book_history.truncate(self.max_history.max(1));
+    }
+
+    /// Moves the given search text to the front of the recent searches, the oldest
+    /// searches are dropped. Empty texts are ignored.
+    pub fn add_recent_search(&mut self, text: &str) {
+        let text = text.trim();
+        if text.is_empty() {
+            return;
+        }
+
+        self.recent_searches.retain(|s| s != text);
+        self.recent_searches.insert(0, text.to_owned());
+        self.recent_searches.truncate(MAX_RECENT_SEARCHES);
+    }
+
     pub fn save_to_file<T: AsRef<Path>>(&self, path: T) -> Result {
         let dir = path.as_ref().parent().ok_or(SettingsError::InvalidPath)?;
 
@@ -120,7 +222,10 @@ impl UserSettings {
 mod tests {
     use std::fs;
 
-    use super::{get_user_settings_path, Result, UserSettings};
+    use super::{
+        get_user_settings_path, Result, UserSettings, MAX_RECENT_SEARCHES, SETTINGS_VERSION,
+    };
+    use crate::books::models::{DbTuning, JournalMode, NameCollation, Synchronous};
 
     #[test]
     fn write_read_settings_file() -> Result {
@@ -133,13 +238,25 @@ mod tests {
         let _ = fs::remove_file(&dest);
 
         let testee = UserSettings {
+            version: SETTINGS_VERSION,
             lang: "Sindarin".to_string(),
             book_history: vec![
                 "/abc/xyz/mybooks.db".to_string(),
                 "/home/elrond/books/magic.db".to_string(),
             ],
-            theme: "dark".to_owned(),
-            menu_expanded: true,
+            max_history: 3,
+            theme: "light".to_owned(),
+            menu_expanded: false,
+            auto_vacuum_on_close_threshold_mb: Some(100),
+            name_collation: NameCollation::Unicode,
+            use_trash: false,
+            recent_searches: vec!["Tolkien".to_owned()],
+            pool_size: 2,
+            db_tuning: DbTuning {
+                journal_mode: JournalMode::Delete,
+                synchronous: Synchronous::Full,
+                busy_timeout: Some(3000),
+            },
         };
 
         testee.save_to_file(&dest)?;
@@ -158,6 +275,78 @@ mod tests {
 
         assert_ne!(modified, testee);
 
+        // Files written before the theme and menu settings existed still load
+        fs::write(&dest, r#"{"version":1,"lang":"Quenya"}"#)?;
+        let loaded = UserSettings::from_file(&dest)?;
+        assert_eq!(loaded.theme, UserSettings::default().theme);
+        assert!(loaded.menu_expanded);
+
         Ok(())
     }
+
+    #[test]
+    fn migrate_old_settings_file() -> Result {
+        let dest =
+            std::env::temp_dir().join(format!("bookshelf-settings-{}.json", std::process::id()));
+        fs::write(
+            &dest,
+            r#"{"lang":"de","book_history":["/home/bilbo/journey.db"],"theme":""}"#,
+        )?;
+
+        let loaded = UserSettings::from_file(&dest)?;
+        assert_eq!(
+            loaded,
+            UserSettings {
+                lang: "de".to_owned(),
+                book_history: vec!["/home/bilbo/journey.db".to_owned()],
+                ..Default::default()
+            }
+        );
+        // The migrated settings were written back
+        assert!(fs::read_to_string(&dest)?.contains(&format!("\"version\":{}", SETTINGS_VERSION)));
+        assert_eq!(UserSettings::from_file(&dest)?, loaded);
+
+        let _ = fs::remove_file(&dest);
+        Ok(())
+    }
+
+    #[test]
+    fn add_history_most_recent_first() {
+        let mut settings = UserSettings {
+            max_history: 3,
+            ..Default::default()
+        };
+        settings.add_history("/books/a.db");
+        settings.add_history("");
+        settings.add_history("/books/b.db");
+        settings.add_history("/books/a.db");
+        assert_eq!(settings.book_history, vec!["/books/a.db", "/books/b.db"]);
+
+        settings.add_history("/books/c.db");
+        settings.add_history("/books/d.db");
+        assert_eq!(
+            settings.book_history,
+            vec!["/books/d.db", "/books/c.db", "/books/a.db"]
+        );
+    }
+
+    #[test]
+    fn add_recent_searches() {
+        let mut settings = UserSettings::default();
+        settings.add_recent_search("Dawkins");
+        settings.add_recent_search("  ");
+        settings.add_recent_search("Thriller ");
+        settings.add_recent_search("Dawkins");
+        assert_eq!(settings.recent_searches, vec!["Dawkins", "Thriller"]);
+
+        for i in 0..MAX_RECENT_SEARCHES {
+            settings.add_recent_search(&format!("search {}", i));
+        }
+        assert_eq!(settings.recent_searches.len(), MAX_RECENT_SEARCHES);
+        assert_eq!(
+            settings.recent_searches[0],
+            format!("search {}", MAX_RECENT_SEARCHES - 1)
+        );
+        assert!(!settings.recent_searches.contains(&"Thriller".to_owned()));
+    }
 }